@@ -41,7 +41,7 @@ fn main() {
             let title = child.get_child("title", ARTICLE_NS).unwrap().text();
             let body = child.get_child("body", ARTICLE_NS).unwrap().text();
             articles.push(Article {
-                title: title,
+                title,
                 body: body.trim().to_owned(),
             });
         }
@@ -0,0 +1,176 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Storage of the attributes of an element, identified by their namespace and local name.
+
+use std::slice;
+use std::sync::Arc;
+
+/// The namespace the `xml` prefix is always bound to.
+pub(crate) const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// An attribute of an element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Attribute {
+    /// The namespace of the attribute, empty if it has none
+    pub(crate) namespace: Arc<str>,
+    /// The local name of the attribute, without any prefix
+    pub(crate) name: String,
+    pub(crate) value: String,
+    /// The prefix the attribute has been parsed with, if any
+    pub(crate) prefix: Option<Arc<str>>,
+}
+
+impl Attribute {
+    /// The namespace and local name identifying this attribute.
+    fn key(&self) -> (&str, &str) {
+        (&self.namespace, &self.name)
+    }
+}
+
+/// The attributes of an element, sorted by namespace and then by local name, or kept in the
+/// order they have been inserted in.
+///
+/// The order doesn't matter for equality.
+#[derive(Clone, Debug, Default, Eq)]
+pub(crate) struct Attributes {
+    list: Vec<Attribute>,
+    /// If the attributes are kept in insertion order, the indices of `list` sorted by namespace
+    /// and local name, to look them up without going through all of them
+    sorted: Option<Vec<usize>>,
+}
+
+impl PartialEq for Attributes {
+    fn eq(&self, other: &Self) -> bool {
+        self.list.len() == other.list.len()
+            && self.list.iter().all(|attribute| {
+                other.get(&attribute.namespace, &attribute.name) == Some(&attribute.value)
+            })
+    }
+}
+
+impl Attributes {
+    /// Build the attributes of an element all at once, returning `None` if two of them have the
+    /// same namespace and local name.
+    pub(crate) fn from_list(mut list: Vec<Attribute>, preserve_order: bool) -> Option<Self> {
+        let (duplicate, sorted) = if preserve_order {
+            let mut sorted: Vec<_> = (0..list.len()).collect();
+            sorted.sort_unstable_by(|&a, &b| list[a].key().cmp(&list[b].key()));
+            let duplicate = sorted
+                .windows(2)
+                .any(|pair| list[pair[0]].key() == list[pair[1]].key());
+            (duplicate, Some(sorted))
+        } else {
+            list.sort_unstable_by(|a, b| a.key().cmp(&b.key()));
+            let duplicate = list.windows(2).any(|pair| pair[0].key() == pair[1].key());
+            (duplicate, None)
+        };
+        if duplicate {
+            return None;
+        }
+        Some(Attributes { list, sorted })
+    }
+
+    pub(crate) fn preserves_order(&self) -> bool {
+        self.sorted.is_some()
+    }
+
+    /// Keep the attributes in insertion order from now on, or sort them.
+    pub(crate) fn set_preserve_order(&mut self, preserve: bool) {
+        match (preserve, self.sorted.is_some()) {
+            (true, false) => self.sorted = Some((0..self.list.len()).collect()),
+            (false, true) => {
+                self.list.sort_by(|a, b| a.key().cmp(&b.key()));
+                self.sorted = None;
+            }
+            _ => (),
+        }
+    }
+
+    pub(crate) fn get(&self, namespace: &str, name: &str) -> Option<&str> {
+        let index = self.find(namespace, name).ok()?;
+        Some(&self.list[index].value)
+    }
+
+    /// Set the value of an attribute, returning its previous value if it was already there. The
+    /// prefix is only recorded for new attributes.
+    pub(crate) fn insert(
+        &mut self,
+        namespace: Arc<str>,
+        name: String,
+        value: String,
+        prefix: Option<Arc<str>>,
+    ) -> Option<String> {
+        let position = match self.find(&namespace, &name) {
+            Ok(index) => return Some(std::mem::replace(&mut self.list[index].value, value)),
+            Err(position) => position,
+        };
+        let attribute = Attribute {
+            namespace,
+            name,
+            value,
+            prefix,
+        };
+        match self.sorted {
+            Some(ref mut sorted) => {
+                sorted.insert(position, self.list.len());
+                self.list.push(attribute);
+            }
+            None => self.list.insert(position, attribute),
+        }
+        None
+    }
+
+    /// Remove an attribute, returning its value if it was there.
+    pub(crate) fn remove(&mut self, namespace: &str, name: &str) -> Option<String> {
+        let index = self.find(namespace, name).ok()?;
+        if let Some(ref mut sorted) = self.sorted {
+            sorted.retain(|&i| i != index);
+            for i in sorted.iter_mut().filter(|i| **i > index) {
+                *i -= 1;
+            }
+        }
+        Some(self.list.remove(index).value)
+    }
+
+    /// Find the namespace of an attribute which has been parsed with the given prefix.
+    pub(crate) fn namespace_of_prefix(&self, prefix: &str) -> Option<&str> {
+        self.list
+            .iter()
+            .find(|attribute| attribute.prefix.as_deref() == Some(prefix))
+            .map(|attribute| &*attribute.namespace)
+    }
+
+    pub(crate) fn iter(&self) -> slice::Iter<Attribute> {
+        self.list.iter()
+    }
+
+    /// Iterate over the attributes, whose values only may be changed.
+    pub(crate) fn iter_mut(&mut self) -> slice::IterMut<Attribute> {
+        self.list.iter_mut()
+    }
+
+    /// Find the index of an attribute in `list`, or else the position in sorted order it should
+    /// be inserted at.
+    fn find(&self, namespace: &str, name: &str) -> Result<usize, usize> {
+        let key = (namespace, name);
+        match self.sorted {
+            Some(ref sorted) => sorted
+                .binary_search_by(|&index| self.list[index].key().cmp(&key))
+                .map(|position| sorted[position]),
+            None => self
+                .list
+                .binary_search_by(|attribute| attribute.key().cmp(&key)),
+        }
+    }
+}
+
+/// Split the name of an attribute, as given to `Element::attr`, into its prefix, if any, and its
+/// local name.
+pub(crate) fn split_attr_name(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, name),
+    }
+}
@@ -0,0 +1,300 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides `Canonicalization`, the methods `Element::write_canonical_to` can write the
+//! canonical form of an element with.
+
+use crate::attributes::XML_NS;
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::node::Node;
+
+use rxml::error::XmlError;
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// A canonicalization method, turning equivalent trees into the same bytes.
+///
+/// The element is canonicalized as the root of a document: the namespace declarations and
+/// `xml:` attributes of the elements it has been taken out of aren't known.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Canonicalization {
+    /// Canonical XML 1.0, dropping comments
+    Inclusive,
+    /// Canonical XML 1.0, keeping comments
+    InclusiveWithComments,
+    /// Exclusive XML Canonicalization 1.0, dropping comments
+    ///
+    /// The `InclusiveNamespaces PrefixList` parameter isn't supported: only the namespaces
+    /// visibly utilized by an element are declared on it.
+    Exclusive,
+    /// Exclusive XML Canonicalization 1.0, keeping comments
+    ExclusiveWithComments,
+}
+
+impl Canonicalization {
+    /// The URI identifying this method, as found in the `Algorithm` attribute of XML Signature
+    /// elements.
+    pub fn uri(self) -> &'static str {
+        match self {
+            Canonicalization::Inclusive => "http://www.w3.org/TR/2001/REC-xml-c14n-20010315",
+            Canonicalization::InclusiveWithComments => {
+                "http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments"
+            }
+            Canonicalization::Exclusive => "http://www.w3.org/2001/10/xml-exc-c14n#",
+            Canonicalization::ExclusiveWithComments => {
+                "http://www.w3.org/2001/10/xml-exc-c14n#WithComments"
+            }
+        }
+    }
+
+    fn is_exclusive(self) -> bool {
+        matches!(
+            self,
+            Canonicalization::Exclusive | Canonicalization::ExclusiveWithComments
+        )
+    }
+
+    fn with_comments(self) -> bool {
+        matches!(
+            self,
+            Canonicalization::InclusiveWithComments | Canonicalization::ExclusiveWithComments
+        )
+    }
+}
+
+/// Namespaces bound to prefixes, the empty prefix standing for the default namespace.
+type Bindings = BTreeMap<String, String>;
+
+pub(crate) fn write_canonical<W: Write>(
+    elem: &Element,
+    method: Canonicalization,
+    writer: &mut W,
+) -> Result<()> {
+    write_element(elem, method, &Bindings::new(), &Bindings::new(), writer)
+}
+
+/// Write `elem`, with `in_scope` the namespaces bound on its parent and `rendered` the ones
+/// declared by the elements written around it.
+fn write_element<W: Write>(
+    elem: &Element,
+    method: Canonicalization,
+    in_scope: &Bindings,
+    rendered: &Bindings,
+    writer: &mut W,
+) -> Result<()> {
+    rxml::strings::validate_ncname(elem.name())
+        .map_err(|_| Error::InvalidElementName(elem.name().to_owned()))?;
+
+    let mut scope = in_scope.clone();
+    for (prefix, namespace) in elem.prefixes.declared_prefixes() {
+        scope.insert(prefix.clone().unwrap_or_default(), namespace.clone());
+    }
+    // The prefix the element has been parsed with, if any, is rebound to its namespace in
+    // case it has been moved to another tree.
+    let prefix = match elem.prefix() {
+        Some(prefix) if !elem.ns().is_empty() => prefix,
+        _ => "",
+    };
+    scope.insert(prefix.to_owned(), elem.ns().to_owned());
+    let mut utilized = vec![prefix.to_owned()];
+
+    let mut attributes: Vec<_> = elem.attributes.iter().collect();
+    attributes.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+    let mut attribute_names = Vec::with_capacity(attributes.len());
+    for attribute in &attributes {
+        rxml::strings::validate_ncname(&attribute.name)
+            .map_err(|_| Error::InvalidAttributeName(attribute.name.clone()))?;
+        let prefix = match &*attribute.namespace {
+            "" => None,
+            XML_NS => Some(String::from("xml")),
+            namespace => Some(prefix_for(
+                &mut scope,
+                namespace,
+                attribute.prefix.as_deref(),
+            )),
+        };
+        match prefix {
+            Some(prefix) => {
+                attribute_names.push(format!("{}:{}", prefix, attribute.name));
+                if prefix != "xml" {
+                    utilized.push(prefix);
+                }
+            }
+            None => attribute_names.push(attribute.name.clone()),
+        }
+    }
+
+    let declared: Vec<&String> = if method.is_exclusive() {
+        utilized.sort();
+        utilized.dedup();
+        utilized.iter().collect()
+    } else {
+        scope.keys().filter(|prefix| *prefix != "xml").collect()
+    };
+    let mut declarations = Vec::new();
+    for prefix in declared {
+        let namespace = &scope[prefix];
+        let rendered_namespace = rendered.get(prefix).map_or("", String::as_str);
+        // Prefixes can't be unbound, only the default namespace can.
+        if rendered_namespace != namespace && (prefix.is_empty() || !namespace.is_empty()) {
+            let invalid = prefix == "xmlns" || rxml::strings::validate_ncname(prefix).is_err();
+            if !prefix.is_empty() && invalid {
+                return Err(Error::InvalidNamespaceDeclaration(
+                    Some(prefix.clone()),
+                    namespace.clone(),
+                ));
+            }
+            declarations.push((prefix, namespace));
+        }
+    }
+
+    let name = match prefix {
+        "" => elem.name().to_owned(),
+        prefix => format!("{}:{}", prefix, elem.name()),
+    };
+    write_raw(writer, "<")?;
+    write_raw(writer, &name)?;
+    let mut rendered = rendered.clone();
+    for (prefix, namespace) in declarations {
+        match prefix.as_str() {
+            "" => write_raw(writer, " xmlns=\"")?,
+            prefix => {
+                write_raw(writer, " xmlns:")?;
+                write_raw(writer, prefix)?;
+                write_raw(writer, "=\"")?;
+            }
+        }
+        write_escaped_attribute(writer, namespace)?;
+        write_raw(writer, "\"")?;
+        rendered.insert(prefix.clone(), namespace.clone());
+    }
+    for (attribute, name) in attributes.iter().zip(attribute_names) {
+        write_raw(writer, " ")?;
+        write_raw(writer, &name)?;
+        write_raw(writer, "=\"")?;
+        write_escaped_attribute(writer, &attribute.value)?;
+        write_raw(writer, "\"")?;
+    }
+    write_raw(writer, ">")?;
+
+    for child in elem.nodes() {
+        match *child {
+            Node::Element(ref child) => write_element(child, method, &scope, &rendered, writer)?,
+            Node::Text(ref text) | Node::CData(ref text) => write_escaped_text(writer, text)?,
+            Node::Comment(ref comment) => {
+                if !method.with_comments() {
+                    continue;
+                }
+                if comment.contains("--") || comment.ends_with('-') {
+                    return Err(rxml::Error::Xml(XmlError::InvalidSyntax("'--' in comment")).into());
+                }
+                write_raw(writer, "<!--")?;
+                write_raw(writer, comment)?;
+                write_raw(writer, "-->")?;
+            }
+            Node::ProcessingInstruction {
+                ref target,
+                ref data,
+            } => {
+                rxml::strings::validate_ncname(target).map_err(rxml::Error::Xml)?;
+                if target.eq_ignore_ascii_case("xml") {
+                    return Err(rxml::Error::Xml(XmlError::InvalidSyntax(
+                        "reserved processing instruction target",
+                    ))
+                    .into());
+                }
+                if data.contains("?>") {
+                    return Err(rxml::Error::Xml(XmlError::InvalidSyntax(
+                        "'?>' in processing instruction",
+                    ))
+                    .into());
+                }
+                rxml::strings::validate_cdata(data).map_err(rxml::Error::Xml)?;
+                write_raw(writer, "<?")?;
+                write_raw(writer, target)?;
+                if !data.is_empty() {
+                    write_raw(writer, " ")?;
+                    write_raw(writer, data)?;
+                }
+                write_raw(writer, "?>")?;
+            }
+        }
+    }
+
+    write_raw(writer, "</")?;
+    write_raw(writer, &name)?;
+    write_raw(writer, ">")
+}
+
+/// Returns a prefix bound to `namespace`, preferably `parsed` which the attribute has been parsed
+/// with, binding one if there isn't any.
+fn prefix_for(scope: &mut Bindings, namespace: &str, parsed: Option<&str>) -> String {
+    if let Some(prefix) = parsed.filter(|prefix| !prefix.is_empty()) {
+        match scope.get(prefix) {
+            Some(bound) if bound == namespace => return prefix.to_owned(),
+            // The declaration may have been left behind if the element has been moved.
+            None => {
+                scope.insert(prefix.to_owned(), namespace.to_owned());
+                return prefix.to_owned();
+            }
+            Some(_) => (),
+        }
+    }
+    if let Some((prefix, _)) = scope
+        .iter()
+        .find(|(prefix, bound)| !prefix.is_empty() && *bound == namespace)
+    {
+        return prefix.clone();
+    }
+    let prefix = (0..)
+        .map(|n| format!("tns{}", n))
+        .find(|prefix| !scope.contains_key(prefix))
+        .unwrap_or_default();
+    scope.insert(prefix.clone(), namespace.to_owned());
+    prefix
+}
+
+fn write_raw<W: Write>(writer: &mut W, s: &str) -> Result<()> {
+    writer
+        .write_all(s.as_bytes())
+        .map_err(|e| rxml::Error::io(e).into())
+}
+
+fn write_escaped_text<W: Write>(writer: &mut W, text: &str) -> Result<()> {
+    let mut last = 0;
+    for (i, c) in text.char_indices() {
+        let escaped = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            '\r' => "&#xD;",
+            _ => continue,
+        };
+        write_raw(writer, &text[last..i])?;
+        write_raw(writer, escaped)?;
+        last = i + 1;
+    }
+    write_raw(writer, &text[last..])
+}
+
+fn write_escaped_attribute<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+    let mut last = 0;
+    for (i, c) in value.char_indices() {
+        let escaped = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '"' => "&quot;",
+            '\t' => "&#x9;",
+            '\n' => "&#xA;",
+            '\r' => "&#xD;",
+            _ => continue,
+        };
+        write_raw(writer, &value[last..i])?;
+        write_raw(writer, escaped)?;
+        last = i + 1;
+    }
+    write_raw(writer, &value[last..])
+}
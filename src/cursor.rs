@@ -0,0 +1,399 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides `Cursor` and `CursorMut`, to move up, down and across a tree of elements.
+
+use crate::element::Element;
+use crate::node::Node;
+
+use std::fmt::Write;
+
+/// A read-only handle on an element of a tree, which knows its ancestors so it can move to its
+/// parent and siblings as well as to its children.
+///
+/// Only elements are visited: text and other nodes between siblings are skipped.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::Element;
+///
+/// let root: Element = "<root xmlns='ns1'><a/>text<b><c/></b></root>".parse().unwrap();
+///
+/// let c = root.cursor().descendants().find(|cursor| cursor.element().name() == "c").unwrap();
+/// assert_eq!(c.path(), "/root/b[1]/c[1]");
+///
+/// let b = c.parent().unwrap();
+/// assert_eq!(b.index_in_parent(), Some(1));
+/// assert_eq!(b.prev_sibling().unwrap().element().name(), "a");
+/// assert!(b.next_sibling().is_none());
+///
+/// let names: Vec<_> = c.ancestors().map(|cursor| cursor.element().name()).collect();
+/// assert_eq!(names, ["b", "root"]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cursor<'a> {
+    /// The ancestors of the element, from the root, each with the index in its `nodes` of the
+    /// next element down to this one
+    ancestors: Vec<(&'a Element, usize)>,
+    element: &'a Element,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(root: &'a Element) -> Cursor<'a> {
+        Cursor {
+            ancestors: Vec::new(),
+            element: root,
+        }
+    }
+
+    /// Create a cursor on the element found by following `indices` from `root`, if they all
+    /// point to elements.
+    pub(crate) fn at(root: &'a Element, indices: &[usize]) -> Option<Cursor<'a>> {
+        let mut cursor = Cursor::new(root);
+        for &index in indices {
+            let child = cursor.element.nodes().nth(index)?.as_element()?;
+            cursor.ancestors.push((cursor.element, index));
+            cursor.element = child;
+        }
+        Some(cursor)
+    }
+
+    /// Returns the element this cursor is on.
+    pub fn element(&self) -> &'a Element {
+        self.element
+    }
+
+    /// Returns a cursor on the parent of this element, or `None` if it is the root.
+    pub fn parent(&self) -> Option<Cursor<'a>> {
+        let mut ancestors = self.ancestors.clone();
+        let (parent, _) = ancestors.pop()?;
+        Some(Cursor {
+            ancestors,
+            element: parent,
+        })
+    }
+
+    /// Returns a cursor on the element following this one in its parent, if any.
+    pub fn next_sibling(&self) -> Option<Cursor<'a>> {
+        let &(parent, index) = self.ancestors.last()?;
+        let (index, sibling) = parent
+            .nodes()
+            .enumerate()
+            .skip(index + 1)
+            .find_map(|(index, node)| Some((index, node.as_element()?)))?;
+        Some(self.with_sibling(index, sibling))
+    }
+
+    /// Returns a cursor on the element preceding this one in its parent, if any.
+    pub fn prev_sibling(&self) -> Option<Cursor<'a>> {
+        let &(parent, index) = self.ancestors.last()?;
+        let (index, sibling) = parent
+            .nodes()
+            .enumerate()
+            .take(index)
+            .rev()
+            .find_map(|(index, node)| Some((index, node.as_element()?)))?;
+        Some(self.with_sibling(index, sibling))
+    }
+
+    /// Returns a cursor on the first child element of this element, if any.
+    pub fn first_child(&self) -> Option<Cursor<'a>> {
+        let (index, child) = self
+            .element
+            .nodes()
+            .enumerate()
+            .find_map(|(index, node)| Some((index, node.as_element()?)))?;
+        let mut ancestors = self.ancestors.clone();
+        ancestors.push((self.element, index));
+        Some(Cursor {
+            ancestors,
+            element: child,
+        })
+    }
+
+    /// Returns an iterator over cursors on the child elements of this element.
+    pub fn children(&self) -> CursorChildren<'a> {
+        CursorChildren {
+            next: self.first_child(),
+        }
+    }
+
+    /// Returns an iterator over cursors on the ancestors of this element, from its parent up to
+    /// the root.
+    pub fn ancestors(&self) -> Ancestors<'a> {
+        Ancestors {
+            next: self.parent(),
+        }
+    }
+
+    /// Returns an iterator over cursors on the descendants of this element, in document order.
+    pub fn descendants(&self) -> Descendants<'a> {
+        Descendants {
+            depth: self.ancestors.len(),
+            next: self.first_child(),
+        }
+    }
+
+    /// Returns the position of this element among the child elements of its parent, as in
+    /// `Element::children`, or `None` if it is the root.
+    pub fn index_in_parent(&self) -> Option<usize> {
+        let &(parent, index) = self.ancestors.last()?;
+        Some(
+            parent
+                .nodes()
+                .take(index)
+                .filter(|node| node.as_element().is_some())
+                .count(),
+        )
+    }
+
+    /// Returns the indices in `Element::nodes` leading from the root to this element, which
+    /// `Element::cursor_mut_at` takes to edit it.
+    pub fn indices(&self) -> Vec<usize> {
+        self.ancestors.iter().map(|&(_, index)| index).collect()
+    }
+
+    /// Describe where this element is in the style of XPath, such as `/root/body[2]/p[1]`,
+    /// giving the position of each element after the root among its siblings of the same name,
+    /// starting at 1.
+    ///
+    /// Namespaces are left out.
+    pub fn path(&self) -> String {
+        let mut elements = self.ancestors.iter().map(|&(element, _)| element);
+        let mut path = format!("/{}", elements.next().unwrap_or(self.element).name());
+        for (&(parent, index), element) in self
+            .ancestors
+            .iter()
+            .zip(elements.chain(Some(self.element)))
+        {
+            let position = parent
+                .nodes()
+                .take(index)
+                .filter_map(Node::as_element)
+                .filter(|sibling| sibling.name() == element.name())
+                .count();
+            // Writing to a String never fails.
+            let _ = write!(path, "/{}[{}]", element.name(), position + 1);
+        }
+        path
+    }
+
+    fn with_sibling(&self, index: usize, sibling: &'a Element) -> Cursor<'a> {
+        let mut ancestors = self.ancestors.clone();
+        if let Some(last) = ancestors.last_mut() {
+            last.1 = index;
+        }
+        Cursor {
+            ancestors,
+            element: sibling,
+        }
+    }
+}
+
+/// An iterator over cursors on the child elements of an element.
+#[derive(Clone, Debug)]
+pub struct CursorChildren<'a> {
+    next: Option<Cursor<'a>>,
+}
+
+impl<'a> Iterator for CursorChildren<'a> {
+    type Item = Cursor<'a>;
+
+    fn next(&mut self) -> Option<Cursor<'a>> {
+        let cursor = self.next.take()?;
+        self.next = cursor.next_sibling();
+        Some(cursor)
+    }
+}
+
+/// An iterator over cursors on the ancestors of an element, from its parent up to the root.
+#[derive(Clone, Debug)]
+pub struct Ancestors<'a> {
+    next: Option<Cursor<'a>>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = Cursor<'a>;
+
+    fn next(&mut self) -> Option<Cursor<'a>> {
+        let cursor = self.next.take()?;
+        self.next = cursor.parent();
+        Some(cursor)
+    }
+}
+
+/// An iterator over cursors on the descendants of an element, in document order.
+#[derive(Clone, Debug)]
+pub struct Descendants<'a> {
+    /// The depth of the element whose descendants are visited
+    depth: usize,
+    next: Option<Cursor<'a>>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = Cursor<'a>;
+
+    fn next(&mut self) -> Option<Cursor<'a>> {
+        let cursor = self.next.take()?;
+        self.next = cursor.first_child();
+        let mut current = Some(cursor.clone());
+        while self.next.is_none() {
+            match current {
+                Some(ref ancestor) if ancestor.ancestors.len() > self.depth => {
+                    self.next = ancestor.next_sibling();
+                    current = ancestor.parent();
+                }
+                _ => break,
+            }
+        }
+        Some(cursor)
+    }
+}
+
+/// A handle on an element of a tree which can move around like `Cursor` and edit the element,
+/// or insert and remove siblings of it.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::Element;
+///
+/// let mut root: Element = "<root xmlns='ns1'><a/><b><c/></b></root>".parse().unwrap();
+///
+/// let indices = root.cursor().descendants().find(|cursor| cursor.element().name() == "c").unwrap().indices();
+/// let mut cursor = root.cursor_mut_at(&indices).unwrap();
+/// cursor.element_mut().set_attr("found", "yes");
+///
+/// assert!(cursor.move_to_parent());
+/// cursor.insert_before(Element::bare("new", "ns1")).unwrap();
+/// assert!(cursor.move_to_prev_sibling());
+/// assert_eq!(cursor.element().name(), "new");
+///
+/// assert_eq!(String::from(&root), r#"<root xmlns='ns1'><a/><new/><b><c found="yes"/></b></root>"#);
+/// ```
+#[derive(Debug)]
+pub struct CursorMut<'a> {
+    root: &'a mut Element,
+    /// The indices in `nodes` leading from the root to the element
+    indices: Vec<usize>,
+}
+
+impl<'a> CursorMut<'a> {
+    pub(crate) fn new(root: &'a mut Element, indices: Vec<usize>) -> CursorMut<'a> {
+        CursorMut { root, indices }
+    }
+
+    /// Returns the element this cursor is on.
+    pub fn element(&self) -> &Element {
+        self.as_cursor().element
+    }
+
+    /// Returns the element this cursor is on, to be modified.
+    pub fn element_mut(&mut self) -> &mut Element {
+        let mut element = &mut *self.root;
+        for &index in &self.indices {
+            element = match element.children.get_mut(index) {
+                Some(Node::Element(child)) => child,
+                _ => unreachable!("cursor indices always lead to elements"),
+            };
+        }
+        element
+    }
+
+    /// Returns a read-only cursor on the same element, to look around without moving this one.
+    pub fn as_cursor(&self) -> Cursor<'_> {
+        match Cursor::at(self.root, &self.indices) {
+            Some(cursor) => cursor,
+            None => unreachable!("cursor indices always lead to elements"),
+        }
+    }
+
+    /// Move to the parent of this element, returning false if it is the root.
+    pub fn move_to_parent(&mut self) -> bool {
+        self.indices.pop().is_some()
+    }
+
+    /// Move to the element following this one in its parent, returning false if there is none.
+    pub fn move_to_next_sibling(&mut self) -> bool {
+        let indices = self
+            .as_cursor()
+            .next_sibling()
+            .map(|cursor| cursor.indices());
+        self.move_to(indices)
+    }
+
+    /// Move to the element preceding this one in its parent, returning false if there is none.
+    pub fn move_to_prev_sibling(&mut self) -> bool {
+        let indices = self
+            .as_cursor()
+            .prev_sibling()
+            .map(|cursor| cursor.indices());
+        self.move_to(indices)
+    }
+
+    /// Move to the first child element of this element, returning false if there is none.
+    pub fn move_to_first_child(&mut self) -> bool {
+        let indices = self
+            .as_cursor()
+            .first_child()
+            .map(|cursor| cursor.indices());
+        self.move_to(indices)
+    }
+
+    /// Returns the position of this element among the child elements of its parent, or `None`
+    /// if it is the root.
+    pub fn index_in_parent(&self) -> Option<usize> {
+        self.as_cursor().index_in_parent()
+    }
+
+    /// Describe where this element is, as `Cursor::path`.
+    pub fn path(&self) -> String {
+        self.as_cursor().path()
+    }
+
+    /// Insert `node` right before this element, failing with the node if this element is the
+    /// root.
+    pub fn insert_before<T: Into<Node>>(&mut self, node: T) -> Result<(), Node> {
+        let index = match self.indices.pop() {
+            Some(index) => index,
+            None => return Err(node.into()),
+        };
+        self.element_mut().children.insert(index, node.into());
+        self.indices.push(index + 1);
+        Ok(())
+    }
+
+    /// Insert `node` right after this element, failing with the node if this element is the
+    /// root.
+    pub fn insert_after<T: Into<Node>>(&mut self, node: T) -> Result<(), Node> {
+        let index = match self.indices.pop() {
+            Some(index) => index,
+            None => return Err(node.into()),
+        };
+        self.element_mut().children.insert(index + 1, node.into());
+        self.indices.push(index);
+        Ok(())
+    }
+
+    /// Remove this element from its parent and move to the parent, returning the removed
+    /// element, or `None` if this element is the root.
+    pub fn remove(&mut self) -> Option<Element> {
+        let index = self.indices.pop()?;
+        match self.element_mut().children.remove(index) {
+            Node::Element(element) => Some(element),
+            _ => unreachable!("cursor indices always lead to elements"),
+        }
+    }
+
+    fn move_to(&mut self, indices: Option<Vec<usize>>) -> bool {
+        match indices {
+            Some(indices) => {
+                self.indices = indices;
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,389 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! DOCTYPE parsing, and expansion of the general entities declared in its internal subset.
+
+use crate::document::Doctype;
+use crate::driver::{find, find_tag_end, normalize_newlines, resolve_reference, syntax_error};
+use crate::error::{Error, Result};
+use crate::tree_builder::{Limit, ParseOptions};
+
+use rxml::error::XmlError;
+
+use std::collections::HashMap;
+
+pub(crate) const DOCTYPE_START: &[u8] = b"<!DOCTYPE";
+
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+/// General entities declared in the internal subset, and the limits on their expansion.
+pub(crate) struct Entities {
+    /// Replacement text of every entity, or `None` for external entities
+    declared: HashMap<String, Option<String>>,
+    /// Entities currently being expanded
+    stack: Vec<String>,
+    /// Bytes of replacement text expanded so far
+    expanded: usize,
+    /// Entity declarations found so far, including the ones which aren't binding
+    declarations: usize,
+    max_depth: usize,
+    max_expansion: usize,
+    max_declarations: usize,
+}
+
+impl Entities {
+    pub(crate) fn new(options: &ParseOptions) -> Self {
+        Entities {
+            declared: HashMap::new(),
+            stack: Vec::new(),
+            expanded: 0,
+            declarations: 0,
+            max_depth: options.limits.max_entity_depth,
+            max_expansion: options.limits.max_entity_expansion,
+            max_declarations: options.limits.max_entity_declarations,
+        }
+    }
+
+    /// Whether any entity has been declared.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.declared.is_empty()
+    }
+
+    /// Declare an entity, unless it already is: the first declaration is binding.
+    fn declare(&mut self, name: &str, value: Option<String>) {
+        if !self.declared.contains_key(name) {
+            self.declared.insert(name.to_owned(), value);
+        }
+    }
+
+    /// Append the expansion of the reference `&name;` to `out`.
+    pub(crate) fn resolve(&mut self, name: &str, out: &mut String) -> Result<()> {
+        if name.starts_with('#') || matches!(name, "lt" | "gt" | "amp" | "apos" | "quot") {
+            out.push(resolve_reference(name)?);
+            return Ok(());
+        }
+        let value = match self.declared.get(name) {
+            Some(Some(value)) => value.clone(),
+            Some(None) => return Err(rxml::Error::RestrictedXml("external entities").into()),
+            None => return Err(rxml::Error::Xml(XmlError::UndeclaredEntity).into()),
+        };
+        if self.stack.iter().any(|entity| entity == name) {
+            return Err(syntax_error("recursive entity reference"));
+        }
+        // Every expansion costs at least a byte, so that empty entities can't be used to make
+        // the parser spin without bounds.
+        self.expanded += value.len().max(1);
+        if self.stack.len() >= self.max_depth {
+            return Err(Error::LimitExceeded(Limit::EntityDepth));
+        }
+        if self.expanded > self.max_expansion {
+            return Err(Error::LimitExceeded(Limit::EntityExpansion));
+        }
+        if value.contains('<') {
+            return Err(rxml::Error::RestrictedXml("entities containing markup").into());
+        }
+
+        self.stack.push(name.to_owned());
+        let mut rest = &value[..];
+        while let Some(i) = rest.find('&') {
+            out.push_str(&rest[..i]);
+            let len = rest[i..]
+                .find(';')
+                .ok_or(rxml::Error::Xml(XmlError::UndeclaredEntity))?;
+            self.resolve(&rest[i + 1..i + len], out)?;
+            rest = &rest[i + len + 1..];
+        }
+        out.push_str(rest);
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Expand the entity references in the attribute values of the start tag `tag`, so that
+    /// only predefined entities and character references are left for rxml.
+    pub(crate) fn expand_attributes(&mut self, tag: &str) -> Result<String> {
+        let mut out = String::with_capacity(tag.len());
+        let mut quote = None;
+        let mut rest = tag;
+        while let Some(i) = rest.find(|c: char| matches!(c, '\'' | '"' | '&')) {
+            let c = rest.as_bytes()[i];
+            out.push_str(&rest[..=i]);
+            rest = &rest[i + 1..];
+            match (quote, c) {
+                (Some(q), c) if q == c => quote = None,
+                (None, b'\'' | b'"') => quote = Some(c),
+                (Some(_), b'&') => {
+                    let len = rest
+                        .find(';')
+                        .ok_or(rxml::Error::Xml(XmlError::UndeclaredEntity))?;
+                    let name = &rest[..len];
+                    if name.starts_with('#') || !self.declared.contains_key(name) {
+                        // Left for rxml to resolve or reject.
+                        continue;
+                    }
+                    out.pop();
+                    let mut value = String::new();
+                    self.resolve(name, &mut value)?;
+                    for c in value.chars() {
+                        match c {
+                            '&' => out.push_str("&amp;"),
+                            '<' => out.push_str("&lt;"),
+                            '\'' => out.push_str("&apos;"),
+                            '"' => out.push_str("&quot;"),
+                            c => out.push(c),
+                        }
+                    }
+                    rest = &rest[len + 1..];
+                }
+                _ => (),
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+/// How far the end of a DOCTYPE has been looked for, to resume the search once more input is
+/// available.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct DoctypeScan {
+    pos: usize,
+    /// Whether the search stopped within the internal subset
+    in_subset: bool,
+    /// The delimiter of the literal, comment or processing instruction the search stopped in
+    closing: Option<&'static [u8]>,
+}
+
+impl DoctypeScan {
+    /// Find the closing `>` of the DOCTYPE at the start of `input`, skipping over its internal
+    /// subset, from where the previous search on the same DOCTYPE stopped.
+    pub(crate) fn find_end(&mut self, input: &[u8]) -> Option<usize> {
+        let mut i = self.pos.max(DOCTYPE_START.len());
+        loop {
+            if let Some(closing) = self.closing {
+                match find(input, closing, i) {
+                    Some(end) => {
+                        i = end + closing.len();
+                        self.closing = None;
+                    }
+                    None => {
+                        self.pos = i.max(input.len().saturating_sub(closing.len() - 1));
+                        return None;
+                    }
+                }
+                continue;
+            }
+            let rest = &input[i.min(input.len())..];
+            // The start of a comment or processing instruction may not be complete yet.
+            if rest.is_empty() || self.in_subset && rest.len() < 4 && b"<!--".starts_with(rest) {
+                self.pos = i;
+                return None;
+            }
+            match rest[0] {
+                b'"' => self.closing = Some(b"\""),
+                b'\'' => self.closing = Some(b"'"),
+                b'<' if self.in_subset && rest.starts_with(b"<!--") => {
+                    self.closing = Some(b"-->");
+                    i += 3;
+                }
+                b'<' if self.in_subset && rest.starts_with(b"<?") => {
+                    self.closing = Some(b"?>");
+                    i += 1;
+                }
+                b'[' if !self.in_subset => self.in_subset = true,
+                b']' if self.in_subset => self.in_subset = false,
+                b'>' if !self.in_subset => return Some(i),
+                _ => (),
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Parse a DOCTYPE, found between `<!DOCTYPE` and `>`, declaring the entities of its internal
+/// subset.
+pub(crate) fn parse_doctype(content: &str, entities: &mut Entities) -> Result<Doctype> {
+    let rest = skip_space(content)?;
+    let name_len = rest
+        .find(|c: char| c == '[' || is_space(c))
+        .unwrap_or(rest.len());
+    let (name, mut rest) = rest.split_at(name_len);
+    rxml::strings::validate_name(name).map_err(rxml::Error::Xml)?;
+
+    let mut public_id = None;
+    let mut system_id = None;
+    let trimmed = rest.trim_start_matches(is_space);
+    if trimmed.starts_with("SYSTEM") || trimmed.starts_with("PUBLIC") {
+        let (public, system, after) = parse_external_id(trimmed)?;
+        public_id = public.map(str::to_owned);
+        system_id = Some(system.to_owned());
+        rest = after;
+    }
+
+    let mut internal_subset = None;
+    let trimmed = rest.trim_start_matches(is_space);
+    if let Some(subset) = trimmed.strip_prefix('[') {
+        let end = subset
+            .rfind(']')
+            .ok_or_else(|| syntax_error("unterminated internal subset"))?;
+        parse_internal_subset(&subset[..end], entities)?;
+        internal_subset = Some(subset[..end].to_owned());
+        rest = &subset[end + 1..];
+    }
+    if !rest.trim_start_matches(is_space).is_empty() {
+        return Err(syntax_error("invalid DOCTYPE"));
+    }
+
+    Ok(Doctype {
+        name: name.to_owned(),
+        public_id,
+        system_id,
+        internal_subset,
+    })
+}
+
+/// Skip the mandatory whitespace at the start of `s`.
+fn skip_space(s: &str) -> Result<&str> {
+    let trimmed = s.trim_start_matches(is_space);
+    if trimmed.len() == s.len() {
+        return Err(syntax_error("missing whitespace in DOCTYPE"));
+    }
+    Ok(trimmed)
+}
+
+/// Split a quoted literal off the start of `s`.
+fn parse_literal(s: &str) -> Result<(&str, &str)> {
+    let quote = match s.chars().next() {
+        Some(quote @ ('\'' | '"')) => quote,
+        _ => return Err(syntax_error("missing literal in DOCTYPE")),
+    };
+    let len = s[1..]
+        .find(quote)
+        .ok_or_else(|| syntax_error("unterminated literal in DOCTYPE"))?;
+    Ok((&s[1..len + 1], &s[len + 2..]))
+}
+
+/// Parse `SYSTEM "system"` or `PUBLIC "public" "system"`.
+fn parse_external_id(s: &str) -> Result<(Option<&str>, &str, &str)> {
+    if let Some(rest) = s.strip_prefix("SYSTEM") {
+        let (system, rest) = parse_literal(skip_space(rest)?)?;
+        Ok((None, system, rest))
+    } else if let Some(rest) = s.strip_prefix("PUBLIC") {
+        let (public, rest) = parse_literal(skip_space(rest)?)?;
+        let (system, rest) = parse_literal(skip_space(rest)?)?;
+        Ok((Some(public), system, rest))
+    } else {
+        Err(syntax_error("invalid external identifier"))
+    }
+}
+
+/// Parse the markup declarations of an internal subset, keeping only the entity declarations.
+fn parse_internal_subset(subset: &str, entities: &mut Entities) -> Result<()> {
+    let mut rest = subset;
+    loop {
+        rest = rest.trim_start_matches(is_space);
+        if rest.is_empty() {
+            return Ok(());
+        }
+        rest = if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment
+                .find("--")
+                .filter(|&end| comment[end + 2..].starts_with('>'))
+                .ok_or_else(|| syntax_error("'--' in comment"))?;
+            &comment[end + 3..]
+        } else if let Some(pi) = rest.strip_prefix("<?") {
+            let end = pi
+                .find("?>")
+                .ok_or_else(|| syntax_error("unterminated processing instruction"))?;
+            &pi[end + 2..]
+        } else if let Some(decl) = rest.strip_prefix("<!ENTITY") {
+            entities.declarations += 1;
+            if entities.declarations > entities.max_declarations {
+                return Err(Error::LimitExceeded(Limit::EntityDeclarations));
+            }
+            parse_entity_decl(decl, entities)?
+        } else if rest.starts_with("<!ELEMENT")
+            || rest.starts_with("<!ATTLIST")
+            || rest.starts_with("<!NOTATION")
+        {
+            let end = find_tag_end(rest.as_bytes())
+                .ok_or_else(|| syntax_error("unterminated markup declaration"))?;
+            &rest[end + 1..]
+        } else if rest.starts_with('%') {
+            return Err(rxml::Error::RestrictedXml("parameter entity references").into());
+        } else {
+            return Err(syntax_error("invalid markup in internal subset"));
+        };
+    }
+}
+
+/// Parse an entity declaration, found after `<!ENTITY`, returning what follows it.
+fn parse_entity_decl<'a>(decl: &'a str, entities: &mut Entities) -> Result<&'a str> {
+    let mut rest = skip_space(decl)?;
+    let parameter = match rest.strip_prefix('%') {
+        Some(after) => {
+            rest = skip_space(after)?;
+            true
+        }
+        None => false,
+    };
+    let name_len = rest.find(is_space).unwrap_or(rest.len());
+    let (name, after) = rest.split_at(name_len);
+    rxml::strings::validate_ncname(name).map_err(rxml::Error::Xml)?;
+    let rest = skip_space(after)?;
+
+    let (value, rest) = if rest.starts_with(['\'', '"']) {
+        let (literal, rest) = parse_literal(rest)?;
+        (Some(parse_entity_value(literal)?), rest)
+    } else {
+        let (_, _, mut rest) = parse_external_id(rest)?;
+        let trimmed = rest.trim_start_matches(is_space);
+        if let Some(notation) = trimmed.strip_prefix("NDATA") {
+            let notation = skip_space(notation)?;
+            let len = notation
+                .find(|c: char| c == '>' || is_space(c))
+                .unwrap_or(notation.len());
+            rest = &notation[len..];
+        }
+        (None, rest)
+    };
+    let rest = rest
+        .trim_start_matches(is_space)
+        .strip_prefix('>')
+        .ok_or_else(|| syntax_error("unterminated entity declaration"))?;
+
+    // Parameter entities can only be used within the DTD, where references are rejected.
+    if !parameter {
+        entities.declare(name, value);
+    }
+    Ok(rest)
+}
+
+/// Turn an entity value literal into replacement text, which only expands character
+/// references: references to other entities are expanded when the entity is used.
+fn parse_entity_value(literal: &str) -> Result<String> {
+    let mut value = String::with_capacity(literal.len());
+    let mut rest = literal;
+    while let Some(i) = rest.find(['&', '%']) {
+        if rest.as_bytes()[i] == b'%' {
+            return Err(rxml::Error::RestrictedXml("parameter entity references").into());
+        }
+        normalize_newlines(&rest[..i], &mut value);
+        let len = rest[i..]
+            .find(';')
+            .ok_or(rxml::Error::Xml(XmlError::UndeclaredEntity))?;
+        let name = &rest[i + 1..i + len];
+        if name.starts_with('#') {
+            value.push(resolve_reference(name)?);
+        } else {
+            rxml::strings::validate_name(name).map_err(rxml::Error::Xml)?;
+            value.push_str(&rest[i..=i + len]);
+        }
+        rest = &rest[i + len + 1..];
+    }
+    normalize_newlines(rest, &mut value);
+    rxml::strings::validate_cdata(&value).map_err(rxml::Error::Xml)?;
+    Ok(value)
+}
@@ -0,0 +1,513 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides a `Document` type, which holds a root `Element` together with everything around it.
+
+use crate::driver::{is_encoding_name, is_version_number, Driver, Event};
+use crate::element::{Element, ItemWriter};
+use crate::encoding::{EncodedWriter, Encoding};
+use crate::error::{Error, Result};
+use crate::node::Node;
+use crate::tree_builder::{ParseOptions, TreeBuilder};
+use crate::writer::WriteOptions;
+
+use rxml::error::XmlError;
+
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncBufRead;
+
+/// The content of an XML declaration, `<?xml version='1.0'?>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XmlDeclaration {
+    /// The declared XML version, such as `1.0`
+    pub version: String,
+    /// The declared encoding, if any
+    pub encoding: Option<String>,
+    /// The standalone flag, if declared
+    pub standalone: Option<bool>,
+}
+
+impl Default for XmlDeclaration {
+    fn default() -> Self {
+        XmlDeclaration {
+            version: String::from("1.0"),
+            encoding: None,
+            standalone: None,
+        }
+    }
+}
+
+/// A document type declaration, `<!DOCTYPE name [...]>`.
+///
+/// Only parsed when allowed by `ParseOptions::allow_doctype`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Doctype {
+    /// The name of the root element
+    pub name: String,
+    /// The public identifier of the external subset, if any
+    pub public_id: Option<String>,
+    /// The system identifier of the external subset, if any
+    pub system_id: Option<String>,
+    /// The internal subset, verbatim and without its brackets
+    pub internal_subset: Option<String>,
+}
+
+/// A whole XML document: the root element, the XML declaration, and the comments, processing
+/// instructions and whitespace found before and after the root element.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::Document;
+///
+/// let xml = "<?xml version='1.0' standalone='yes'?>\n<!-- hi -->\n<root xmlns='ns1'/>\n";
+/// let doc: Document = xml.parse().unwrap();
+///
+/// assert_eq!(doc.declaration().unwrap().standalone, Some(true));
+/// assert_eq!(doc.prolog()[1].as_comment(), Some(" hi "));
+/// assert_eq!(doc.root().name(), "root");
+///
+/// let mut out = Vec::new();
+/// doc.write_to(&mut out).unwrap();
+/// assert_eq!(out, xml.as_bytes());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Document {
+    declaration: Option<XmlDeclaration>,
+    doctype: Option<Doctype>,
+    /// How many prolog nodes come before the DOCTYPE
+    doctype_position: usize,
+    prolog: Vec<Node>,
+    root: Element,
+    epilog: Vec<Node>,
+}
+
+impl FromStr for Document {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Document> {
+        Document::from_reader(s.as_bytes())
+    }
+}
+
+impl From<Element> for Document {
+    fn from(root: Element) -> Document {
+        Document::new(root)
+    }
+}
+
+impl Document {
+    /// Create a document around `root`, without an XML declaration.
+    pub fn new(root: Element) -> Document {
+        Document {
+            declaration: None,
+            doctype: None,
+            doctype_position: 0,
+            prolog: Vec::new(),
+            root,
+            epilog: Vec::new(),
+        }
+    }
+
+    /// Parse a whole document from a `BufRead`, up to the end of its input.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Document> {
+        Document::from_reader_with_options(reader, ParseOptions::default())
+    }
+
+    /// Parse a whole document from a `BufRead`, using the given `ParseOptions`.
+    pub fn from_reader_with_options<R: BufRead>(
+        mut reader: R,
+        options: ParseOptions,
+    ) -> Result<Document> {
+        let mut builder = DocumentBuilder::new(options);
+        let mut driver = Driver::new(builder.tree_builder.options());
+        let mut parse = || {
+            while !builder.drive(&mut driver)? {
+                driver.feed_from(&mut reader)?;
+            }
+            Ok(())
+        };
+        parse()
+            .and_then(|()| builder.finish())
+            .map_err(|err| driver.locate(err))
+    }
+
+    /// Parse a whole document from an `AsyncBufRead`, up to the end of its input.
+    #[cfg(feature = "tokio")]
+    pub async fn from_async_reader<R: AsyncBufRead + Unpin>(reader: R) -> Result<Document> {
+        Document::from_async_reader_with_options(reader, ParseOptions::default()).await
+    }
+
+    /// Parse a whole document from an `AsyncBufRead`, using the given `ParseOptions`.
+    #[cfg(feature = "tokio")]
+    pub async fn from_async_reader_with_options<R: AsyncBufRead + Unpin>(
+        mut reader: R,
+        options: ParseOptions,
+    ) -> Result<Document> {
+        let mut builder = DocumentBuilder::new(options);
+        let mut driver = Driver::new(builder.tree_builder.options());
+        let parse = async {
+            while !builder.drive(&mut driver)? {
+                driver.feed_from_async(&mut reader).await?;
+            }
+            Ok(())
+        };
+        parse
+            .await
+            .and_then(|()| builder.finish())
+            .map_err(|err| driver.locate(err))
+    }
+
+    /// The XML declaration of this document, if any.
+    pub fn declaration(&self) -> Option<&XmlDeclaration> {
+        self.declaration.as_ref()
+    }
+
+    /// Set or remove the XML declaration of this document.
+    pub fn set_declaration(&mut self, declaration: Option<XmlDeclaration>) {
+        self.declaration = declaration;
+    }
+
+    /// The DOCTYPE of this document, if any.
+    pub fn doctype(&self) -> Option<&Doctype> {
+        self.doctype.as_ref()
+    }
+
+    /// Set or remove the DOCTYPE of this document. A parsed DOCTYPE is written back where it was
+    /// found among the prolog nodes, a new one right after the XML declaration.
+    pub fn set_doctype(&mut self, doctype: Option<Doctype>) {
+        if self.doctype.is_none() {
+            self.doctype_position = 0;
+        }
+        self.doctype = doctype;
+    }
+
+    /// The nodes between the XML declaration and the root element.
+    pub fn prolog(&self) -> &[Node] {
+        &self.prolog
+    }
+
+    /// A mutable reference to the nodes between the XML declaration and the root element. Only
+    /// comments, processing instructions and whitespace text can be written out.
+    pub fn prolog_mut(&mut self) -> &mut Vec<Node> {
+        &mut self.prolog
+    }
+
+    /// The root element of this document.
+    pub fn root(&self) -> &Element {
+        &self.root
+    }
+
+    /// A mutable reference to the root element of this document.
+    pub fn root_mut(&mut self) -> &mut Element {
+        &mut self.root
+    }
+
+    /// Turn this document into its root element, dropping everything around it.
+    pub fn into_root(self) -> Element {
+        self.root
+    }
+
+    /// The nodes after the root element.
+    pub fn epilog(&self) -> &[Node] {
+        &self.epilog
+    }
+
+    /// A mutable reference to the nodes after the root element. Only comments, processing
+    /// instructions and whitespace text can be written out.
+    pub fn epilog_mut(&mut self) -> &mut Vec<Node> {
+        &mut self.epilog
+    }
+
+    /// Output the whole document to a `Writer`, in the encoding its XML declaration declares,
+    /// or in UTF-8 if it doesn't declare any.
+    ///
+    /// UTF-16, UTF-16LE, UTF-16BE, ISO-8859-1 and US-ASCII are supported besides UTF-8.
+    /// Documents declared as UTF-16 are written in big endian, after a byte order mark.
+    /// Characters the encoding lacks are written as character references in text and
+    /// attribute values, CDATA sections being split around them. Anywhere else, like in names
+    /// and comments, writing fails with `Error::UnrepresentableChar`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Document, Element, XmlDeclaration};
+    ///
+    /// let mut doc = Document::new(Element::builder("a", "ns1").append("café").build());
+    /// doc.set_declaration(Some(XmlDeclaration {
+    ///     encoding: Some(String::from("ISO-8859-1")),
+    ///     ..XmlDeclaration::default()
+    /// }));
+    ///
+    /// let mut out = Vec::new();
+    /// doc.write_to(&mut out).unwrap();
+    /// assert_eq!(out, b"<?xml version='1.0' encoding='ISO-8859-1'?><a xmlns='ns1'>caf\xe9</a>");
+    /// ```
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.write_to_with_options(writer, WriteOptions::default())
+    }
+
+    /// Output the whole document to a `Writer` like `write_to()`, laid out according to
+    /// `options`. When pretty printing, the nodes around the root element are put on their own
+    /// lines instead of being separated by the whitespace they had.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Document, WriteOptions};
+    ///
+    /// let doc = Document::from_reader(&b"<?xml version='1.0'?><!-- a --><a xmlns='ns1'><b/></a>"[..]).unwrap();
+    ///
+    /// let mut out = Vec::new();
+    /// doc.write_to_with_options(&mut out, WriteOptions::new().indent("  ")).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(out).unwrap(),
+    ///     "<?xml version='1.0'?>\n<!-- a -->\n<a xmlns='ns1'>\n  <b/>\n</a>",
+    /// );
+    /// ```
+    pub fn write_to_with_options<W: Write>(
+        &self,
+        writer: &mut W,
+        options: WriteOptions,
+    ) -> Result<()> {
+        let name = match self.declaration {
+            Some(XmlDeclaration {
+                encoding: Some(ref name),
+                ..
+            }) => name,
+            _ => return self.to_writer(&mut ItemWriter::with_options(writer, options)),
+        };
+        let encoding =
+            Encoding::from_name(name).ok_or_else(|| Error::UnsupportedEncoding(name.to_owned()))?;
+        if encoding == Encoding::Utf8 {
+            return self.to_writer(&mut ItemWriter::with_options(writer, options));
+        }
+        let mut encoded = EncodedWriter::new(writer, encoding);
+        let result = self.to_writer(&mut ItemWriter::with_options(&mut encoded, options));
+        match encoded.unrepresentable() {
+            Some(c) => Err(Error::UnrepresentableChar(c)),
+            None => result,
+        }
+    }
+
+    /// Output the whole document to an `ItemWriter`, always in UTF-8.
+    pub fn to_writer<W: Write>(&self, writer: &mut ItemWriter<W>) -> Result<()> {
+        // When pretty printing, whitespace is replaced with a line break before every node but
+        // the first one.
+        let pretty = writer.pretty;
+        let mut first = true;
+        let mut separate = |writer: &mut ItemWriter<W>| {
+            if !std::mem::replace(&mut first, false) {
+                writer.write_indent(0)?;
+            }
+            Ok::<_, Error>(())
+        };
+        if let Some(ref declaration) = self.declaration {
+            separate(writer)?;
+            write_declaration(declaration, writer)?;
+        }
+        for (i, node) in self.prolog.iter().enumerate() {
+            if i == self.doctype_position && self.doctype.is_some() {
+                separate(writer)?;
+                self.write_doctype(writer)?;
+            }
+            if pretty && is_whitespace(node) {
+                continue;
+            }
+            separate(writer)?;
+            write_misc(node, writer)?;
+        }
+        if self.doctype_position >= self.prolog.len() && self.doctype.is_some() {
+            separate(writer)?;
+            self.write_doctype(writer)?;
+        }
+        separate(writer)?;
+        self.root.to_writer(writer)?;
+        for node in &self.epilog {
+            if pretty && is_whitespace(node) {
+                continue;
+            }
+            separate(writer)?;
+            write_misc(node, writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_doctype<W: Write>(&self, writer: &mut ItemWriter<W>) -> Result<()> {
+        let doctype = match self.doctype {
+            Some(ref doctype) => doctype,
+            None => return Ok(()),
+        };
+        rxml::strings::validate_name(&doctype.name).map_err(rxml::Error::Xml)?;
+        writer.write_raw(b"<!DOCTYPE ")?;
+        writer.write_raw(doctype.name.as_bytes())?;
+        match (&doctype.public_id, &doctype.system_id) {
+            (Some(public_id), Some(system_id)) => {
+                writer.write_raw(b" PUBLIC ")?;
+                write_literal(public_id, writer)?;
+                writer.write_raw(b" ")?;
+                write_literal(system_id, writer)?;
+            }
+            (None, Some(system_id)) => {
+                writer.write_raw(b" SYSTEM ")?;
+                write_literal(system_id, writer)?;
+            }
+            (Some(_), None) => {
+                return Err(syntax_error("public identifier without system identifier"))
+            }
+            (None, None) => (),
+        }
+        if let Some(ref subset) = doctype.internal_subset {
+            rxml::strings::validate_cdata(subset).map_err(rxml::Error::Xml)?;
+            writer.write_raw(b" [")?;
+            writer.write_raw(subset.as_bytes())?;
+            writer.write_raw(b"]")?;
+        }
+        writer.write_raw(b">")
+    }
+}
+
+/// Sorts out the events of a whole document.
+struct DocumentBuilder {
+    tree_builder: TreeBuilder,
+    declaration: Option<XmlDeclaration>,
+    doctype: Option<Doctype>,
+    doctype_position: usize,
+    prolog: Vec<Node>,
+    root: Option<Element>,
+    epilog: Vec<Node>,
+}
+
+impl DocumentBuilder {
+    fn new(options: ParseOptions) -> DocumentBuilder {
+        DocumentBuilder {
+            tree_builder: TreeBuilder::new().with_options(options),
+            declaration: None,
+            doctype: None,
+            doctype_position: 0,
+            prolog: Vec::new(),
+            root: None,
+            epilog: Vec::new(),
+        }
+    }
+
+    /// Process the events available from `driver`, returning whether the end of the input has
+    /// been reached.
+    fn drive(&mut self, driver: &mut Driver) -> Result<bool> {
+        while let Some(event) = driver.read()? {
+            self.process(event)?;
+        }
+        Ok(driver.is_eof())
+    }
+
+    fn process(&mut self, event: Event) -> Result<()> {
+        let node = match event {
+            _ if self.tree_builder.depth() > 0 => None,
+            Event::XmlDeclaration(declaration) => {
+                self.declaration = Some(declaration);
+                return Ok(());
+            }
+            Event::Doctype(doctype) => {
+                self.doctype = Some(doctype);
+                self.doctype_position = self.prolog.len();
+                return Ok(());
+            }
+            Event::Text(ref text) => Some(Node::Text(text.clone())),
+            Event::Comment(ref comment) => Some(Node::Comment(comment.clone())),
+            Event::ProcessingInstruction {
+                ref target,
+                ref data,
+            } => Some(Node::ProcessingInstruction {
+                target: target.clone(),
+                data: data.clone(),
+            }),
+            _ => None,
+        };
+        match node {
+            Some(node) if self.root.is_none() => self.prolog.push(node),
+            Some(node) => self.epilog.push(node),
+            None => {
+                self.tree_builder.process_driver_event(event)?;
+                if let Some(root) = self.tree_builder.root.take() {
+                    self.root = Some(root);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Document> {
+        Ok(Document {
+            declaration: self.declaration,
+            doctype: self.doctype,
+            doctype_position: self.doctype_position,
+            prolog: self.prolog,
+            root: self.root.ok_or(Error::EndOfDocument)?,
+            epilog: self.epilog,
+        })
+    }
+}
+
+fn syntax_error(msg: &'static str) -> Error {
+    rxml::Error::Xml(XmlError::InvalidSyntax(msg)).into()
+}
+
+fn write_declaration<W: Write>(
+    declaration: &XmlDeclaration,
+    writer: &mut ItemWriter<W>,
+) -> Result<()> {
+    if !is_version_number(&declaration.version) {
+        return Err(syntax_error("invalid XML version"));
+    }
+    writer.write_raw(b"<?xml version='")?;
+    writer.write_raw(declaration.version.as_bytes())?;
+    writer.write_raw(b"'")?;
+    if let Some(ref encoding) = declaration.encoding {
+        if !is_encoding_name(encoding) {
+            return Err(syntax_error("invalid encoding name"));
+        }
+        writer.write_raw(b" encoding='")?;
+        writer.write_raw(encoding.as_bytes())?;
+        writer.write_raw(b"'")?;
+    }
+    match declaration.standalone {
+        Some(true) => writer.write_raw(b" standalone='yes'")?,
+        Some(false) => writer.write_raw(b" standalone='no'")?,
+        None => (),
+    }
+    writer.write_raw(b"?>")
+}
+
+/// Write a quoted literal of a DOCTYPE.
+fn write_literal<W: Write>(literal: &str, writer: &mut ItemWriter<W>) -> Result<()> {
+    let quote: &[u8] = match (literal.contains('"'), literal.contains('\'')) {
+        (false, _) => b"\"",
+        (true, false) => b"'",
+        (true, true) => return Err(syntax_error("literal containing both kinds of quotes")),
+    };
+    writer.write_raw(quote)?;
+    writer.write_raw(literal.as_bytes())?;
+    writer.write_raw(quote)
+}
+
+fn is_whitespace(node: &Node) -> bool {
+    match *node {
+        Node::Text(ref text) => text.chars().all(|c| matches!(c, ' ' | '\t' | '\r' | '\n')),
+        _ => false,
+    }
+}
+
+/// Write a node found outside of the root element.
+pub(crate) fn write_misc<W: Write>(node: &Node, writer: &mut ItemWriter<W>) -> Result<()> {
+    match *node {
+        Node::Text(ref text) if is_whitespace(node) => writer.write_raw(text.as_bytes()),
+        Node::Comment(_) | Node::ProcessingInstruction { .. } => node.write_to_inner(writer),
+        _ => Err(syntax_error(
+            "only comments, processing instructions and whitespace are allowed around the root element",
+        )),
+    }
+}
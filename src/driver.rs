@@ -0,0 +1,1060 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Push-based parser frontend.
+//!
+//! rxml only accepts the subset of XML needed for XMPP, and rejects constructs like comments. The
+//! `Driver` splits its input into constructs: tags are handed over to rxml one at a time, while
+//! the XML declaration, comments, processing instructions, text and CDATA sections are handled
+//! here. `ElementRef` splits its input with the same `Scanner`, but reads tags by itself, to
+//! borrow their names and values.
+
+use crate::doctype::{parse_doctype, DoctypeScan, Entities, DOCTYPE_START};
+use crate::document::{Doctype, XmlDeclaration};
+use crate::encoding::{Decoder, Encoding};
+use crate::error::{Error, Result};
+use crate::position::{Position, Span, Tracker};
+use crate::tree_builder::{Limit, ParseOptions};
+
+use rxml::error::XmlError;
+use rxml::{Lexer, PushDriver, RawEvent, RawParser};
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::str;
+#[cfg(feature = "tokio")]
+use std::{future, pin::Pin};
+
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncBufRead;
+
+const COMMENT_START: &[u8] = b"<!--";
+const COMMENT_END: &[u8] = b"--";
+const CDATA_START: &[u8] = b"<![CDATA[";
+const CDATA_END: &[u8] = b"]]>";
+const PI_START: &[u8] = b"<?";
+const PI_END: &[u8] = b"?>";
+const XML_DECL_START: &[u8] = b"<?xml";
+
+/// An event produced by the `Driver`.
+pub(crate) enum Event {
+    /// An event produced by rxml
+    Raw(RawEvent),
+    /// The XML declaration
+    XmlDeclaration(XmlDeclaration),
+    /// The DOCTYPE, if allowed
+    Doctype(Doctype),
+    /// Where the next start or end tag is, sent before its events
+    TagSpan {
+        /// The whole tag
+        span: Span,
+        /// Every attribute, namespace declarations included, in order
+        attributes: Vec<Span>,
+    },
+    /// Text content, including CDATA sections unless they are preserved
+    Text(String),
+    /// The content of a CDATA section, if they are preserved
+    CData(String),
+    /// A comment, without its delimiters
+    Comment(String),
+    /// A processing instruction
+    ProcessingInstruction {
+        /// The target application
+        target: String,
+        /// Everything after the target and the whitespace following it
+        data: String,
+    },
+}
+
+/// The kind of the next construct in the input.
+pub(crate) enum Construct {
+    Text,
+    CData,
+    Comment,
+    ProcessingInstruction,
+    XmlDeclaration,
+    Doctype,
+    Markup,
+}
+
+/// Incremental parser, which is fed with bytes and produces `Event`s.
+pub(crate) struct Driver {
+    /// Bytes which have been fed but not processed yet, starting at `pos`
+    buf: Vec<u8>,
+    pos: usize,
+    /// Finds where the construct at `pos` ends
+    scanner: Scanner,
+    /// Position at `pos`, and at the start of the construct being processed
+    tracker: Tracker,
+    construct_start: Position,
+    /// Whether the end of the input has been reached
+    eof: bool,
+    /// Whether rxml has been told about the end of the input
+    finished: bool,
+    /// Parser for tags and the XML declaration
+    inner: PushDriver<RawParser>,
+    /// Number of currently open elements
+    depth: usize,
+    /// Whether the root element has been opened
+    seen_root: bool,
+    /// Whether a DOCTYPE has been found
+    seen_doctype: bool,
+    /// Entities declared in the DOCTYPE
+    entities: Entities,
+    /// Text accumulated since the last non-text event
+    text: String,
+    /// Whether CDATA sections are reported separately from text
+    preserve_cdata: bool,
+    /// Whether tag spans are reported, and errors located
+    record_positions: bool,
+    /// Longest text allowed
+    max_text_size: usize,
+    /// Events ready to be returned
+    queue: VecDeque<Event>,
+    /// Decoder the input goes through before `buf`, if its encoding is to be detected
+    decoder: Option<Decoder>,
+}
+
+impl Driver {
+    /// Create a new driver, waiting for input.
+    pub(crate) fn new(options: &ParseOptions) -> Self {
+        Driver {
+            buf: Vec::new(),
+            pos: 0,
+            scanner: Scanner::new(options),
+            tracker: Tracker::default(),
+            construct_start: Position::default(),
+            eof: false,
+            finished: false,
+            inner: PushDriver::wrap(Lexer::new(), RawParser::new()),
+            depth: 0,
+            seen_root: false,
+            seen_doctype: false,
+            entities: Entities::new(options),
+            text: String::new(),
+            preserve_cdata: options.preserve_cdata,
+            record_positions: options.record_positions,
+            max_text_size: options.limits.max_text_size,
+            queue: VecDeque::new(),
+            decoder: options
+                .detect_encoding
+                .then(|| Decoder::new(options.limits.max_token_size)),
+        }
+    }
+
+    /// Add bytes to the input.
+    pub(crate) fn feed(&mut self, data: &[u8]) {
+        match self.decoder {
+            Some(ref mut decoder) => decoder.feed(data),
+            None => {
+                self.compact();
+                self.buf.extend_from_slice(data);
+            }
+        }
+    }
+
+    /// Drop the bytes which have been processed already.
+    fn compact(&mut self) {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+    }
+
+    /// Signal that no more input will be fed.
+    pub(crate) fn feed_eof(&mut self) {
+        self.eof = true;
+    }
+
+    /// Whether the end of the input has been signalled.
+    pub(crate) fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Feed the next chunk of a `BufRead`, or signal the end of the input if it is exhausted.
+    pub(crate) fn feed_from<R: BufRead>(&mut self, reader: &mut R) -> Result<()> {
+        let len = loop {
+            match reader.fill_buf() {
+                Ok(data) => {
+                    self.feed(data);
+                    break data.len();
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(rxml::Error::io(e).into()),
+            }
+        };
+        if len == 0 {
+            self.feed_eof();
+        } else {
+            reader.consume(len);
+        }
+        Ok(())
+    }
+
+    /// Feed the next chunk of an `AsyncBufRead`, or signal the end of the input if it is
+    /// exhausted.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn feed_from_async<R: AsyncBufRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<()> {
+        let len = future::poll_fn(|cx| {
+            Pin::new(&mut *reader).poll_fill_buf(cx).map_ok(|data| {
+                self.feed(data);
+                data.len()
+            })
+        })
+        .await
+        .map_err(rxml::Error::io)?;
+        if len == 0 {
+            self.feed_eof();
+        } else {
+            Pin::new(reader).consume(len);
+        }
+        Ok(())
+    }
+
+    /// Return the next event, or `None` if more input is required, or if the end of the input has
+    /// been reached.
+    pub(crate) fn read(&mut self) -> Result<Option<Event>> {
+        self.decode()?;
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Ok(Some(event));
+            }
+            if !self.step()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Decode the input fed so far into `buf`, if its encoding is to be detected.
+    pub(crate) fn decode(&mut self) -> Result<()> {
+        if self.decoder.is_some() {
+            self.compact();
+            if let Some(ref mut decoder) = self.decoder {
+                decoder.decode(&mut self.buf, self.eof)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// How many bytes of the input haven't been processed yet.
+    pub(crate) fn rest_len(&self) -> usize {
+        let undecoded = self.decoder.as_ref().map_or(0, Decoder::pending_len);
+        self.buf.len() - self.pos + undecoded
+    }
+
+    /// Returns the input which has been fed but not processed yet, decoded to UTF-8, and then
+    /// the bytes following it which the decoder couldn't decode yet.
+    pub(crate) fn into_rest(mut self) -> (Vec<u8>, Vec<u8>) {
+        self.compact();
+        let undecoded = self.decoder.map(Decoder::into_pending).unwrap_or_default();
+        (self.buf, undecoded)
+    }
+
+    /// Attach the position of the construct being processed to `err`, if positions are
+    /// recorded.
+    pub(crate) fn locate(&self, err: Error) -> Error {
+        if self.record_positions {
+            err.with_position(self.construct_start)
+        } else {
+            err
+        }
+    }
+
+    /// Check that the declared encoding is the one the input is in.
+    fn check_encoding(&self, name: &str) -> Result<()> {
+        let detected = match self.decoder.as_ref().and_then(Decoder::encoding) {
+            Some(detected) => detected,
+            None if name.eq_ignore_ascii_case("utf-8") => return Ok(()),
+            None => return Err(rxml::Error::RestrictedXml("only utf-8 encoding is allowed").into()),
+        };
+        match Encoding::from_name(name) {
+            Some(declared) if detected.accepts(declared) => Ok(()),
+            Some(_) => Err(syntax_error("declared encoding doesn't match the document")),
+            None => Err(Error::UnsupportedEncoding(name.to_owned())),
+        }
+    }
+
+    /// Process the next construct of the input, returning whether any progress has been made.
+    fn step(&mut self) -> Result<bool> {
+        self.construct_start = self.tracker.position();
+        if self.pos == self.buf.len() {
+            if !self.eof || self.finished {
+                return Ok(false);
+            }
+            self.flush_text()?;
+            self.finished = true;
+            self.feed_inner(self.pos, self.pos)?;
+            return Ok(true);
+        }
+
+        let input = &self.buf[self.pos..];
+        let (construct, len) =
+            match self
+                .scanner
+                .scan(input, self.eof, self.depth, self.text.len())?
+            {
+                Some(next) => next,
+                None => return Ok(false),
+            };
+        let start = self.pos;
+        let mut end = start + len;
+        let mut tracker = self.tracker.clone();
+        tracker.advance(&self.buf[start..end]);
+
+        match construct {
+            // Only whitespace is allowed outside of the root element.
+            Construct::Text if self.depth == 0 => {
+                let text = to_str(&self.buf[start..end])?;
+                if !text
+                    .bytes()
+                    .all(|b| matches!(b, b' ' | b'\t' | b'\r' | b'\n'))
+                {
+                    return Err(syntax_error("text outside of the root element"));
+                }
+                normalize_newlines(text, &mut self.text);
+            }
+            Construct::Text => {
+                decode_text(&self.buf[start..end], &mut self.text, &mut self.entities)?
+            }
+            Construct::CData if self.preserve_cdata => {
+                self.flush_text()?;
+                let content = &self.buf[start + CDATA_START.len()..end - CDATA_END.len()];
+                let mut cdata = String::with_capacity(content.len());
+                normalize_newlines(to_str(content)?, &mut cdata);
+                validate_cdata(&cdata)?;
+                self.queue.push_back(Event::CData(cdata));
+            }
+            Construct::CData => {
+                let content = &self.buf[start + CDATA_START.len()..end - CDATA_END.len()];
+                normalize_newlines(to_str(content)?, &mut self.text);
+            }
+            Construct::Comment => {
+                self.flush_text()?;
+                let content = &self.buf[start + COMMENT_START.len()..end - 3];
+                let mut comment = String::with_capacity(content.len());
+                normalize_newlines(to_str(content)?, &mut comment);
+                validate_cdata(&comment)?;
+                self.queue.push_back(Event::Comment(comment));
+            }
+            Construct::ProcessingInstruction => {
+                self.flush_text()?;
+                let content = &self.buf[start + PI_START.len()..end - PI_END.len()];
+                let (target, data) = split_processing_instruction(to_str(content)?)?;
+                self.queue
+                    .push_back(Event::ProcessingInstruction { target, data });
+            }
+            // rxml only accepts standalone UTF-8 documents, so the declaration is parsed here.
+            Construct::XmlDeclaration => {
+                if self.construct_start.offset > 0 {
+                    return Err(syntax_error("XML declaration not at start of document"));
+                }
+                let content = &self.buf[start + XML_DECL_START.len()..end - PI_END.len()];
+                let declaration = parse_xml_declaration(to_str(content)?)?;
+                if let Some(ref encoding) = declaration.encoding {
+                    self.check_encoding(encoding)?;
+                }
+                self.queue.push_back(Event::XmlDeclaration(declaration));
+            }
+            Construct::Doctype => {
+                if self.seen_root || self.seen_doctype {
+                    return Err(syntax_error("DOCTYPE not allowed here"));
+                }
+                self.seen_doctype = true;
+                self.flush_text()?;
+                let content = &self.buf[start + DOCTYPE_START.len()..end - 1];
+                let doctype = parse_doctype(to_str(content)?, &mut self.entities)?;
+                self.queue.push_back(Event::Doctype(doctype));
+            }
+            Construct::Markup => {
+                self.flush_text()?;
+                let tag = &self.buf[start..end];
+                let is_start_tag = tag.len() > 1 && !matches!(tag[1], b'/' | b'!' | b'?');
+                if self.record_positions && (is_start_tag || tag.starts_with(b"</")) {
+                    let span = Span {
+                        start: self.construct_start,
+                        end: tracker.position(),
+                    };
+                    let attributes = if is_start_tag {
+                        attribute_spans(tag, self.tracker.clone())
+                    } else {
+                        Vec::new()
+                    };
+                    self.queue.push_back(Event::TagSpan { span, attributes });
+                }
+                if is_start_tag && !self.entities.is_empty() && tag.contains(&b'&') {
+                    // rxml doesn't know about the entities, so they are expanded beforehand.
+                    let expanded = self.entities.expand_attributes(to_str(tag)?)?;
+                    self.buf.splice(start..end, expanded.bytes());
+                    end = start + expanded.len();
+                }
+                self.feed_inner(start, end)?;
+            }
+        }
+
+        if self.text.len() > self.max_text_size {
+            return Err(Error::LimitExceeded(Limit::TextSize));
+        }
+
+        self.pos = end;
+        self.scanner.reset();
+        self.tracker = tracker;
+        Ok(true)
+    }
+
+    /// Emit the text accumulated so far as a single event.
+    fn flush_text(&mut self) -> Result<()> {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            validate_cdata(&text)?;
+            self.queue.push_back(Event::Text(text));
+        }
+        Ok(())
+    }
+
+    /// Hand the input between `start` and `end` over to rxml, queueing the events it produces.
+    fn feed_inner(&mut self, start: usize, end: usize) -> Result<()> {
+        let mut data = &self.buf[start..end];
+        loop {
+            match self.inner.parse(&mut data, self.finished) {
+                Ok(Some(event)) => {
+                    match event {
+                        RawEvent::ElementHeadOpen(..) => {
+                            self.depth += 1;
+                            self.seen_root = true;
+                        }
+                        RawEvent::ElementFoot(..) => self.depth -= 1,
+                        _ => (),
+                    }
+                    self.queue.push_back(Event::Raw(event));
+                }
+                Ok(None) => return Ok(()),
+                Err(rxml::Error::IO(e)) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Splits the input into constructs, resuming the search for the end of the current one as more
+/// input comes.
+pub(crate) struct Scanner {
+    /// How far the end of the current construct has already been looked for, and the state of
+    /// the search for tags and the DOCTYPE
+    scanned: usize,
+    tag_scan: TagScan,
+    doctype_scan: DoctypeScan,
+    /// Whether a DOCTYPE is accepted
+    allow_doctype: bool,
+    /// Whether CDATA sections are kept apart from the text
+    preserve_cdata: bool,
+    /// Longest text allowed, checked before the text is complete
+    max_text_size: usize,
+    /// Longest markup allowed, checked before its end is found
+    max_token_size: usize,
+    /// Longest DOCTYPE allowed, checked before its end is found
+    max_doctype_size: usize,
+}
+
+impl Scanner {
+    pub(crate) fn new(options: &ParseOptions) -> Self {
+        Scanner {
+            scanned: 0,
+            tag_scan: TagScan::default(),
+            doctype_scan: DoctypeScan::default(),
+            allow_doctype: options.allow_doctype,
+            preserve_cdata: options.preserve_cdata,
+            max_text_size: options.limits.max_text_size,
+            max_token_size: options.limits.max_token_size,
+            max_doctype_size: options.limits.max_doctype_size,
+        }
+    }
+
+    /// Forget about the construct which has been found, to look for the next one.
+    pub(crate) fn reset(&mut self) {
+        self.scanned = 0;
+        self.tag_scan = TagScan::default();
+        self.doctype_scan = DoctypeScan::default();
+    }
+
+    /// Find the kind and length of the construct at the start of `input`, or `None` if more
+    /// input is required to find its end, given the number of open elements and the length of
+    /// the text preceding it.
+    pub(crate) fn scan(
+        &mut self,
+        input: &[u8],
+        eof: bool,
+        depth: usize,
+        text: usize,
+    ) -> Result<Option<(Construct, usize)>> {
+        if input[0] != b'<' {
+            return Ok(match find(input, b"<", self.scanned) {
+                Some(len) => Some((Construct::Text, len)),
+                None if eof => Some((Construct::Text, input.len())),
+                None => {
+                    // Text is processed as it comes, so that it isn't buffered until its end.
+                    let len = complete_text_len(input);
+                    if input.len() - len > self.max_token_size {
+                        return Err(Error::LimitExceeded(Limit::TokenSize));
+                    }
+                    if len > 0 {
+                        return Ok(Some((Construct::Text, len)));
+                    }
+                    self.scanned = input.len();
+                    None
+                }
+            });
+        }
+
+        // Make sure there is enough input to tell the kind of markup apart.
+        let longest = CDATA_START.len().max(DOCTYPE_START.len());
+        if input.len() < longest
+            && !eof
+            && (COMMENT_START.starts_with(input)
+                || CDATA_START.starts_with(input)
+                || DOCTYPE_START.starts_with(input))
+        {
+            return Ok(None);
+        }
+
+        let (construct, found) = if input.starts_with(COMMENT_START) {
+            let from = self.scanned.max(COMMENT_START.len());
+            // The first "--" must be the start of the delimiter.
+            let found = match find(input, COMMENT_END, from) {
+                Some(i) if i + 2 < input.len() && input[i + 2] != b'>' => {
+                    return Err(syntax_error("'--' in comment"));
+                }
+                Some(i) if i + 2 < input.len() => Some(i + 3),
+                Some(i) => {
+                    self.scanned = i;
+                    None
+                }
+                None => {
+                    self.scanned = input.len().saturating_sub(1);
+                    None
+                }
+            };
+            (Construct::Comment, found.ok_or("in comment"))
+        } else if depth > 0 && input.starts_with(CDATA_START) {
+            let from = self.scanned.max(CDATA_START.len());
+            let found = find(input, CDATA_END, from).map(|i| i + CDATA_END.len());
+            if found.is_none() {
+                self.scanned = input.len().saturating_sub(CDATA_END.len() - 1);
+            }
+            (Construct::CData, found.ok_or("in CDATA section"))
+        } else if depth == 0 && self.allow_doctype && input.starts_with(DOCTYPE_START) {
+            let found = self.doctype_scan.find_end(input).map(|i| i + 1);
+            if found.unwrap_or(input.len()) > self.max_doctype_size {
+                return Err(Error::LimitExceeded(Limit::DoctypeSize));
+            }
+            (Construct::Doctype, found.ok_or("in DOCTYPE"))
+        } else if input.starts_with(PI_START) {
+            let from = self.scanned.max(PI_START.len());
+            match find(input, PI_END, from).map(|i| i + PI_END.len()) {
+                // The XML declaration looks like a processing instruction, but is not one.
+                Some(len) if is_xml_decl(&input[..len]) => (Construct::XmlDeclaration, Ok(len)),
+                Some(len) => (Construct::ProcessingInstruction, Ok(len)),
+                None => {
+                    self.scanned = input.len().saturating_sub(PI_END.len() - 1);
+                    (
+                        Construct::ProcessingInstruction,
+                        Err("in processing instruction"),
+                    )
+                }
+            }
+        } else {
+            (
+                Construct::Markup,
+                self.tag_scan.find_end(input).map(|i| i + 1).ok_or(""),
+            )
+        };
+
+        // Check the limits before the end has been found, for the input not to grow unbounded.
+        let size = match found {
+            Ok(len) => len,
+            Err(_) => input.len(),
+        };
+        match construct {
+            // CDATA sections are text, bounded like the rest of it.
+            Construct::CData => {
+                let content = match found {
+                    Ok(len) => len - CDATA_START.len() - CDATA_END.len(),
+                    Err(_) => input.len() - CDATA_START.len(),
+                };
+                let text = if self.preserve_cdata { 0 } else { text };
+                if text + content > self.max_text_size {
+                    return Err(Error::LimitExceeded(Limit::TextSize));
+                }
+            }
+            Construct::Doctype => (),
+            _ if size > self.max_token_size => return Err(Error::LimitExceeded(Limit::TokenSize)),
+            _ => (),
+        }
+
+        match (construct, found) {
+            (construct, Ok(len)) => Ok(Some((construct, len))),
+            (_, Err(_)) if !eof => Ok(None),
+            // Let rxml report what is wrong with the truncated markup.
+            (Construct::Markup, Err(_)) => Ok(Some((Construct::Markup, input.len()))),
+            (_, Err(ctx)) => Err(rxml::Error::Xml(XmlError::InvalidEof(ctx)).into()),
+        }
+    }
+}
+
+pub(crate) fn syntax_error(msg: &'static str) -> Error {
+    rxml::Error::Xml(XmlError::InvalidSyntax(msg)).into()
+}
+
+fn to_str(bytes: &[u8]) -> Result<&str> {
+    str::from_utf8(bytes).map_err(|e| rxml::Error::InvalidUtf8Byte(bytes[e.valid_up_to()]).into())
+}
+
+fn validate_cdata(s: &str) -> Result<()> {
+    rxml::strings::validate_cdata(s).map_err(|e| rxml::Error::Xml(e).into())
+}
+
+fn is_xml_decl(markup: &[u8]) -> bool {
+    markup.starts_with(XML_DECL_START)
+        && matches!(
+            markup.get(XML_DECL_START.len()),
+            Some(b' ' | b'\t' | b'\r' | b'\n' | b'?')
+        )
+}
+
+/// Parse the pseudo-attributes of the XML declaration, found between `<?xml` and `?>`.
+pub(crate) fn parse_xml_declaration(content: &str) -> Result<XmlDeclaration> {
+    let is_space = |c: char| matches!(c, ' ' | '\t' | '\r' | '\n');
+    let mut attributes = Vec::new();
+    let mut rest = content;
+    loop {
+        let trimmed = rest.trim_start_matches(is_space);
+        if trimmed.is_empty() {
+            break;
+        }
+        if trimmed.len() == rest.len() {
+            return Err(syntax_error("missing whitespace in XML declaration"));
+        }
+        let name_len = trimmed
+            .find(|c: char| c == '=' || is_space(c))
+            .unwrap_or(trimmed.len());
+        let (name, after) = trimmed.split_at(name_len);
+        let value = after
+            .trim_start_matches(is_space)
+            .strip_prefix('=')
+            .map(|value| value.trim_start_matches(is_space))
+            .ok_or_else(|| syntax_error("missing '=' in XML declaration"))?;
+        let quote = match value.chars().next() {
+            Some(quote @ ('\'' | '"')) => quote,
+            _ => return Err(syntax_error("unquoted value in XML declaration")),
+        };
+        let value = &value[1..];
+        let len = value
+            .find(quote)
+            .ok_or_else(|| syntax_error("unterminated value in XML declaration"))?;
+        attributes.push((name, &value[..len]));
+        rest = &value[len + 1..];
+    }
+
+    let mut attributes = attributes.into_iter().peekable();
+    let version = match attributes.next() {
+        Some(("version", version)) if is_version_number(version) => version.to_owned(),
+        Some(("version", _)) => return Err(syntax_error("invalid XML version")),
+        _ => {
+            return Err(syntax_error(
+                "'<?xml' must be followed by version attribute",
+            ))
+        }
+    };
+    let encoding = match attributes.next_if(|(name, _)| *name == "encoding") {
+        Some((_, encoding)) if is_encoding_name(encoding) => Some(encoding.to_owned()),
+        Some(_) => return Err(syntax_error("invalid encoding name")),
+        None => None,
+    };
+    let standalone = match attributes.next_if(|(name, _)| *name == "standalone") {
+        Some((_, "yes")) => Some(true),
+        Some((_, "no")) => Some(false),
+        Some(_) => return Err(syntax_error("standalone must be 'yes' or 'no'")),
+        None => None,
+    };
+    if attributes.next().is_some() {
+        return Err(syntax_error("unexpected attribute in XML declaration"));
+    }
+
+    Ok(XmlDeclaration {
+        version,
+        encoding,
+        standalone,
+    })
+}
+
+/// Whether `name` is a valid `EncName`.
+pub(crate) fn is_encoding_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Whether `version` is a valid `VersionNum`, which any 1.x version is.
+pub(crate) fn is_version_number(version: &str) -> bool {
+    version.strip_prefix("1.").map_or(false, |minor| {
+        !minor.is_empty() && minor.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+/// Split the content of a processing instruction into its target and data.
+fn split_processing_instruction(content: &str) -> Result<(String, String)> {
+    let is_space = |c: char| matches!(c, ' ' | '\t' | '\r' | '\n');
+    let (target, data) = match content.find(is_space) {
+        Some(i) => (&content[..i], content[i..].trim_start_matches(is_space)),
+        None => (content, ""),
+    };
+    rxml::strings::validate_ncname(target).map_err(rxml::Error::Xml)?;
+    if target.eq_ignore_ascii_case("xml") {
+        return Err(syntax_error("reserved processing instruction target"));
+    }
+    let mut normalized = String::with_capacity(data.len());
+    normalize_newlines(data, &mut normalized);
+    validate_cdata(&normalized)?;
+    Ok((target.to_owned(), normalized))
+}
+
+/// Find `needle` in `haystack`, starting at `from`.
+pub(crate) fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if haystack.len() < needle.len() {
+        return None;
+    }
+    (from..=haystack.len() - needle.len()).find(|&i| haystack[i..].starts_with(needle))
+}
+
+/// How far the end of a tag has been looked for, and the quote of the attribute value the search
+/// stopped in, to resume it once more input is available.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TagScan {
+    pos: usize,
+    quote: Option<u8>,
+}
+
+impl TagScan {
+    /// Find the closing `>` of the tag at the start of `input`, skipping over attribute values,
+    /// from where the previous search on the same tag stopped.
+    pub(crate) fn find_end(&mut self, input: &[u8]) -> Option<usize> {
+        for (i, &b) in input.iter().enumerate().skip(self.pos) {
+            match (self.quote, b) {
+                (Some(q), b) if q == b => self.quote = None,
+                (Some(_), _) => (),
+                (None, b'"' | b'\'') => self.quote = Some(b),
+                (None, b'>') => return Some(i),
+                (None, _) => (),
+            }
+        }
+        self.pos = input.len();
+        None
+    }
+}
+
+/// Find the closing `>` of the tag at the start of `input`, skipping over attribute values.
+pub(crate) fn find_tag_end(input: &[u8]) -> Option<usize> {
+    TagScan::default().find_end(input)
+}
+
+/// Find the attributes of the start tag `tag`, which starts at `tracker`.
+fn attribute_spans(tag: &[u8], mut tracker: Tracker) -> Vec<Span> {
+    let is_space = |b: &u8| matches!(b, b' ' | b'\t' | b'\r' | b'\n');
+    let mut spans = Vec::new();
+    let mut i = match tag
+        .iter()
+        .position(|b| is_space(b) || matches!(b, b'/' | b'>'))
+    {
+        Some(i) => i,
+        None => return spans,
+    };
+    let mut done = 0;
+    loop {
+        i += tag[i..].iter().take_while(|b| is_space(b)).count();
+        if i >= tag.len() || matches!(tag[i], b'/' | b'>') {
+            return spans;
+        }
+        let end = tag[i..]
+            .iter()
+            .position(|&b| b == b'\'' || b == b'"')
+            .and_then(|quote| {
+                let quote_at = i + quote;
+                let len = tag[quote_at + 1..]
+                    .iter()
+                    .position(|&b| b == tag[quote_at])?;
+                Some(quote_at + len + 2)
+            });
+        let end = match end {
+            Some(end) => end,
+            None => return spans,
+        };
+        tracker.advance(&tag[done..i]);
+        let start = tracker.position();
+        tracker.advance(&tag[i..end]);
+        spans.push(Span {
+            start,
+            end: tracker.position(),
+        });
+        done = end;
+        i = end;
+    }
+}
+
+/// How much of the text at the start of `input` can be processed before the input following it
+/// is known: a reference, a line ending, `]]>` or a character may go on after its end.
+fn complete_text_len(input: &[u8]) -> usize {
+    let mut len = input.len();
+    if let Some(amp) = input.iter().rposition(|&b| b == b'&') {
+        if !input[amp..].contains(&b';') {
+            len = amp;
+        }
+    }
+    if let Err(e) = str::from_utf8(&input[..len]) {
+        if e.error_len().is_none() {
+            len = e.valid_up_to();
+        }
+    }
+    while len > 0 && matches!(input[len - 1], b']' | b'\r') {
+        len -= 1;
+    }
+    len
+}
+
+/// Append `text` to `out`, turning every line ending into a single `\n`.
+pub(crate) fn normalize_newlines(text: &str, out: &mut String) {
+    let mut rest = text;
+    while let Some(i) = rest.find('\r') {
+        out.push_str(&rest[..i]);
+        out.push('\n');
+        rest = rest[i + 1..].strip_prefix('\n').unwrap_or(&rest[i + 1..]);
+    }
+    out.push_str(rest);
+}
+
+/// Append the character data `raw` to `out`, expanding references and normalizing line endings.
+fn decode_text(raw: &[u8], out: &mut String, entities: &mut Entities) -> Result<()> {
+    let mut rest = to_str(raw)?;
+    while let Some(i) = rest.find(|c: char| c == '&' || c == '>') {
+        if rest.as_bytes()[i] == b'>' {
+            if rest[..i].ends_with("]]") {
+                return Err(syntax_error("']]>' in text"));
+            }
+            normalize_newlines(&rest[..=i], out);
+            rest = &rest[i + 1..];
+            continue;
+        }
+        normalize_newlines(&rest[..i], out);
+        let len = rest[i..]
+            .find(';')
+            .ok_or(rxml::Error::Xml(XmlError::UndeclaredEntity))?;
+        entities.resolve(&rest[i + 1..i + len], out)?;
+        rest = &rest[i + len + 1..];
+    }
+    normalize_newlines(rest, out);
+    Ok(())
+}
+
+/// Resolve the predefined entity or character reference `name`, given without its delimiters.
+pub(crate) fn resolve_reference(name: &str) -> Result<char> {
+    let codepoint = match name {
+        "lt" => return Ok('<'),
+        "gt" => return Ok('>'),
+        "amp" => return Ok('&'),
+        "apos" => return Ok('\''),
+        "quot" => return Ok('"'),
+        _ => match (name.strip_prefix("#x"), name.strip_prefix('#')) {
+            (Some(hex), _) if !hex.is_empty() && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                u32::from_str_radix(hex, 16).ok()
+            }
+            (None, Some(dec)) if !dec.is_empty() && dec.bytes().all(|b| b.is_ascii_digit()) => {
+                dec.parse().ok()
+            }
+            _ => None,
+        },
+    };
+    let codepoint = codepoint.ok_or(rxml::Error::Xml(XmlError::UndeclaredEntity))?;
+    char::from_u32(codepoint).ok_or_else(|| {
+        rxml::Error::Xml(XmlError::InvalidChar("in text node", codepoint, true)).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(chunks: &[&[u8]]) -> Result<Vec<String>> {
+        events_with_options(chunks, &ParseOptions::default())
+    }
+
+    fn events_with_options(chunks: &[&[u8]], options: &ParseOptions) -> Result<Vec<String>> {
+        Ok(read_all(chunks, options)?
+            .into_iter()
+            .filter(|event| !matches!(event, Event::TagSpan { .. }))
+            .map(describe)
+            .collect())
+    }
+
+    fn read_all(chunks: &[&[u8]], options: &ParseOptions) -> Result<Vec<Event>> {
+        let mut driver = Driver::new(options);
+        let mut events = Vec::new();
+        for chunk in chunks {
+            driver.feed(chunk);
+            while let Some(event) = driver.read()? {
+                events.push(event);
+            }
+        }
+        driver.feed_eof();
+        while let Some(event) = driver.read()? {
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    fn describe(event: Event) -> String {
+        match event {
+            Event::Raw(RawEvent::ElementHeadOpen(_, (_, name))) => format!("<{}", name),
+            Event::Raw(RawEvent::ElementFoot(_)) => String::from("/>"),
+            Event::Raw(_) => String::from("raw"),
+            Event::Doctype(doctype) => format!("doctype {}", doctype.name),
+            Event::TagSpan { span, .. } => {
+                format!("span {}..{}", span.start.offset, span.end.offset)
+            }
+            Event::XmlDeclaration(decl) => format!(
+                "decl {} {:?} {:?}",
+                decl.version, decl.encoding, decl.standalone
+            ),
+            Event::Text(text) => format!("text {}", text),
+            Event::CData(cdata) => format!("cdata {}", cdata),
+            Event::Comment(comment) => format!("comment {}", comment),
+            Event::ProcessingInstruction { target, data } => format!("pi {} {}", target, data),
+        }
+    }
+
+    #[test]
+    fn split_across_chunks() {
+        let xml: &[u8] =
+            b"<?xml version='1.0'?><?pi?><a><!-- c1 -->x &amp; y<![CDATA[<z>]]>\r\n<?t a?b?><b/></a>";
+        let expected = events(&[xml]).unwrap();
+        assert_eq!(
+            expected,
+            [
+                "decl 1.0 None None",
+                "pi pi ",
+                "<a",
+                "raw",
+                "comment  c1 ",
+                "text x & y<z>\n",
+                "pi t a?b",
+                "<b",
+                "raw",
+                "/>",
+                "/>"
+            ]
+        );
+        for i in 1..xml.len() {
+            assert_eq!(events(&[&xml[..i], &xml[i..]]).unwrap(), expected);
+        }
+        let bytewise: Vec<&[u8]> = xml.chunks(1).collect();
+        assert_eq!(events(&bytewise).unwrap(), expected);
+    }
+
+    #[test]
+    fn tags_across_chunks() {
+        let xml: &[u8] = b"<a b='>' c=\"'>\"><d e=\"\"/></a>";
+        let expected = events(&[xml]).unwrap();
+        assert_eq!(
+            expected,
+            ["<a", "raw", "raw", "raw", "<d", "raw", "raw", "/>", "/>"]
+        );
+        let bytewise: Vec<&[u8]> = xml.chunks(1).collect();
+        assert_eq!(events(&bytewise).unwrap(), expected);
+    }
+
+    #[test]
+    fn preserved_cdata() {
+        let options = ParseOptions::new().preserve_cdata(true);
+        let xml: &[u8] = b"<a>x<![CDATA[]]><![CDATA[<&>]]>y</a>";
+        assert_eq!(
+            events_with_options(&[xml], &options).unwrap(),
+            ["<a", "raw", "text x", "cdata ", "cdata <&>", "text y", "/>"]
+        );
+    }
+
+    #[test]
+    fn xml_declarations() {
+        assert_eq!(
+            events(&[b"<?xml version=\"1.0\" encoding = 'UTF-8'  standalone='no' ?>\r\n<a/>"])
+                .unwrap(),
+            [
+                "decl 1.0 Some(\"UTF-8\") Some(false)",
+                "text \n",
+                "<a",
+                "raw",
+                "/>"
+            ]
+        );
+        assert!(events(&[b"<?xml version='1.0' ?><a/>"]).is_ok());
+        assert!(events(&[b"<?xml version='1.1'?><a/>"]).is_ok());
+        assert!(events(&[b"<?xml?><a/>"]).is_err());
+        assert!(events(&[b"<?xml version='2.0'?><a/>"]).is_err());
+        assert!(events(&[b"<?xml version='1.0'standalone='yes'?><a/>"]).is_err());
+        assert!(events(&[b"<?xml version='1.0' standalone='yes' encoding='utf-8'?><a/>"]).is_err());
+        assert!(events(&[b"<?xml version='1.0' standalone='maybe'?><a/>"]).is_err());
+        assert!(events(&[b"<?xml version='1.0' encoding='latin1'?><a/>"]).is_err());
+        assert!(events(&[b" <?xml version='1.0'?><a/>"]).is_err());
+    }
+
+    #[test]
+    fn doctype_across_chunks() {
+        let options = ParseOptions::new().allow_doctype(true);
+        let xml: &[u8] =
+            b"<!DOCTYPE a [<!-- ]> --><?p ]>?><!ENTITY e \"]>&#62;\">]><a b='&e;'>&e;</a>";
+        let expected = ["doctype a", "<a", "raw", "raw", "text ]>>", "/>"];
+        for i in 1..xml.len() {
+            assert_eq!(
+                events_with_options(&[&xml[..i], &xml[i..]], &options).unwrap(),
+                expected
+            );
+        }
+        let bytewise: Vec<&[u8]> = xml.chunks(1).collect();
+        assert_eq!(events_with_options(&bytewise, &options).unwrap(), expected);
+    }
+
+    #[test]
+    fn comment_line_endings() {
+        assert_eq!(
+            events(&[b"<a><!--x\r\ny\rz\n--></a>"]).unwrap(),
+            ["<a", "raw", "comment x\ny\nz\n", "/>"]
+        );
+    }
+
+    #[test]
+    fn invalid_comments() {
+        assert!(events(&[b"<a><!-- a -- b --></a>"]).is_err());
+        assert!(events(&[b"<a><!-- a ---></a>"]).is_err());
+        assert!(events(&[b"<a><!-- a"]).is_err());
+    }
+
+    #[test]
+    fn invalid_processing_instructions() {
+        assert!(events(&[b"<a><?xml version='1.0'?></a>"]).is_err());
+        assert!(events(&[b"<a><?XmL foo?></a>"]).is_err());
+        assert!(events(&[b"<a><?a:b foo?></a>"]).is_err());
+        assert!(events(&[b"<a><?1 foo?></a>"]).is_err());
+        assert!(events(&[b"<a><? foo?></a>"]).is_err());
+        assert!(events(&[b"<a><?foo"]).is_err());
+    }
+
+    #[test]
+    fn invalid_text() {
+        assert!(events(&[b"<a>]]></a>"]).is_err());
+        assert!(events(&[b"<a>&foo;</a>"]).is_err());
+        assert!(events(&[b"<a>&#0;</a>"]).is_err());
+        assert!(events(&[b"<a>\x01</a>"]).is_err());
+        assert!(events(&[b"<a>\xff</a>"]).is_err());
+        assert!(events(&[b"x<a/>"]).is_err());
+    }
+}
@@ -12,36 +12,100 @@
 
 //! Provides an `Element` type, which represents DOM nodes, and a builder to create them with.
 
+use crate::attributes::{split_attr_name, Attribute, Attributes, XML_NS};
+use crate::canonical::{self, Canonicalization};
 use crate::convert::IntoAttributeValue;
+use crate::cursor::{Cursor, CursorMut};
+use crate::driver::Driver;
 use crate::error::{Error, Result};
+use crate::namespace_tracker::ScopedNamespaces;
 use crate::namespaces::NSChoice;
 use crate::node::Node;
+use crate::position::{ElementSpans, Span};
 use crate::prefixes::{Namespace, Prefix, Prefixes};
-use crate::tree_builder::TreeBuilder;
+use crate::tree_builder::{ParseOptions, TreeBuilder, WhitespaceMode};
+use crate::writer::WriteOptions;
 
-use std::collections::{btree_map, BTreeMap};
 use std::io::{BufRead, Write};
 use std::sync::Arc;
 
 use std::borrow::Cow;
 use std::str;
 
+use rxml::error::XmlError;
 use rxml::writer::{Encoder, Item, TrackNamespace};
-use rxml::{EventRead, Lexer, PullDriver, RawParser, XmlVersion};
+use rxml::XmlVersion;
 
 use std::str::FromStr;
 
 use std::slice;
 
+#[cfg(feature = "tokio")]
+use std::{future, io, pin::Pin};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncBufRead, AsyncWrite};
+
+/// How many bytes are buffered before being written to an `AsyncWrite`.
+#[cfg(feature = "tokio")]
+const ASYNC_CHUNK_SIZE: usize = 8192;
+
 fn encode_and_write<W: Write, T: rxml::writer::TrackNamespace>(
     item: Item<'_>,
     enc: &mut Encoder<T>,
     mut w: W,
-) -> rxml::Result<()> {
+) -> Result<()> {
     let mut buf = rxml::bytes::BytesMut::new();
-    enc.encode_into_bytes(item, &mut buf)
-        .expect("encoder driven incorrectly");
-    w.write_all(&buf[..])?;
+    enc.encode_into_bytes(item, &mut buf)?;
+    w.write_all(&buf[..]).map_err(rxml::Error::io)?;
+    Ok(())
+}
+
+/// Split `text` into pieces of about `ASYNC_CHUNK_SIZE` bytes, never right before a `>` so that
+/// a `]]>` stays in one piece.
+#[cfg(feature = "tokio")]
+fn async_pieces(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let len = (ASYNC_CHUNK_SIZE.min(rest.len())..rest.len())
+            .find(|&i| rest.is_char_boundary(i) && rest.as_bytes()[i] != b'>')
+            .unwrap_or(rest.len());
+        let (piece, tail) = rest.split_at(len);
+        rest = tail;
+        Some(piece)
+    })
+}
+
+/// Send what `items` holds to `writer` once it is at least a chunk.
+#[cfg(feature = "tokio")]
+async fn send_chunk<A: AsyncWrite + Unpin>(
+    items: &mut ItemWriter<Vec<u8>>,
+    writer: &mut A,
+) -> Result<()> {
+    if items.get_ref().len() >= ASYNC_CHUNK_SIZE {
+        write_all_async(writer, items.get_ref()).await?;
+        items.get_mut().clear();
+    }
+    Ok(())
+}
+
+/// Write the whole of `buf` to an `AsyncWrite`.
+#[cfg(feature = "tokio")]
+pub(crate) async fn write_all_async<A: AsyncWrite + Unpin>(
+    writer: &mut A,
+    mut buf: &[u8],
+) -> Result<()> {
+    while !buf.is_empty() {
+        let len = future::poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, buf))
+            .await
+            .map_err(rxml::Error::io)?;
+        if len == 0 {
+            return Err(rxml::Error::io(io::Error::from(io::ErrorKind::WriteZero)).into());
+        }
+        buf = &buf[len..];
+    }
     Ok(())
 }
 
@@ -50,26 +114,253 @@ fn encode_and_write<W: Write, T: rxml::writer::TrackNamespace>(
 pub struct CustomItemWriter<W, T> {
     writer: W,
     encoder: Encoder<T>,
+    options: WriteOptions,
+    /// How many elements are open
+    depth: usize,
+    /// Whether the content being written may be indented, which it can't be once in mixed
+    /// content
+    pub(crate) pretty: bool,
+    /// Whether the content being written is within `xml:space="preserve"`
+    pub(crate) space_preserved: bool,
+    /// The start tag being written and its depth, kept until it is complete to lay out its
+    /// attributes
+    head: Option<(usize, Vec<u8>)>,
 }
 
-impl<W: Write> CustomItemWriter<W, rxml::writer::SimpleNamespaces> {
+impl<W: Write> CustomItemWriter<W, ScopedNamespaces> {
     pub(crate) fn new(writer: W) -> Self {
+        Self::with_options(writer, WriteOptions::default())
+    }
+
+    pub(crate) fn with_options(writer: W, options: WriteOptions) -> Self {
         Self {
             writer,
-            encoder: Encoder::new(),
+            encoder: Encoder::from(ScopedNamespaces::new()),
+            pretty: options.indent.is_some(),
+            options,
+            depth: 0,
+            space_preserved: false,
+            head: None,
         }
     }
 }
 
 impl<W: Write, T: rxml::writer::TrackNamespace> CustomItemWriter<W, T> {
-    pub(crate) fn write(&mut self, item: Item<'_>) -> rxml::Result<()> {
-        encode_and_write(item, &mut self.encoder, &mut self.writer)
+    pub(crate) fn write(&mut self, item: Item<'_>) -> Result<()> {
+        if self.options.indent.is_none() {
+            return encode_and_write(item, &mut self.encoder, &mut self.writer);
+        }
+        // Start tags are buffered until they are complete, to know how long they are.
+        match item {
+            Item::ElementHeadStart(..) => {
+                let mut head = Vec::new();
+                encode_and_write(item, &mut self.encoder, &mut head)?;
+                self.head = Some((self.depth, head));
+                self.depth += 1;
+                Ok(())
+            }
+            Item::Attribute(..) => match self.head {
+                Some((_, ref mut head)) => encode_and_write(item, &mut self.encoder, head),
+                None => encode_and_write(item, &mut self.encoder, &mut self.writer),
+            },
+            Item::ElementHeadEnd | Item::ElementFoot => {
+                if let Item::ElementFoot = item {
+                    self.depth -= 1;
+                }
+                match self.head.take() {
+                    Some((depth, mut head)) => {
+                        encode_and_write(item, &mut self.encoder, &mut head)?;
+                        self.write_head(depth, &head)
+                    }
+                    None => encode_and_write(item, &mut self.encoder, &mut self.writer),
+                }
+            }
+            _ => encode_and_write(item, &mut self.encoder, &mut self.writer),
+        }
+    }
+
+    /// Write a complete start tag found at `depth`, putting its attributes on their own lines
+    /// if it is too long or if the options ask for it.
+    fn write_head(&mut self, depth: usize, head: &[u8]) -> Result<()> {
+        let indent = self.options.indent.as_deref().unwrap_or("");
+        let (name, attributes, end) = match split_head(head) {
+            Some(parts) => parts,
+            None => return self.write_raw(head),
+        };
+        let width = depth * indent.chars().count()
+            + head.iter().filter(|&&byte| byte & 0xc0 != 0x80).count();
+        let per_line = (self.options.attributes_per_line && attributes.len() > 1)
+            || (!attributes.is_empty() && width > self.options.line_width);
+        if !per_line {
+            return self.write_raw(head);
+        }
+        let mut lines = name.to_vec();
+        for attribute in attributes {
+            lines.push(b'\n');
+            for _ in 0..=depth {
+                lines.extend_from_slice(indent.as_bytes());
+            }
+            lines.extend_from_slice(attribute);
+        }
+        lines.extend_from_slice(end);
+        self.write_raw(&lines)
+    }
+
+    /// Start a new line indented for `depth`, when pretty printing.
+    pub(crate) fn write_indent(&mut self, depth: usize) -> Result<()> {
+        let indent = match self.options.indent {
+            Some(ref indent) => indent,
+            None => return Ok(()),
+        };
+        self.writer.write_all(b"\n").map_err(rxml::Error::io)?;
+        for _ in 0..depth {
+            self.writer
+                .write_all(indent.as_bytes())
+                .map_err(rxml::Error::io)?;
+        }
+        Ok(())
+    }
+
+    /// How many elements are open.
+    pub(crate) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.writer.flush().map_err(|e| rxml::Error::io(e).into())
+    }
+
+    pub(crate) fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write a comment, which rxml's encoder has no item for.
+    pub(crate) fn write_comment(&mut self, comment: &str) -> Result<()> {
+        if comment.contains("--") || comment.ends_with('-') {
+            return Err(rxml::Error::Xml(XmlError::InvalidSyntax("'--' in comment")).into());
+        }
+        rxml::strings::validate_cdata(comment).map_err(rxml::Error::Xml)?;
+        self.write_raw(b"<!--")?;
+        self.write_raw(comment.as_bytes())?;
+        self.write_raw(b"-->")
+    }
+
+    /// Write a CDATA section, splitting it wherever its content contains `]]>`.
+    pub(crate) fn write_cdata(&mut self, cdata: &str) -> Result<()> {
+        rxml::strings::validate_cdata(cdata).map_err(rxml::Error::Xml)?;
+        self.write_raw(b"<![CDATA[")?;
+        self.write_cdata_content(cdata)?;
+        self.write_raw(b"]]>")
+    }
+
+    /// Write part of the content of a CDATA section, splitting the section wherever it contains
+    /// `]]>`.
+    fn write_cdata_content(&mut self, cdata: &str) -> Result<()> {
+        let mut parts = cdata.split("]]>");
+        if let Some(first) = parts.next() {
+            self.write_raw(first.as_bytes())?;
+        }
+        for part in parts {
+            self.write_raw(b"]]]]><![CDATA[>")?;
+            self.write_raw(part.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Write a processing instruction, which rxml's encoder has no item for either.
+    pub(crate) fn write_processing_instruction(&mut self, target: &str, data: &str) -> Result<()> {
+        rxml::strings::validate_ncname(target).map_err(rxml::Error::Xml)?;
+        if target.eq_ignore_ascii_case("xml") {
+            return Err(rxml::Error::Xml(XmlError::InvalidSyntax(
+                "reserved processing instruction target",
+            ))
+            .into());
+        }
+        if data.contains("?>") {
+            return Err(rxml::Error::Xml(XmlError::InvalidSyntax(
+                "'?>' in processing instruction",
+            ))
+            .into());
+        }
+        rxml::strings::validate_cdata(data).map_err(rxml::Error::Xml)?;
+        self.write_raw(b"<?")?;
+        self.write_raw(target.as_bytes())?;
+        if !data.is_empty() {
+            self.write_raw(b" ")?;
+            self.write_raw(data.as_bytes())?;
+        }
+        self.write_raw(b"?>")
+    }
+
+    pub(crate) fn write_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer
+            .write_all(bytes)
+            .map_err(|e| rxml::Error::io(e).into())
     }
 }
 
 /// Type alias to simplify the use for the default namespace tracking
 /// implementation.
-pub type ItemWriter<W> = CustomItemWriter<W, rxml::writer::SimpleNamespaces>;
+pub type ItemWriter<W> = CustomItemWriter<W, ScopedNamespaces>;
+
+/// The name of a start tag, its attributes and namespace declarations, and its end.
+type HeadParts<'a> = (&'a [u8], Vec<&'a [u8]>, &'a [u8]);
+
+/// How the children of an element being written are laid out, along with the layout state of
+/// its parent to restore once it is written.
+pub(crate) struct ContentLayout {
+    pretty: bool,
+    space_preserved: bool,
+    /// Whether each child is put on its own line
+    indent: bool,
+}
+
+impl ContentLayout {
+    /// Get ready to write `child`, returning false if it must be skipped, as whitespace between
+    /// indented children is.
+    pub(crate) fn start_child<W: Write>(
+        &self,
+        writer: &mut ItemWriter<W>,
+        child: &Node,
+    ) -> Result<bool> {
+        if !self.indent {
+            return Ok(true);
+        }
+        if let Node::Text(_) = *child {
+            return Ok(false);
+        }
+        writer.write_indent(writer.depth())?;
+        Ok(true)
+    }
+}
+
+/// Split a start tag written by the encoder into its parts, or return `None` if it isn't laid
+/// out as expected.
+fn split_head(head: &[u8]) -> Option<HeadParts> {
+    let mut pos = head
+        .iter()
+        .position(|&byte| byte == b' ' || byte == b'>' || byte == b'/')?;
+    let name = &head[..pos];
+    let mut attributes = Vec::new();
+    while head.get(pos) == Some(&b' ') {
+        let start = pos + 1;
+        let equals = start + head[start..].iter().position(|&byte| byte == b'=')?;
+        let quote = *head.get(equals + 1)?;
+        let value = equals + 2;
+        pos = value + head.get(value..)?.iter().position(|&byte| byte == quote)? + 1;
+        attributes.push(&head[start..pos]);
+    }
+    Some((name, attributes, &head[pos..]))
+}
 
 /// helper function to escape a `&[u8]` and replace all
 /// xml special characters (<, >, &, ', ") with their corresponding
@@ -116,20 +407,41 @@ pub fn escape(raw: &[u8]) -> Cow<[u8]> {
 
 #[derive(Clone, Eq, Debug)]
 /// A struct representing a DOM Element.
+///
+/// Names and namespaces are reference-counted, and shared between all the elements of a parsed
+/// tree, which makes cloning elements cheaper.
 pub struct Element {
-    name: String,
-    namespace: String,
+    name: Arc<str>,
+    namespace: Arc<str>,
+    /// The prefix this element has been parsed with, if any
+    pub(crate) prefix: Option<Arc<str>>,
     /// Namespace declarations
     pub prefixes: Prefixes,
-    attributes: BTreeMap<String, String>,
-    children: Vec<Node>,
+    pub(crate) attributes: Attributes,
+    pub(crate) children: Vec<Node>,
+    /// Where this element has been found, if it has been parsed
+    pub(crate) spans: Option<Box<ElementSpans>>,
 }
 
+/// Deprecated: use `Element::to_xml_string`, which tells why an element can't be serialized.
+///
+/// Returns an empty string if the element can't be serialized, for instance because one of its
+/// names isn't valid.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::Element;
+///
+/// let elem = Element::bare("message", "jabber:client");
+/// assert_eq!(String::from(&elem), "<message xmlns='jabber:client'/>");
+///
+/// let elem = Element::bare("not a name", "jabber:client");
+/// assert_eq!(String::from(&elem), "");
+/// ```
 impl<'a> From<&'a Element> for String {
     fn from(elem: &'a Element) -> String {
-        let mut writer = Vec::new();
-        elem.write_to(&mut writer).unwrap();
-        String::from_utf8(writer).unwrap()
+        elem.to_xml_string().unwrap_or_default()
     }
 }
 
@@ -143,7 +455,9 @@ impl FromStr for Element {
 
 impl PartialEq for Element {
     fn eq(&self, other: &Self) -> bool {
-        if self.name() == other.name() && self.ns() == other.ns() && self.attrs().eq(other.attrs())
+        if self.name() == other.name()
+            && self.ns() == other.ns()
+            && self.attributes == other.attributes
         {
             if self.nodes().count() != other.nodes().count() {
                 return false;
@@ -158,19 +472,21 @@ impl PartialEq for Element {
 }
 
 impl Element {
-    pub(crate) fn new<P: Into<Prefixes>>(
-        name: String,
-        namespace: String,
+    pub(crate) fn new<N: Into<Arc<str>>, NS: Into<Arc<str>>, P: Into<Prefixes>>(
+        name: N,
+        namespace: NS,
         prefixes: P,
-        attributes: BTreeMap<String, String>,
+        attributes: Attributes,
         children: Vec<Node>,
     ) -> Element {
         Element {
-            name,
-            namespace,
+            name: name.into(),
+            namespace: namespace.into(),
+            prefix: None,
             prefixes: prefixes.into(),
             attributes,
             children,
+            spans: None,
         }
     }
 
@@ -195,10 +511,10 @@ impl Element {
     pub fn builder<S: AsRef<str>, NS: Into<String>>(name: S, namespace: NS) -> ElementBuilder {
         ElementBuilder {
             root: Element::new(
-                name.as_ref().to_string(),
+                name.as_ref(),
                 namespace.into(),
                 None,
-                BTreeMap::new(),
+                Attributes::default(),
                 Vec::new(),
             ),
         }
@@ -223,7 +539,7 @@ impl Element {
             name.into(),
             namespace.into(),
             None,
-            BTreeMap::new(),
+            Attributes::default(),
             Vec::new(),
         )
     }
@@ -234,19 +550,88 @@ impl Element {
     }
 
     /// Returns a reference to the namespace of this element.
-    pub fn ns(&self) -> String {
-        self.namespace.clone()
+    pub fn ns(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Returns the prefix this element has been parsed with, if it had one.
+    ///
+    /// The writer uses it again wherever it is still bound to the namespace of the element,
+    /// and picks a prefix or the default namespace otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Element;
+    ///
+    /// let xml = "<stream:stream xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'><stream:features/></stream:stream>";
+    /// let elem: Element = xml.parse().unwrap();
+    /// assert_eq!(elem.prefix(), Some("stream"));
+    /// assert_eq!(String::from(&elem), xml);
+    ///
+    /// // Out of its parent, the prefix isn't declared anymore.
+    /// let features = elem.get_child("features", "http://etherx.jabber.org/streams").unwrap();
+    /// assert_eq!(features.prefix(), Some("stream"));
+    /// assert_eq!(String::from(features), "<features xmlns='http://etherx.jabber.org/streams'/>");
+    /// ```
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
     }
 
     /// Returns a reference to the value of the given attribute, if it exists, else `None`.
+    ///
+    /// A prefixed name, as in `xlink:href`, is looked up in the namespace its prefix is bound
+    /// to. Besides `xml`, which is understood everywhere, the prefix has to be declared on this
+    /// element, or to be the one this element or one of its attributes has been parsed with.
+    /// Use `attr_ns` to look attributes up by namespace.
     pub fn attr(&self, name: &str) -> Option<&str> {
-        if let Some(value) = self.attributes.get(name) {
-            return Some(value);
+        let (prefix, name) = split_attr_name(name);
+        let namespace = self.resolve_attr_prefix(prefix)?;
+        self.attributes.get(namespace, name)
+    }
+
+    /// Find the namespace the prefix of an attribute name, as given to `attr`, is bound to.
+    fn resolve_attr_prefix(&self, prefix: Option<&str>) -> Option<&str> {
+        let prefix = match prefix {
+            None => return Some(""),
+            Some("xml") => return Some(XML_NS),
+            Some(prefix) => prefix,
+        };
+        let declared = self
+            .prefixes
+            .declared_prefixes()
+            .iter()
+            .find(|(declared, _)| declared.as_deref() == Some(prefix));
+        if let Some((_, namespace)) = declared {
+            return Some(namespace);
         }
-        None
+        if self.prefix.as_deref() == Some(prefix) {
+            return Some(&self.namespace);
+        }
+        self.attributes.namespace_of_prefix(prefix)
+    }
+
+    /// Returns a reference to the value of the attribute with the given local name and
+    /// namespace, if it exists, else `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Element;
+    ///
+    /// let elem: Element = "<use xmlns='svg' xmlns:xlink='http://www.w3.org/1999/xlink' xlink:href='#a'/>".parse().unwrap();
+    ///
+    /// assert_eq!(elem.attr_ns("href", "http://www.w3.org/1999/xlink"), Some("#a"));
+    /// assert_eq!(elem.attr("href"), None);
+    /// ```
+    pub fn attr_ns(&self, name: &str, namespace: &str) -> Option<&str> {
+        self.attributes.get(namespace, name)
     }
 
-    /// Returns an iterator over the attributes of this element.
+    /// Returns an iterator over the local names and values of the attributes of this element.
+    ///
+    /// Attributes are sorted by namespace, then by name, unless their order is preserved. Use
+    /// `attrs_ns` to tell apart attributes of the same name in different namespaces.
     ///
     /// # Example
     ///
@@ -266,6 +651,14 @@ impl Element {
         }
     }
 
+    /// Returns an iterator over the namespaces, local names and values of the attributes of
+    /// this element, the namespace being empty for attributes which have none.
+    pub fn attrs_ns(&self) -> AttrsNs {
+        AttrsNs {
+            iter: self.attributes.iter(),
+        }
+    }
+
     /// Returns an iterator over the attributes of this element, with the value being a mutable
     /// reference.
     pub fn attrs_mut(&mut self) -> AttrsMut {
@@ -274,19 +667,101 @@ impl Element {
         }
     }
 
-    /// Modifies the value of an attribute.
+    /// Keep the attributes of this element in the order they are added in, new ones being
+    /// written after the current ones, or sort them again by namespace and name.
+    ///
+    /// Elements parsed with `ParseOptions::preserve_attribute_order` keep the order of the
+    /// document. The order never matters when comparing elements.
+    pub fn set_preserve_attribute_order(&mut self, preserve: bool) {
+        self.attributes.set_preserve_order(preserve);
+    }
+
+    /// Returns whether the attributes of this element are kept in the order they have been
+    /// added in.
+    pub fn preserves_attribute_order(&self) -> bool {
+        self.attributes.preserves_order()
+    }
+
+    /// Modifies the value of an attribute, named as in `attr`, or removes it if the value is
+    /// `None`. The prefix of the name is kept when writing the attribute.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name has a prefix which isn't bound to a namespace as `attr` requires.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Element;
+    ///
+    /// let mut elem = Element::builder("use", "svg")
+    ///     .prefix(Some(String::from("xlink")), "http://www.w3.org/1999/xlink")
+    ///     .unwrap()
+    ///     .build();
+    /// elem.set_attr("xlink:href", "#a");
+    ///
+    /// assert_eq!(elem.attr_ns("href", "http://www.w3.org/1999/xlink"), Some("#a"));
+    /// assert_eq!(
+    ///     elem.to_xml_string().unwrap(),
+    ///     r##"<use xmlns='svg' xmlns:xlink='http://www.w3.org/1999/xlink' xlink:href="#a"/>"##,
+    /// );
+    /// ```
     pub fn set_attr<S: Into<String>, V: IntoAttributeValue>(&mut self, name: S, val: V) {
         let name = name.into();
-        let val = val.into_attribute_value();
+        let (prefix, local) = split_attr_name(&name);
+        let namespace = match self.resolve_attr_prefix(prefix) {
+            Some(namespace) => Arc::from(namespace),
+            None => panic!(
+                "the prefix of attribute {:?} isn't bound to a namespace on this element",
+                name
+            ),
+        };
+        self.set_attr_with_prefix(local.to_owned(), namespace, prefix, val);
+    }
 
-        if let Some(value) = self.attributes.get_mut(&name) {
-            *value = val
-                .expect("removing existing value via set_attr, this is not yet supported (TODO)"); // TODO
-            return;
-        }
+    /// Modifies the value of the attribute with the given local name and namespace, or removes
+    /// it if the value is `None`. The writer picks a prefix for the namespace, declaring it if
+    /// needed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Element;
+    ///
+    /// let mut elem = Element::bare("use", "svg");
+    /// elem.set_attr_ns("href", "http://www.w3.org/1999/xlink", "#a");
+    ///
+    /// let xml = String::from(&elem);
+    /// let parsed: Element = xml.parse().unwrap();
+    /// assert_eq!(parsed.attr_ns("href", "http://www.w3.org/1999/xlink"), Some("#a"));
+    ///
+    /// elem.set_attr_ns("href", "http://www.w3.org/1999/xlink", None::<String>);
+    /// assert_eq!(elem.attr_ns("href", "http://www.w3.org/1999/xlink"), None);
+    /// ```
+    pub fn set_attr_ns<S: Into<String>, NS: AsRef<str>, V: IntoAttributeValue>(
+        &mut self,
+        name: S,
+        namespace: NS,
+        val: V,
+    ) {
+        self.set_attr_with_prefix(name.into(), namespace.as_ref().into(), None, val);
+    }
 
-        if let Some(val) = val {
-            self.attributes.insert(name, val);
+    fn set_attr_with_prefix<V: IntoAttributeValue>(
+        &mut self,
+        name: String,
+        namespace: Arc<str>,
+        prefix: Option<&str>,
+        val: V,
+    ) {
+        match val.into_attribute_value() {
+            Some(val) => {
+                self.attributes
+                    .insert(namespace, name, val, prefix.map(Arc::from));
+            }
+            None => {
+                self.attributes.remove(&namespace, &name);
+            }
         }
     }
 
@@ -310,7 +785,7 @@ impl Element {
     /// assert_eq!(elem.is("name", NSChoice::Any), true);
     /// ```
     pub fn is<'a, N: AsRef<str>, NS: Into<NSChoice<'a>>>(&self, name: N, namespace: NS) -> bool {
-        self.name == name.as_ref() && namespace.into().compare(self.namespace.as_ref())
+        *self.name == *name.as_ref() && namespace.into().compare(self.namespace.as_ref())
     }
 
     /// Returns whether the element has the given namespace.
@@ -334,18 +809,65 @@ impl Element {
         namespace.into().compare(self.namespace.as_ref())
     }
 
-    /// Parse a document from a `BufRead`.
-    pub fn from_reader<R: BufRead>(reader: R) -> Result<Element> {
-        let mut tree_builder = TreeBuilder::new();
-        let mut driver = PullDriver::wrap(reader, Lexer::new(), RawParser::new());
-        while let Some(event) = driver.read()? {
-            tree_builder.process_event(event)?;
+    /// Returns where the start tag of this element is in the document it has been parsed from,
+    /// or `None` if it hasn't been parsed with `ParseOptions::record_positions`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, ParseOptions};
+    ///
+    /// let xml = b"<root xmlns='ns1'>\n  <child a='b'/></root>";
+    /// let options = ParseOptions::new().record_positions(true);
+    /// let elem = Element::from_reader_with_options(&xml[..], options).unwrap();
+    /// let child = elem.get_child("child", "ns1").unwrap();
+    ///
+    /// let span = child.start_tag_span().unwrap();
+    /// assert_eq!((span.start.line, span.start.column), (2, 3));
+    /// assert_eq!(child.end_tag_span(), Some(span));
+    ///
+    /// let span = child.attr_span("a").unwrap();
+    /// assert_eq!((span.start.offset, span.end.offset), (28, 33));
+    ///
+    /// assert_eq!(Element::bare("root", "ns1").start_tag_span(), None);
+    /// ```
+    pub fn start_tag_span(&self) -> Option<Span> {
+        self.spans.as_ref().map(|spans| spans.start_tag)
+    }
 
-            if let Some(root) = tree_builder.root.take() {
-                return Ok(root);
-            }
+    /// Returns where the end tag of this element is in the document it has been parsed from,
+    /// which is the same as the start tag for empty-element tags like `<a/>`.
+    pub fn end_tag_span(&self) -> Option<Span> {
+        self.spans.as_ref().and_then(|spans| spans.end_tag)
+    }
+
+    /// Returns where the attribute `name`, named as in `attr`, is in the start tag this element
+    /// has been parsed from, if it was there.
+    pub fn attr_span(&self, name: &str) -> Option<Span> {
+        let (prefix, name) = split_attr_name(name);
+        let namespace = self.resolve_attr_prefix(prefix)?;
+        self.spans.as_ref().and_then(|spans| {
+            spans
+                .attributes
+                .iter()
+                .find(|((ns, local), _)| **ns == *namespace && local == name)
+                .map(|(_, span)| *span)
+        })
+    }
+
+    pub(crate) fn set_end_tag_span(&mut self, span: Span) {
+        if let Some(ref mut spans) = self.spans {
+            spans.end_tag = Some(span);
         }
-        Err(Error::EndOfDocument)
+    }
+
+    /// Parse a document from a `BufRead`.
+    ///
+    /// Errors don't tell where they have been found in the document, so that they can be matched
+    /// on directly; use `from_reader_with_options` with `ParseOptions::record_positions` to get
+    /// their positions.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Element> {
+        Element::from_tree_builder(reader, TreeBuilder::new())
     }
 
     /// Parse a document from a `BufRead`, allowing Prefixes to be specified. Useful to provide
@@ -355,16 +877,172 @@ impl Element {
         reader: R,
         prefixes: P,
     ) -> Result<Element> {
-        let mut tree_builder = TreeBuilder::new().with_prefixes_stack(vec![prefixes.into()]);
-        let mut driver = PullDriver::wrap(reader, Lexer::new(), RawParser::new());
-        while let Some(event) = driver.read()? {
-            tree_builder.process_event(event)?;
+        let tree_builder = TreeBuilder::new().with_prefixes_stack(vec![prefixes.into()]);
+        Element::from_tree_builder(reader, tree_builder)
+    }
+
+    /// Parse a document from a `BufRead`, using the given `ParseOptions`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, NSChoice, ParseOptions};
+    ///
+    /// let xml = br#"<svg><rect width="10"/></svg>"#;
+    /// let options = ParseOptions::new().allow_missing_namespace(true);
+    /// let elem = Element::from_reader_with_options(&xml[..], options).unwrap();
+    ///
+    /// assert!(elem.is("svg", NSChoice::None));
+    /// assert!(elem.has_child("rect", NSChoice::None));
+    /// ```
+    pub fn from_reader_with_options<R: BufRead>(
+        reader: R,
+        options: ParseOptions,
+    ) -> Result<Element> {
+        Element::from_tree_builder(reader, TreeBuilder::new().with_options(options))
+    }
+
+    /// Parse a document from a `BufRead`, using the given `ParseOptions`, and also return the
+    /// bytes following the end tag of the root element which have already been read from
+    /// `reader`.
+    ///
+    /// Whatever comes after these bytes is left in `reader`, so that several documents, or a
+    /// document followed by anything else, can be read from the same stream by passing it as
+    /// `&mut reader`.
+    ///
+    /// The bytes are returned in two parts. If `ParseOptions::detect_encoding` found the
+    /// document to be in another encoding than UTF-8, the first part has been decoded to UTF-8,
+    /// and the second one holds the bytes after it which couldn't be decoded yet, such as half
+    /// of a UTF-16 code unit, in the encoding of the document. Otherwise, the second part is
+    /// empty.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, ParseOptions};
+    ///
+    /// let mut reader = &b"<a xmlns='ns1'/><b xmlns='ns1'/>\x00\x01"[..];
+    /// let (a, rest, _) = Element::from_reader_with_rest(&mut reader, ParseOptions::new()).unwrap();
+    /// assert_eq!(a.name(), "a");
+    ///
+    /// let mut reader = &rest[..];
+    /// let (b, rest, _) = Element::from_reader_with_rest(&mut reader, ParseOptions::new()).unwrap();
+    /// assert_eq!(b.name(), "b");
+    /// assert_eq!(rest, b"\x00\x01");
+    /// ```
+    pub fn from_reader_with_rest<R: BufRead>(
+        reader: R,
+        options: ParseOptions,
+    ) -> Result<(Element, Vec<u8>, Vec<u8>)> {
+        let tree_builder = TreeBuilder::new().with_options(options);
+        Element::from_tree_builder_with_driver(reader, tree_builder).map(|(root, driver)| {
+            let (rest, undecoded) = driver.into_rest();
+            (root, rest, undecoded)
+        })
+    }
+
+    fn from_tree_builder<R: BufRead>(reader: R, tree_builder: TreeBuilder) -> Result<Element> {
+        Element::from_tree_builder_with_driver(reader, tree_builder).map(|(root, _)| root)
+    }
 
-            if let Some(root) = tree_builder.root.take() {
-                return Ok(root);
+    fn from_tree_builder_with_driver<R: BufRead>(
+        mut reader: R,
+        mut tree_builder: TreeBuilder,
+    ) -> Result<(Element, Driver)> {
+        let mut driver = Driver::new(tree_builder.options());
+        let mut parse = || -> Result<Element> {
+            loop {
+                if let Some(root) = tree_builder.drive(&mut driver)? {
+                    return Ok(root);
+                }
+                driver.feed_from(&mut reader)?;
             }
+        };
+        match parse() {
+            Ok(root) => Ok((root, driver)),
+            Err(err) => Err(driver.locate(err)),
         }
-        Err(Error::EndOfDocument)
+    }
+
+    /// Parse a document from an `AsyncBufRead`.
+    ///
+    /// Only tokio's `AsyncBufRead` is supported, with the `tokio` feature; a
+    /// `futures::AsyncBufRead` can be wrapped with the compatibility layer of `tokio-util`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Element;
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+    /// let xml = b"<message xmlns='jabber:client'><body>Hi!</body></message>";
+    /// let elem = Element::from_async_reader(&xml[..]).await.unwrap();
+    ///
+    /// assert_eq!(elem.name(), "message");
+    /// # });
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn from_async_reader<R: AsyncBufRead + Unpin>(reader: R) -> Result<Element> {
+        Element::from_async_tree_builder(reader, TreeBuilder::new()).await
+    }
+
+    /// Parse a document from an `AsyncBufRead`, allowing Prefixes to be specified, as with
+    /// `from_reader_with_prefixes`.
+    #[cfg(feature = "tokio")]
+    pub async fn from_async_reader_with_prefixes<R: AsyncBufRead + Unpin, P: Into<Prefixes>>(
+        reader: R,
+        prefixes: P,
+    ) -> Result<Element> {
+        let tree_builder = TreeBuilder::new().with_prefixes_stack(vec![prefixes.into()]);
+        Element::from_async_tree_builder(reader, tree_builder).await
+    }
+
+    /// Parse a document from an `AsyncBufRead`, using the given `ParseOptions`.
+    #[cfg(feature = "tokio")]
+    pub async fn from_async_reader_with_options<R: AsyncBufRead + Unpin>(
+        reader: R,
+        options: ParseOptions,
+    ) -> Result<Element> {
+        Element::from_async_tree_builder(reader, TreeBuilder::new().with_options(options)).await
+    }
+
+    #[cfg(feature = "tokio")]
+    async fn from_async_tree_builder<R: AsyncBufRead + Unpin>(
+        mut reader: R,
+        mut tree_builder: TreeBuilder,
+    ) -> Result<Element> {
+        let mut driver = Driver::new(tree_builder.options());
+        let parse = async {
+            loop {
+                if let Some(root) = tree_builder.drive(&mut driver)? {
+                    return Ok(root);
+                }
+                driver.feed_from_async(&mut reader).await?;
+            }
+        };
+        parse.await.map_err(|err| driver.locate(err))
+    }
+
+    /// Serialize this element into a `String`, as `write_to` does.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, Error};
+    ///
+    /// let elem = Element::bare("message", "jabber:client");
+    /// assert_eq!(elem.to_xml_string().unwrap(), "<message xmlns='jabber:client'/>");
+    ///
+    /// let elem = Element::bare("not a name", "jabber:client");
+    /// assert!(matches!(elem.to_xml_string(), Err(Error::InvalidElementName(_))));
+    /// ```
+    pub fn to_xml_string(&self) -> Result<String> {
+        let mut writer = Vec::new();
+        self.write_to(&mut writer)?;
+        String::from_utf8(writer).map_err(|err| {
+            let byte = err.as_bytes()[err.utf8_error().valid_up_to()];
+            rxml::Error::InvalidUtf8Byte(byte).into()
+        })
     }
 
     /// Output a document to a `Writer`.
@@ -372,6 +1050,143 @@ impl Element {
         self.to_writer(&mut ItemWriter::new(writer))
     }
 
+    /// Output a document to a `Writer`, laid out according to `options`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, WriteOptions};
+    ///
+    /// let elem: Element = "<a xmlns='ns1'><b><c/></b><p>Some <em>mixed</em> text</p></a>"
+    ///     .parse()
+    ///     .unwrap();
+    ///
+    /// let mut out = Vec::new();
+    /// elem.write_to_with_options(&mut out, WriteOptions::new().indent("\t")).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(out).unwrap(),
+    ///     "<a xmlns='ns1'>\n\t<b>\n\t\t<c/>\n\t</b>\n\t<p>Some <em>mixed</em> text</p>\n</a>",
+    /// );
+    /// ```
+    pub fn write_to_with_options<W: Write>(
+        &self,
+        writer: &mut W,
+        options: WriteOptions,
+    ) -> Result<()> {
+        self.to_writer(&mut ItemWriter::with_options(writer, options))
+    }
+
+    /// Output the canonical form of this element to a `Writer`, following `method`, so that
+    /// equivalent elements are written as the same bytes.
+    ///
+    /// CDATA sections are written as text, empty elements get an end tag, attributes are
+    /// sorted by namespace and then by local name, and only the namespace declarations the
+    /// method asks for are written, sorted by prefix.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Canonicalization, Element};
+    ///
+    /// let elem: Element = r#"<a:root xmlns:a="ns1" xmlns:b="ns2" z="1" y="&#9;"><a:c/></a:root>"#
+    ///     .parse()
+    ///     .unwrap();
+    ///
+    /// let mut out = Vec::new();
+    /// elem.write_canonical_to(&mut out, Canonicalization::Exclusive).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(out).unwrap(),
+    ///     r#"<a:root xmlns:a="ns1" y="&#x9;" z="1"><a:c></a:c></a:root>"#,
+    /// );
+    /// ```
+    pub fn write_canonical_to<W: Write>(
+        &self,
+        writer: &mut W,
+        method: Canonicalization,
+    ) -> Result<()> {
+        canonical::write_canonical(self, method, writer)
+    }
+
+    /// Output a document to an `AsyncWrite`, a chunk at a time instead of serializing all of
+    /// it first.
+    ///
+    /// Only tokio's `AsyncWrite` is supported, with the `tokio` feature; a `futures::AsyncWrite`
+    /// can be wrapped with the compatibility layer of `tokio-util`. Text and CDATA sections are
+    /// sent in pieces, but a start tag, attribute values included, is buffered whole.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Element;
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+    /// let elem: Element = "<message xmlns='jabber:client'><body>Hi!</body></message>".parse().unwrap();
+    ///
+    /// let mut out = Vec::new();
+    /// elem.write_to_async(&mut out).await.unwrap();
+    /// assert_eq!(out, b"<message xmlns='jabber:client'><body>Hi!</body></message>");
+    /// # });
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn write_to_async<A: AsyncWrite + Unpin>(&self, writer: &mut A) -> Result<()> {
+        self.write_to_async_inner(&mut ItemWriter::new(Vec::new()), writer)
+            .await
+    }
+
+    /// Write this element to `writer` through the `items` buffer, emptying it whenever it
+    /// holds a chunk.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn write_to_async_inner<A: AsyncWrite + Unpin>(
+        &self,
+        items: &mut ItemWriter<Vec<u8>>,
+        writer: &mut A,
+    ) -> Result<()> {
+        // The tree is walked without recursing, which async functions can't do.
+        let layout = self.write_start_inner(items)?;
+        let mut stack = vec![(self, self.children.iter(), layout)];
+        while let Some((_, children, layout)) = stack.last_mut() {
+            match children.next() {
+                Some(child) => {
+                    if layout.start_child(items, child)? {
+                        match *child {
+                            Node::Element(ref child) => {
+                                let layout = child.write_start_inner(items)?;
+                                stack.push((child, child.children.iter(), layout));
+                            }
+                            // Large text is sent a piece at a time instead of being copied
+                            // whole into the buffer.
+                            Node::Text(ref text) => {
+                                for piece in async_pieces(text) {
+                                    items.write(Item::Text(piece.try_into()?))?;
+                                    send_chunk(items, writer).await?;
+                                }
+                            }
+                            Node::CData(ref cdata) => {
+                                rxml::strings::validate_cdata(cdata).map_err(rxml::Error::Xml)?;
+                                items.write_raw(b"<![CDATA[")?;
+                                for piece in async_pieces(cdata) {
+                                    items.write_cdata_content(piece)?;
+                                    send_chunk(items, writer).await?;
+                                }
+                                items.write_raw(b"]]>")?;
+                            }
+                            ref child => child.write_to_inner(items)?,
+                        }
+                    }
+                }
+                None => {
+                    if let Some((elem, _, layout)) = stack.pop() {
+                        elem.write_end_inner(items, layout)?;
+                    }
+                }
+            }
+            send_chunk(items, writer).await?;
+        }
+        write_all_async(writer, items.get_ref()).await?;
+        items.get_mut().clear();
+        Ok(())
+    }
+
     /// Output a document to a `Writer`.
     pub fn write_to_decl<W: Write>(&self, writer: &mut W) -> Result<()> {
         self.to_writer_decl(&mut ItemWriter::new(writer))
@@ -384,51 +1199,128 @@ impl Element {
 
     /// Output the document to an `ItemWriter`
     pub fn to_writer_decl<W: Write>(&self, writer: &mut ItemWriter<W>) -> Result<()> {
-        writer
-            .write(Item::XmlDeclaration(XmlVersion::V1_0))
-            .unwrap(); // TODO: error return
+        writer.write(Item::XmlDeclaration(XmlVersion::V1_0))?;
         self.write_to_inner(writer)
     }
 
     /// Like `write_to()` but without the `<?xml?>` prelude
     pub fn write_to_inner<W: Write>(&self, writer: &mut ItemWriter<W>) -> Result<()> {
+        let layout = self.write_start_inner(writer)?;
+        for child in self.children.iter() {
+            if layout.start_child(writer, child)? {
+                child.write_to_inner(writer)?;
+            }
+        }
+        self.write_end_inner(writer, layout)
+    }
+
+    /// Write the start tag of this element, and get ready to write its children.
+    pub(crate) fn write_start_inner<W: Write>(
+        &self,
+        writer: &mut ItemWriter<W>,
+    ) -> Result<ContentLayout> {
+        self.write_head_inner(writer)?;
+        let layout = ContentLayout {
+            pretty: writer.pretty,
+            space_preserved: writer.space_preserved,
+            indent: false,
+        };
+        if self.children.is_empty() {
+            return Ok(layout);
+        }
+
+        writer.write(Item::ElementHeadEnd)?;
+        writer.space_preserved = match self.attr("xml:space") {
+            Some("preserve") => true,
+            Some("default") => false,
+            _ => layout.space_preserved,
+        };
+        let is_whitespace = |c| matches!(c, ' ' | '\t' | '\n' | '\r');
+        writer.pretty = layout.pretty
+            && self.children.iter().all(|child| match *child {
+                Node::Text(ref text) => text.chars().all(is_whitespace),
+                Node::CData(_) => false,
+                _ => true,
+            });
+        let indent = writer.pretty
+            && !writer.space_preserved
+            && self
+                .children
+                .iter()
+                .any(|child| !matches!(child, Node::Text(_)));
+        Ok(ContentLayout { indent, ..layout })
+    }
+
+    /// Write the end tag of this element, once its children have been written.
+    pub(crate) fn write_end_inner<W: Write>(
+        &self,
+        writer: &mut ItemWriter<W>,
+        layout: ContentLayout,
+    ) -> Result<()> {
+        if layout.indent {
+            writer.write_indent(writer.depth() - 1)?;
+        }
+        writer.write(Item::ElementFoot)?;
+        writer.pretty = layout.pretty;
+        writer.space_preserved = layout.space_preserved;
+        Ok(())
+    }
+
+    /// Write the start of the start tag of this element, up to its attributes.
+    pub(crate) fn write_head_inner<W: Write>(&self, writer: &mut ItemWriter<W>) -> Result<()> {
         for (prefix, namespace) in self.prefixes.declared_prefixes() {
-            assert!(writer.encoder.inner_mut().declare_fixed(
-                prefix.as_ref().map(|x| (&**x).try_into()).transpose()?,
-                Some(Arc::new(namespace.clone().try_into()?))
-            ));
+            let invalid = || Error::InvalidNamespaceDeclaration(prefix.clone(), namespace.clone());
+            let ncname = match prefix {
+                Some(prefix) => Some((&**prefix).try_into().map_err(|_| invalid())?),
+                None => None,
+            };
+            if !writer
+                .encoder
+                .inner_mut()
+                .declare_fixed(ncname, Some(Arc::new(namespace.clone().try_into()?)))
+            {
+                return Err(invalid());
+            }
         }
 
         let namespace = if self.namespace.is_empty() {
             None
         } else {
-            Some(Arc::new(self.namespace.clone().try_into()?))
+            Some(Arc::new(String::from(&*self.namespace).try_into()?))
         };
-        writer.write(Item::ElementHeadStart(namespace, (*self.name).try_into()?))?;
-
-        for (key, value) in self.attributes.iter() {
-            let (prefix, name) = <&rxml::NameStr>::try_from(&**key)
-                .unwrap()
-                .split_name()
-                .unwrap();
-            let namespace = match prefix {
-                Some(prefix) => match writer.encoder.inner().lookup_prefix(Some(prefix)) {
-                    Ok(v) => Some(v),
-                    Err(rxml::writer::PrefixError::Undeclared) => return Err(Error::InvalidPrefix),
-                },
-                None => None,
+        let prefix = self
+            .prefix
+            .as_deref()
+            .and_then(|prefix| prefix.try_into().ok());
+        writer.encoder.inner_mut().prefer_prefix(prefix);
+        let name = (*self.name)
+            .try_into()
+            .map_err(|_| Error::InvalidElementName(String::from(&*self.name)))?;
+        writer.write(Item::ElementHeadStart(namespace, name))?;
+
+        for attribute in self.attributes.iter() {
+            // The encoder reuses the prefix the attribute has been parsed with or another one
+            // bound to the namespace, or declares one.
+            let prefix = attribute
+                .prefix
+                .as_deref()
+                .and_then(|prefix| prefix.try_into().ok());
+            writer.encoder.inner_mut().prefer_prefix(prefix);
+            let namespace = if attribute.namespace.is_empty() {
+                None
+            } else {
+                Some(Arc::new(String::from(&*attribute.namespace).try_into()?))
             };
-            writer.write(Item::Attribute(namespace, name, (&**value).try_into()?))?;
+            let name = (*attribute.name)
+                .try_into()
+                .map_err(|_| Error::InvalidAttributeName(attribute.name.clone()))?;
+            writer.write(Item::Attribute(
+                namespace,
+                name,
+                (*attribute.value).try_into()?,
+            ))?;
         }
 
-        if !self.children.is_empty() {
-            writer.write(Item::ElementHeadEnd)?;
-            for child in self.children.iter() {
-                child.write_to_inner(writer)?;
-            }
-        }
-        writer.write(Item::ElementFoot)?;
-
         Ok(())
     }
 
@@ -491,7 +1383,25 @@ impl Element {
         }
     }
 
-    /// Returns an iterator over references to every text node of this element.
+    /// Returns a `Cursor` on this element, to move to its descendants and back.
+    pub fn cursor(&self) -> Cursor {
+        Cursor::new(self)
+    }
+
+    /// Returns a `CursorMut` on this element, to move to its descendants and edit them.
+    pub fn cursor_mut(&mut self) -> CursorMut {
+        CursorMut::new(self, Vec::new())
+    }
+
+    /// Returns a `CursorMut` on the descendant found by following `indices`, as returned by
+    /// `Cursor::indices`, or `None` if they don't lead to an element.
+    pub fn cursor_mut_at(&mut self, indices: &[usize]) -> Option<CursorMut> {
+        Cursor::at(self, indices)?;
+        Some(CursorMut::new(self, indices.to_vec()))
+    }
+
+    /// Returns an iterator over references to every text node of this element, including CDATA
+    /// sections.
     ///
     /// # Examples
     ///
@@ -512,7 +1422,8 @@ impl Element {
         }
     }
 
-    /// Returns an iterator over mutable references to every text node of this element.
+    /// Returns an iterator over mutable references to every text node of this element, including
+    /// CDATA sections.
     #[inline]
     pub fn texts_mut(&mut self) -> TextsMut {
         TextsMut {
@@ -520,6 +1431,35 @@ impl Element {
         }
     }
 
+    /// Returns an iterator over references to every comment node of this element.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Element;
+    ///
+    /// let elem: Element = "<root xmlns=\"ns1\"><!-- a -->hello<!--b--></root>".parse().unwrap();
+    ///
+    /// let mut iter = elem.comments();
+    /// assert_eq!(iter.next().unwrap(), " a ");
+    /// assert_eq!(iter.next().unwrap(), "b");
+    /// assert_eq!(iter.next(), None);
+    /// ```
+    #[inline]
+    pub fn comments(&self) -> Comments {
+        Comments {
+            iter: self.children.iter(),
+        }
+    }
+
+    /// Returns an iterator over mutable references to every comment node of this element.
+    #[inline]
+    pub fn comments_mut(&mut self) -> CommentsMut {
+        CommentsMut {
+            iter: self.children.iter_mut(),
+        }
+    }
+
     /// Appends a child node to the `Element`, returning the appended node.
     ///
     /// # Examples
@@ -571,6 +1511,24 @@ impl Element {
         self.children.push(Node::Text(child.into()));
     }
 
+    /// Appends a comment node to an `Element`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Element;
+    ///
+    /// let mut elem = Element::bare("node", "ns1");
+    ///
+    /// elem.append_comment_node(" note ");
+    ///
+    /// assert_eq!(elem.comments().next(), Some(" note "));
+    /// assert_eq!(String::from(&elem), "<node xmlns='ns1'><!-- note --></node>");
+    /// ```
+    pub fn append_comment_node<S: Into<String>>(&mut self, comment: S) {
+        self.children.push(Node::Comment(comment.into()));
+    }
+
     /// Appends a node to an `Element`.
     ///
     /// # Examples
@@ -588,7 +1546,7 @@ impl Element {
         self.children.push(node);
     }
 
-    /// Returns the concatenation of all text nodes in the `Element`.
+    /// Returns the concatenation of all text nodes and CDATA sections in the `Element`.
     ///
     /// # Examples
     ///
@@ -603,6 +1561,65 @@ impl Element {
         self.texts().fold(String::new(), |ret, new| ret + new)
     }
 
+    /// Apply `mode` to the text nodes of this element and of its descendants, as parsing with
+    /// `ParseOptions::whitespace` would.
+    ///
+    /// Text nodes of elements in the scope of `xml:space="preserve"` are kept as they are, which
+    /// doesn't take the ancestors of this element into account.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, WhitespaceMode};
+    ///
+    /// let mut elem: Element = "<a xmlns='ns1'>\n  <b> text </b>\n</a>".parse().unwrap();
+    /// elem.normalize_whitespace(WhitespaceMode::DropWhitespaceOnly);
+    ///
+    /// assert_eq!(elem, Element::builder("a", "ns1").append(
+    ///     Element::builder("b", "ns1").append(" text ")
+    /// ).build());
+    /// ```
+    pub fn normalize_whitespace(&mut self, mode: WhitespaceMode) {
+        self.normalize_whitespace_inner(mode, false);
+    }
+
+    fn normalize_whitespace_inner(&mut self, mode: WhitespaceMode, preserve: bool) {
+        let preserve = match self.attr("xml:space") {
+            Some("preserve") => true,
+            Some("default") => false,
+            _ => preserve,
+        };
+        if !preserve {
+            self.normalize_text_nodes(mode);
+        }
+        for child in self.children_mut() {
+            child.normalize_whitespace_inner(mode, preserve);
+        }
+    }
+
+    /// Apply `mode` to the text nodes of this element only.
+    pub(crate) fn normalize_text_nodes(&mut self, mode: WhitespaceMode) {
+        let is_whitespace = |c| matches!(c, ' ' | '\t' | '\n' | '\r');
+        match mode {
+            WhitespaceMode::Preserve => return,
+            WhitespaceMode::DropWhitespaceOnly => (),
+            WhitespaceMode::Trim => {
+                for node in &mut self.children {
+                    if let Node::Text(text) = node {
+                        let trimmed = text.trim_matches(is_whitespace);
+                        if trimmed.len() != text.len() {
+                            *text = trimmed.to_owned();
+                        }
+                    }
+                }
+            }
+        }
+        self.children.retain(|node| match node {
+            Node::Text(text) => !text.chars().all(is_whitespace),
+            _ => true,
+        });
+    }
+
     /// Returns a reference to the first child element with the specific name and namespace, if it
     /// exists in the direct descendants of this `Element`, else returns `None`.
     ///
@@ -760,7 +1777,7 @@ impl<'a> Iterator for Texts<'a> {
 
     fn next(&mut self) -> Option<&'a str> {
         for item in &mut self.iter {
-            if let Node::Text(ref child) = *item {
+            if let Node::Text(ref child) | Node::CData(ref child) = *item {
                 return Some(child);
             }
         }
@@ -778,7 +1795,43 @@ impl<'a> Iterator for TextsMut<'a> {
 
     fn next(&mut self) -> Option<&'a mut String> {
         for item in &mut self.iter {
-            if let Node::Text(ref mut child) = *item {
+            if let Node::Text(ref mut child) | Node::CData(ref mut child) = *item {
+                return Some(child);
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over references to child comment nodes of an `Element`.
+pub struct Comments<'a> {
+    iter: slice::Iter<'a, Node>,
+}
+
+impl<'a> Iterator for Comments<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        for item in &mut self.iter {
+            if let Node::Comment(ref child) = *item {
+                return Some(child);
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over mutable references to child comment nodes of an `Element`.
+pub struct CommentsMut<'a> {
+    iter: slice::IterMut<'a, Node>,
+}
+
+impl<'a> Iterator for CommentsMut<'a> {
+    type Item = &'a mut String;
+
+    fn next(&mut self) -> Option<&'a mut String> {
+        for item in &mut self.iter {
+            if let Node::Comment(ref mut child) = *item {
                 return Some(child);
             }
         }
@@ -794,27 +1847,50 @@ pub type NodesMut<'a> = slice::IterMut<'a, Node>;
 
 /// An iterator over the attributes of an `Element`.
 pub struct Attrs<'a> {
-    iter: btree_map::Iter<'a, String, String>,
+    iter: slice::Iter<'a, Attribute>,
 }
 
 impl<'a> Iterator for Attrs<'a> {
     type Item = (&'a str, &'a str);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next().map(|(x, y)| (x.as_ref(), y.as_ref()))
+        self.iter
+            .next()
+            .map(|attribute| (attribute.name.as_ref(), attribute.value.as_ref()))
+    }
+}
+
+/// An iterator over the attributes of an `Element`, with their namespaces.
+pub struct AttrsNs<'a> {
+    iter: slice::Iter<'a, Attribute>,
+}
+
+impl<'a> Iterator for AttrsNs<'a> {
+    type Item = (&'a str, &'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|attribute| {
+            (
+                attribute.namespace.as_ref(),
+                attribute.name.as_ref(),
+                attribute.value.as_ref(),
+            )
+        })
     }
 }
 
 /// An iterator over the attributes of an `Element`, with the values mutable.
 pub struct AttrsMut<'a> {
-    iter: btree_map::IterMut<'a, String, String>,
+    iter: slice::IterMut<'a, Attribute>,
 }
 
 impl<'a> Iterator for AttrsMut<'a> {
     type Item = (&'a str, &'a mut String);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next().map(|(x, y)| (x.as_ref(), y))
+        self.iter
+            .next()
+            .map(|attribute| (attribute.name.as_ref(), &mut attribute.value))
     }
 }
 
@@ -837,7 +1913,18 @@ impl ElementBuilder {
         Ok(self)
     }
 
-    /// Sets an attribute.
+    /// Keep the attributes in the order they are set in, as `Element::set_preserve_attribute_order`.
+    pub fn preserve_attribute_order(mut self, preserve: bool) -> ElementBuilder {
+        self.root.set_preserve_attribute_order(preserve);
+        self
+    }
+
+    /// Sets an attribute, named as in `Element::attr`. A prefix has to be declared with
+    /// `prefix` first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name has a prefix which isn't bound to a namespace, as `Element::set_attr`.
     pub fn attr<S: Into<String>, V: IntoAttributeValue>(
         mut self,
         name: S,
@@ -847,6 +1934,17 @@ impl ElementBuilder {
         self
     }
 
+    /// Sets an attribute in the given namespace.
+    pub fn attr_ns<S: Into<String>, NS: AsRef<str>, V: IntoAttributeValue>(
+        mut self,
+        name: S,
+        namespace: NS,
+        value: V,
+    ) -> ElementBuilder {
+        self.root.set_attr_ns(name, namespace, value);
+        self
+    }
+
     /// Appends anything implementing `Into<Node>` into the tree.
     pub fn append<T: Into<Node>>(mut self, node: T) -> ElementBuilder {
         self.root.append_node(node.into());
@@ -876,11 +1974,13 @@ mod tests {
 
     #[test]
     fn test_element_new() {
+        let mut attributes = Attributes::default();
+        attributes.insert("".into(), "name".to_owned(), "value".to_owned(), None);
         let elem = Element::new(
             "name".to_owned(),
             "namespace".to_owned(),
             (None, "namespace".to_owned()),
-            BTreeMap::from_iter(vec![("name".to_string(), "value".to_string())].into_iter()),
+            attributes,
             Vec::new(),
         );
 
@@ -953,6 +2053,19 @@ mod tests {
         assert_eq!(elem.text(), "&apos;&gt;blah<blah>");
     }
 
+    #[test]
+    fn preserves_cdata_when_asked() {
+        let xml = b"<test xmlns='test'>a<![CDATA[&apos;<blah>]]>b</test>";
+        let options = ParseOptions::new().preserve_cdata(true);
+        let elem = Element::from_reader_with_options(&xml[..], options).unwrap();
+        assert_eq!(
+            elem.nodes().nth(1).unwrap().as_cdata(),
+            Some("&apos;<blah>")
+        );
+        assert_eq!(elem.text(), "a&apos;<blah>b");
+        assert_eq!(String::from(&elem).as_bytes(), &xml[..]);
+    }
+
     #[test]
     fn test_compare_all_ns() {
         let xml = b"<foo xmlns='foo' xmlns:bar='baz'><bar:meh xmlns:bar='baz' /></foo>";
@@ -995,7 +2108,7 @@ mod tests {
 
     #[test]
     fn failure_with_duplicate_namespace() {
-        let _: Element = r###"<?xml version="1.0" encoding="UTF-8"?>
+        let _: Element = r#"<?xml version="1.0" encoding="UTF-8"?>
             <wsdl:definitions
                     xmlns:wsdl="http://schemas.xmlsoap.org/wsdl/"
                     xmlns:xsd="http://www.w3.org/2001/XMLSchema">
@@ -1004,7 +2117,7 @@ mod tests {
                     </xsd:schema>
                 </wsdl:types>
             </wsdl:definitions>
-        "###
+        "#
         .parse()
         .unwrap();
     }
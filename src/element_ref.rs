@@ -0,0 +1,816 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides `ElementRef` and `NodeRef`, a read-only tree borrowing from the string it has been
+//! parsed from.
+
+use crate::attributes::{split_attr_name, Attribute, Attributes, XML_NS};
+use crate::doctype::{parse_doctype, Entities, DOCTYPE_START};
+use crate::driver::{
+    find_tag_end, normalize_newlines, parse_xml_declaration, syntax_error, Construct, Scanner,
+};
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::interner::Interner;
+use crate::namespaces::NSChoice;
+use crate::node::Node;
+use crate::position::Tracker;
+use crate::prefixes::Prefixes;
+use crate::tree_builder::{Limit, ParseOptions, WhitespaceMode};
+
+use rxml::error::XmlError;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::slice;
+
+/// The namespace, local name, value and prefix of an attribute of an `ElementRef`.
+type AttributeRef<'a> = (Cow<'a, str>, &'a str, Cow<'a, str>, Option<&'a str>);
+
+/// A read-only element borrowing its names, and whatever text and attribute values didn't need
+/// to be unescaped, from the string it has been parsed from.
+///
+/// It offers the same queries as `Element`, and `to_owned` turns it into one.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::ElementRef;
+///
+/// let xml = String::from("<message xmlns='jabber:client' to='a@example.com'><body>Hi!</body></message>");
+/// let message = ElementRef::parse(&xml).unwrap();
+///
+/// let body = message.get_child("body", "jabber:client").unwrap();
+/// let text: &str = body.texts().next().unwrap();
+/// assert_eq!(text.as_ptr(), xml[xml.find("Hi!").unwrap()..].as_ptr());
+///
+/// let owned = message.to_owned();
+/// assert_eq!(owned.attr("to"), Some("a@example.com"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElementRef<'a> {
+    prefix: Option<&'a str>,
+    name: &'a str,
+    namespace: Cow<'a, str>,
+    /// Namespace declarations
+    prefixes: Vec<(Option<&'a str>, Cow<'a, str>)>,
+    /// Sorted by namespace and then by local name unless their order is preserved
+    attributes: Vec<AttributeRef<'a>>,
+    /// If the attributes are in document order, their indices sorted by namespace and local name
+    sorted_attributes: Option<Vec<usize>>,
+    children: Vec<NodeRef<'a>>,
+}
+
+/// A node of an `ElementRef`, borrowing from the string it has been parsed from when possible.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeRef<'a> {
+    /// An `ElementRef`
+    Element(ElementRef<'a>),
+    /// A text node
+    Text(Cow<'a, str>),
+    /// A CDATA section, if `ParseOptions::preserve_cdata` has been set
+    CData(Cow<'a, str>),
+    /// A comment
+    Comment(Cow<'a, str>),
+    /// A processing instruction
+    ProcessingInstruction {
+        /// The target of the processing instruction
+        target: &'a str,
+        /// The data following the target
+        data: Cow<'a, str>,
+    },
+}
+
+impl<'a> ElementRef<'a> {
+    /// Parse a whole document from a string.
+    pub fn parse(input: &'a str) -> Result<ElementRef<'a>> {
+        ElementRef::parse_with_options(input, ParseOptions::default())
+    }
+
+    /// Parse a whole document from a string, using the given `ParseOptions`.
+    ///
+    /// The string being already decoded, `ParseOptions::detect_encoding` only skips a leading
+    /// byte order mark.
+    pub fn parse_with_options(input: &'a str, options: ParseOptions) -> Result<ElementRef<'a>> {
+        let mut parser = Parser {
+            input,
+            pos: 0,
+            construct_start: 0,
+            scanner: Scanner::new(&options),
+            entities: Entities::new(&options),
+            seen_doctype: false,
+            options,
+            stack: Vec::new(),
+            text: None,
+            elements: 0,
+        };
+        parser.parse().map_err(|err| {
+            if !parser.options.record_positions {
+                return err;
+            }
+            let mut tracker = Tracker::default();
+            tracker.advance(&input.as_bytes()[..parser.construct_start]);
+            err.with_position(tracker.position())
+        })
+    }
+
+    /// Returns the local name of this element (that is, without a possible prefix).
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns the namespace of this element.
+    pub fn ns(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Returns the value of the given attribute, named as in `Element::attr`, if it exists,
+    /// else `None`.
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        let (prefix, name) = split_attr_name(name);
+        let namespace = match prefix {
+            None => "",
+            Some("xml") => XML_NS,
+            Some(prefix) => self
+                .prefixes
+                .iter()
+                .find(|(declared, _)| *declared == Some(prefix))
+                .map(|(_, namespace)| &**namespace)
+                .or_else(|| (self.prefix == Some(prefix)).then_some(&*self.namespace))
+                .or_else(|| {
+                    self.attributes
+                        .iter()
+                        .find(|(.., parsed)| *parsed == Some(prefix))
+                        .map(|(namespace, ..)| &**namespace)
+                })?,
+        };
+        self.attr_ns(name, namespace)
+    }
+
+    /// Returns the value of the attribute with the given local name and namespace, if it
+    /// exists, else `None`.
+    pub fn attr_ns(&self, name: &str, namespace: &str) -> Option<&str> {
+        let key = |(ns, local, ..): &AttributeRef| (&**ns, *local).cmp(&(namespace, name));
+        let index = match self.sorted_attributes {
+            Some(ref sorted) => sorted
+                .binary_search_by(|&i| key(&self.attributes[i]))
+                .map(|position| sorted[position]),
+            None => self.attributes.binary_search_by(key),
+        };
+        index.ok().map(|i| &*self.attributes[i].2)
+    }
+
+    /// Returns an iterator over the local names and values of the attributes of this element,
+    /// sorted by namespace and then by name unless `ParseOptions::preserve_attribute_order` has
+    /// been set.
+    pub fn attrs(&self) -> impl Iterator<Item = (&'a str, &str)> {
+        self.attributes
+            .iter()
+            .map(|(_, name, value, _)| (*name, &**value))
+    }
+
+    /// Returns an iterator over the namespaces, local names and values of the attributes of
+    /// this element, as `Element::attrs_ns`.
+    pub fn attrs_ns(&self) -> impl Iterator<Item = (&str, &'a str, &str)> {
+        self.attributes
+            .iter()
+            .map(|(namespace, name, value, _)| (&**namespace, *name, &**value))
+    }
+
+    /// Returns whether the element has the given name and namespace.
+    pub fn is<'b, N: AsRef<str>, NS: Into<NSChoice<'b>>>(&self, name: N, namespace: NS) -> bool {
+        self.name == name.as_ref() && namespace.into().compare(&self.namespace)
+    }
+
+    /// Returns whether the element has the given namespace.
+    pub fn has_ns<'b, NS: Into<NSChoice<'b>>>(&self, namespace: NS) -> bool {
+        namespace.into().compare(&self.namespace)
+    }
+
+    /// Returns an iterator over the nodes of this element.
+    pub fn nodes(&self) -> slice::Iter<NodeRef<'a>> {
+        self.children.iter()
+    }
+
+    /// Returns an iterator over the child elements of this element.
+    pub fn children(&self) -> impl Iterator<Item = &ElementRef<'a>> {
+        self.children.iter().filter_map(NodeRef::as_element)
+    }
+
+    /// Returns an iterator over the text nodes of this element, including CDATA sections.
+    pub fn texts(&self) -> impl Iterator<Item = &str> {
+        self.children
+            .iter()
+            .filter_map(|node| node.as_text().or_else(|| node.as_cdata()))
+    }
+
+    /// Returns an iterator over the comments of this element.
+    pub fn comments(&self) -> impl Iterator<Item = &str> {
+        self.children.iter().filter_map(NodeRef::as_comment)
+    }
+
+    /// Returns the concatenation of all text nodes and CDATA sections of this element.
+    pub fn text(&self) -> String {
+        self.texts().collect()
+    }
+
+    /// Returns the first child element with the given name and namespace, if any.
+    pub fn get_child<'b, N: AsRef<str>, NS: Into<NSChoice<'b>>>(
+        &self,
+        name: N,
+        namespace: NS,
+    ) -> Option<&ElementRef<'a>> {
+        let namespace = namespace.into();
+        self.children()
+            .find(|child| child.is(name.as_ref(), namespace))
+    }
+
+    /// Returns whether a child element with the given name and namespace exists.
+    pub fn has_child<'b, N: AsRef<str>, NS: Into<NSChoice<'b>>>(
+        &self,
+        name: N,
+        namespace: NS,
+    ) -> bool {
+        self.get_child(name, namespace).is_some()
+    }
+
+    /// Copy this element and all of its descendants into an `Element`.
+    pub fn to_owned(&self) -> Element {
+        self.to_owned_with(&mut Interner::default())
+    }
+
+    /// Copy this element, sharing its name and namespace with those of its descendants.
+    fn to_owned_with(&self, interner: &mut Interner) -> Element {
+        let prefixes: BTreeMap<_, _> = self
+            .prefixes
+            .iter()
+            .map(|(prefix, namespace)| (prefix.map(String::from), namespace.to_string()))
+            .collect();
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|(namespace, name, value, prefix)| Attribute {
+                namespace: interner.intern(namespace),
+                name: String::from(*name),
+                value: value.to_string(),
+                prefix: prefix.map(|prefix| interner.intern(prefix)),
+            })
+            .collect();
+        // Duplicates have already been rejected while parsing.
+        let attributes =
+            Attributes::from_list(attributes, self.sorted_attributes.is_some()).unwrap_or_default();
+        let mut element = Element::new(
+            interner.intern(self.name),
+            interner.intern(&self.namespace),
+            Prefixes::from(prefixes),
+            attributes,
+            self.children
+                .iter()
+                .map(|child| child.to_owned_with(interner))
+                .collect(),
+        );
+        element.prefix = self.prefix.map(|prefix| interner.intern(prefix));
+        element
+    }
+
+    /// Apply `mode` to the text nodes of this element only, as `Element::normalize_text_nodes`.
+    fn normalize_text_nodes(&mut self, mode: WhitespaceMode) {
+        let is_whitespace = |c| matches!(c, ' ' | '\t' | '\n' | '\r');
+        match mode {
+            WhitespaceMode::Preserve => return,
+            WhitespaceMode::DropWhitespaceOnly => (),
+            WhitespaceMode::Trim => {
+                for node in &mut self.children {
+                    if let NodeRef::Text(text) = node {
+                        *text = match text {
+                            Cow::Borrowed(text) => Cow::Borrowed(text.trim_matches(is_whitespace)),
+                            Cow::Owned(text) => Cow::Owned(text.trim_matches(is_whitespace).into()),
+                        };
+                    }
+                }
+            }
+        }
+        self.children.retain(|node| match node {
+            NodeRef::Text(text) => !text.chars().all(is_whitespace),
+            _ => true,
+        });
+    }
+}
+
+impl<'a> NodeRef<'a> {
+    /// Returns the element of this node, if it is one.
+    pub fn as_element(&self) -> Option<&ElementRef<'a>> {
+        match self {
+            NodeRef::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    /// Returns the text of this node, if it is a text node.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            NodeRef::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Returns the content of this node, if it is a CDATA section.
+    pub fn as_cdata(&self) -> Option<&str> {
+        match self {
+            NodeRef::CData(cdata) => Some(cdata),
+            _ => None,
+        }
+    }
+
+    /// Returns the content of this node, if it is a comment.
+    pub fn as_comment(&self) -> Option<&str> {
+        match self {
+            NodeRef::Comment(comment) => Some(comment),
+            _ => None,
+        }
+    }
+
+    /// Returns the target and data of this node, if it is a processing instruction.
+    pub fn as_processing_instruction(&self) -> Option<(&'a str, &str)> {
+        match self {
+            NodeRef::ProcessingInstruction { target, data } => Some((target, data)),
+            _ => None,
+        }
+    }
+
+    /// Copy this node into a `Node`.
+    pub fn to_owned(&self) -> Node {
+        self.to_owned_with(&mut Interner::default())
+    }
+
+    fn to_owned_with(&self, interner: &mut Interner) -> Node {
+        match self {
+            NodeRef::Element(element) => Node::Element(element.to_owned_with(interner)),
+            NodeRef::Text(text) => Node::Text(text.to_string()),
+            NodeRef::CData(cdata) => Node::CData(cdata.to_string()),
+            NodeRef::Comment(comment) => Node::Comment(comment.to_string()),
+            NodeRef::ProcessingInstruction { target, data } => Node::ProcessingInstruction {
+                target: String::from(*target),
+                data: data.to_string(),
+            },
+        }
+    }
+}
+
+impl<'a> From<&ElementRef<'a>> for Element {
+    fn from(element: &ElementRef<'a>) -> Element {
+        element.to_owned()
+    }
+}
+
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+fn validate_cdata(s: &str) -> Result<()> {
+    rxml::strings::validate_cdata(s).map_err(|e| rxml::Error::Xml(e).into())
+}
+
+fn invalid_eof(ctx: &'static str) -> Error {
+    rxml::Error::Xml(XmlError::InvalidEof(ctx)).into()
+}
+
+/// Normalize the line endings of `raw`, only copying it if it has any carriage return.
+fn normalize(raw: &str) -> Cow<str> {
+    if !raw.contains('\r') {
+        return Cow::Borrowed(raw);
+    }
+    let mut normalized = String::with_capacity(raw.len());
+    normalize_newlines(raw, &mut normalized);
+    Cow::Owned(normalized)
+}
+
+/// Expand the references of `raw`, and normalize its line endings, or its whitespace if it is
+/// an attribute value.
+fn unescape<'a>(raw: &'a str, attribute: bool, entities: &mut Entities) -> Result<Cow<'a, str>> {
+    let special = |c: char| c == '&' || c == '\r' || (attribute && matches!(c, '\t' | '\n'));
+    if !raw.contains(special) {
+        validate_cdata(raw)?;
+        return Ok(Cow::Borrowed(raw));
+    }
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(i) = rest.find(special) {
+        out.push_str(&rest[..i]);
+        let c = rest[i..].chars().next().unwrap_or_default();
+        rest = &rest[i + 1..];
+        match c {
+            '&' => {
+                let len = rest
+                    .find(';')
+                    .ok_or(rxml::Error::Xml(XmlError::UndeclaredEntity))?;
+                let name = &rest[..len];
+                if attribute && !name.starts_with('#') {
+                    // The whitespace of replacement texts is normalized like the rest of the value.
+                    let mut value = String::new();
+                    entities.resolve(name, &mut value)?;
+                    out.extend(value.chars().map(|c| if is_space(c) { ' ' } else { c }));
+                } else {
+                    entities.resolve(name, &mut out)?;
+                }
+                rest = &rest[len + 1..];
+            }
+            '\r' => {
+                rest = rest.strip_prefix('\n').unwrap_or(rest);
+                out.push(if attribute { ' ' } else { '\n' });
+            }
+            _ => out.push(' '),
+        }
+    }
+    out.push_str(rest);
+    validate_cdata(&out)?;
+    Ok(Cow::Owned(out))
+}
+
+/// Split the qualified name `name` into its prefix and local name.
+fn split_name(name: &str) -> Result<(Option<&str>, &str)> {
+    rxml::strings::validate_name(name).map_err(rxml::Error::Xml)?;
+    let (prefix, local) = match name.split_once(':') {
+        Some((prefix, local)) => (Some(prefix), local),
+        None => (None, name),
+    };
+    if let Some(prefix) = prefix {
+        rxml::strings::validate_ncname(prefix).map_err(rxml::Error::Xml)?;
+    }
+    rxml::strings::validate_ncname(local).map_err(rxml::Error::Xml)?;
+    Ok((prefix, local))
+}
+
+/// Builds an `ElementRef` straight from its source.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    /// Where the construct being parsed starts, which is where errors are reported
+    construct_start: usize,
+    /// Splits the input into constructs, as the `Driver` does
+    scanner: Scanner,
+    /// Entities declared in the DOCTYPE, and whether it has been found
+    entities: Entities,
+    seen_doctype: bool,
+    options: ParseOptions,
+    /// Elements which have been opened but not closed yet
+    stack: Vec<ElementRef<'a>>,
+    /// Text accumulated since the last node which isn't text
+    text: Option<Cow<'a, str>>,
+    /// Number of elements opened so far
+    elements: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(&mut self) -> Result<ElementRef<'a>> {
+        if self.options.detect_encoding {
+            self.pos = self.input.strip_prefix('\u{feff}').map_or(0, |_| 3);
+        }
+        let mut root = None;
+        loop {
+            self.construct_start = self.pos;
+            let rest = &self.input[self.pos..];
+            if rest.is_empty() {
+                break;
+            }
+            let text = self.text.as_ref().map_or(0, |text| text.len());
+            let (construct, len) =
+                match self
+                    .scanner
+                    .scan(rest.as_bytes(), true, self.stack.len(), text)?
+                {
+                    Some(next) => next,
+                    None => break,
+                };
+            self.scanner.reset();
+            self.pos += len;
+            let raw = &rest[..len];
+            match construct {
+                Construct::Text => self.text(raw)?,
+                Construct::CData => {
+                    let cdata = normalize(&raw[9..len - 3]);
+                    validate_cdata(&cdata)?;
+                    if self.options.preserve_cdata {
+                        self.push_node(NodeRef::CData(cdata))?;
+                    } else {
+                        self.push_text(cdata);
+                    }
+                }
+                Construct::Comment => {
+                    let comment = normalize(&raw[4..len - 3]);
+                    validate_cdata(&comment)?;
+                    self.push_node(NodeRef::Comment(comment))?;
+                }
+                Construct::XmlDeclaration => self.xml_declaration(&raw[5..len - 2])?,
+                Construct::ProcessingInstruction => {
+                    self.processing_instruction(&raw[2..len - 2])?
+                }
+                Construct::Doctype => {
+                    if root.is_some() || self.seen_doctype {
+                        return Err(syntax_error("DOCTYPE not allowed here"));
+                    }
+                    self.seen_doctype = true;
+                    self.flush_text()?;
+                    parse_doctype(&raw[DOCTYPE_START.len()..len - 1], &mut self.entities)?;
+                }
+                Construct::Markup => {
+                    // Markup going on until the end of the input may be truncated.
+                    if self.pos == self.input.len() && find_tag_end(raw.as_bytes()) != Some(len - 1)
+                    {
+                        return Err(invalid_eof("in element"));
+                    }
+                    if raw.starts_with("<![CDATA[") {
+                        return Err(syntax_error("CDATA section outside of the root element"));
+                    } else if raw.starts_with("<!") {
+                        return Err(syntax_error("DOCTYPE not allowed here"));
+                    } else if raw.starts_with("</") {
+                        if let Some(element) = self.end_tag(raw)? {
+                            root = Some(element);
+                        }
+                    } else {
+                        if root.is_some() {
+                            return Err(syntax_error("element after the root element"));
+                        }
+                        if let Some(element) = self.start_tag(raw)? {
+                            root = Some(element);
+                        }
+                    }
+                }
+            }
+        }
+        self.construct_start = self.pos;
+        if !self.stack.is_empty() {
+            return Err(invalid_eof("in element"));
+        }
+        root.ok_or(Error::EndOfDocument)
+    }
+
+    /// Process the content of an XML declaration, between `<?xml` and `?>`.
+    fn xml_declaration(&mut self, content: &'a str) -> Result<()> {
+        if self.construct_start > 0 {
+            return Err(syntax_error("XML declaration not at start of document"));
+        }
+        let declaration = parse_xml_declaration(content)?;
+        match declaration.encoding {
+            Some(ref encoding) if !encoding.eq_ignore_ascii_case("utf-8") => {
+                Err(rxml::Error::RestrictedXml("only utf-8 encoding is allowed").into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Process the content of a processing instruction, between `<?` and `?>`.
+    fn processing_instruction(&mut self, content: &'a str) -> Result<()> {
+        let (target, data) = match content.find(is_space) {
+            Some(i) => (&content[..i], content[i..].trim_start_matches(is_space)),
+            None => (content, ""),
+        };
+        rxml::strings::validate_ncname(target).map_err(rxml::Error::Xml)?;
+        if target.eq_ignore_ascii_case("xml") {
+            return Err(syntax_error("reserved processing instruction target"));
+        }
+        let data = normalize(data);
+        validate_cdata(&data)?;
+        self.push_node(NodeRef::ProcessingInstruction { target, data })
+    }
+
+    fn text(&mut self, raw: &'a str) -> Result<()> {
+        if self.stack.is_empty() {
+            // Only whitespace is allowed outside of the root element.
+            if !raw.chars().all(is_space) {
+                return Err(syntax_error("text outside of the root element"));
+            }
+            return Ok(());
+        }
+        if raw.contains("]]>") {
+            return Err(syntax_error("']]>' in text"));
+        }
+        let text = unescape(raw, false, &mut self.entities)?;
+        self.push_text(text);
+        Ok(())
+    }
+
+    fn push_text(&mut self, text: Cow<'a, str>) {
+        match self.text {
+            Some(ref mut previous) => previous.to_mut().push_str(&text),
+            None => self.text = Some(text),
+        }
+    }
+
+    /// Add the accumulated text to the element being parsed.
+    fn flush_text(&mut self) -> Result<()> {
+        if let Some(text) = self.text.take() {
+            if text.len() > self.options.limits.max_text_size {
+                return Err(Error::LimitExceeded(Limit::TextSize));
+            }
+            if let Some(top) = self.stack.last_mut() {
+                top.children.push(NodeRef::Text(text));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a node to the element being parsed, dropping it outside of the root element.
+    fn push_node(&mut self, node: NodeRef<'a>) -> Result<()> {
+        self.flush_text()?;
+        if let Some(top) = self.stack.last_mut() {
+            top.children.push(node);
+        }
+        Ok(())
+    }
+
+    /// Parse the start tag `tag`, returning the root element if it is an empty-element tag
+    /// closing it.
+    fn start_tag(&mut self, tag: &'a str) -> Result<Option<ElementRef<'a>>> {
+        let tag = &tag[1..tag.len() - 1];
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_len = tag.find(is_space).unwrap_or(tag.len());
+        let (prefix, name) = split_name(&tag[..name_len])?;
+
+        let limits = &self.options.limits;
+        if self.stack.len() >= limits.max_depth {
+            return Err(Error::LimitExceeded(Limit::Depth));
+        }
+        if self.elements >= limits.max_elements {
+            return Err(Error::LimitExceeded(Limit::Elements));
+        }
+        self.elements += 1;
+
+        let mut prefixes = Vec::new();
+        let mut attributes = Vec::new();
+        let mut rest = &tag[name_len..];
+        loop {
+            let trimmed = rest.trim_start_matches(is_space);
+            if trimmed.is_empty() {
+                break;
+            }
+            if trimmed.len() == rest.len() {
+                return Err(syntax_error("missing whitespace before attribute"));
+            }
+            let name_len = trimmed
+                .find(|c| c == '=' || is_space(c))
+                .unwrap_or(trimmed.len());
+            let (qname, after) = trimmed.split_at(name_len);
+            let value = after
+                .trim_start_matches(is_space)
+                .strip_prefix('=')
+                .map(|value| value.trim_start_matches(is_space))
+                .ok_or_else(|| syntax_error("missing '=' after attribute name"))?;
+            let quote = match value.chars().next() {
+                Some(quote @ ('\'' | '"')) => quote,
+                _ => return Err(syntax_error("unquoted attribute value")),
+            };
+            let value = &value[1..];
+            let len = value
+                .find(quote)
+                .ok_or_else(|| syntax_error("unterminated attribute value"))?;
+            rest = &value[len + 1..];
+            let raw = &value[..len];
+            if raw.contains('<') {
+                return Err(syntax_error("'<' in attribute value"));
+            }
+            let value = unescape(raw, true, &mut self.entities)?;
+
+            let declared = match split_name(qname)? {
+                (None, "xmlns") => Some(None),
+                (Some("xmlns"), _) if value.is_empty() => {
+                    return Err(rxml::Error::Xml(XmlError::EmptyNamespaceUri).into())
+                }
+                (Some("xmlns"), prefix) => Some(Some(prefix)),
+                _ => None,
+            };
+            let duplicate = match declared {
+                Some(prefix) => {
+                    if prefixes.len() >= limits.max_namespace_declarations {
+                        return Err(Error::LimitExceeded(Limit::NamespaceDeclarations));
+                    }
+                    let duplicate = prefixes.iter().any(|(declared, _)| *declared == prefix);
+                    prefixes.push((prefix, value));
+                    duplicate
+                }
+                None => {
+                    if attributes.len() >= limits.max_attributes {
+                        return Err(Error::LimitExceeded(Limit::Attributes));
+                    }
+                    attributes.push((split_name(qname)?, value));
+                    false
+                }
+            };
+            if duplicate {
+                return Err(rxml::Error::Xml(XmlError::DuplicateAttribute).into());
+            }
+        }
+
+        let namespace = prefixes
+            .iter()
+            .find(|(declared, _)| *declared == prefix)
+            .map(|(_, namespace)| namespace.clone())
+            .or_else(|| self.lookup_prefix(prefix));
+        let namespace = match namespace {
+            Some(namespace) => namespace,
+            None if prefix.is_none() && self.options.allow_missing_namespace => Cow::Borrowed(""),
+            None => return Err(Error::MissingNamespace),
+        };
+
+        // Prefixes of attributes are resolved once all the declarations of the tag are known.
+        let mut attributes = attributes
+            .into_iter()
+            .map(|((prefix, name), value)| {
+                let namespace = match prefix {
+                    None => Cow::Borrowed(""),
+                    Some("xml") => Cow::Borrowed(XML_NS),
+                    Some(prefix) => prefixes
+                        .iter()
+                        .find(|(declared, _)| *declared == Some(prefix))
+                        .map(|(_, namespace)| namespace.clone())
+                        .or_else(|| self.lookup_prefix(Some(prefix)))
+                        .ok_or(Error::MissingNamespace)?,
+                };
+                Ok((namespace, name, value, prefix))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let key = |i: usize| (&attributes[i].0, attributes[i].1);
+        let mut sorted: Vec<_> = (0..attributes.len()).collect();
+        sorted.sort_unstable_by(|&a, &b| key(a).cmp(&key(b)));
+        if sorted.windows(2).any(|pair| key(pair[0]) == key(pair[1])) {
+            return Err(rxml::Error::Xml(XmlError::DuplicateAttribute).into());
+        }
+        let sorted_attributes = if self.options.preserve_attribute_order {
+            Some(sorted)
+        } else {
+            attributes
+                .sort_by(|(ns1, name1, ..), (ns2, name2, ..)| (ns1, name1).cmp(&(ns2, name2)));
+            None
+        };
+
+        self.flush_text()?;
+        self.stack.push(ElementRef {
+            prefix,
+            name,
+            namespace,
+            prefixes,
+            attributes,
+            sorted_attributes,
+            children: Vec::new(),
+        });
+        if empty {
+            self.close()
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parse the end tag `tag`, returning the root element if it has been closed.
+    fn end_tag(&mut self, tag: &str) -> Result<Option<ElementRef<'a>>> {
+        let qname = tag[2..tag.len() - 1].trim_end_matches(is_space);
+        let matches = match self.stack.last() {
+            Some(top) => match top.prefix {
+                Some(prefix) => qname.split_once(':') == Some((prefix, top.name)),
+                None => qname == top.name,
+            },
+            None => false,
+        };
+        if !matches {
+            return Err(rxml::Error::Xml(XmlError::ElementMismatch).into());
+        }
+        self.close()
+    }
+
+    /// Close the element on top of the stack, returning it if it is the root element.
+    fn close(&mut self) -> Result<Option<ElementRef<'a>>> {
+        self.flush_text()?;
+        let mut element = match self.stack.pop() {
+            Some(element) => element,
+            None => return Ok(None),
+        };
+        let mode = self.options.whitespace;
+        let preserve = std::iter::once(&element)
+            .chain(self.stack.iter().rev())
+            .find_map(|element| element.attr("xml:space"))
+            == Some("preserve");
+        if mode != WhitespaceMode::Preserve && !preserve {
+            element.normalize_text_nodes(mode);
+        }
+        match self.stack.last_mut() {
+            Some(parent) => {
+                parent.children.push(NodeRef::Element(element));
+                Ok(None)
+            }
+            None => Ok(Some(element)),
+        }
+    }
+
+    fn lookup_prefix(&self, prefix: Option<&str>) -> Option<Cow<'a, str>> {
+        self.stack.iter().rev().find_map(|element| {
+            element
+                .prefixes
+                .iter()
+                .find(|(declared, _)| *declared == prefix)
+                .map(|(_, namespace)| namespace.clone())
+        })
+    }
+}
@@ -0,0 +1,487 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Detection of the encoding of documents, and transcoding from and to UTF-8.
+
+use crate::driver::parse_xml_declaration;
+use crate::error::{Error, Result};
+use crate::tree_builder::Limit;
+
+use rxml::error::XmlError;
+
+use std::io::{self, Write};
+use std::str;
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const UTF16BE_BOM: &[u8] = b"\xfe\xff";
+const UTF16LE_BOM: &[u8] = b"\xff\xfe";
+
+/// An encoding documents can be read from and written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Utf8,
+    /// UTF-16 starting with a byte order mark, written in big endian
+    Utf16,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Ascii,
+}
+
+impl Encoding {
+    /// Find an encoding from its name, as declared in an XML declaration.
+    pub(crate) fn from_name(name: &str) -> Option<Encoding> {
+        let name = name.to_ascii_lowercase();
+        Some(match name.as_str() {
+            "utf-8" | "utf8" => Encoding::Utf8,
+            "utf-16" | "utf16" => Encoding::Utf16,
+            "utf-16le" => Encoding::Utf16Le,
+            "utf-16be" => Encoding::Utf16Be,
+            "iso-8859-1" | "iso_8859-1" | "latin1" | "l1" => Encoding::Latin1,
+            "us-ascii" | "ascii" => Encoding::Ascii,
+            _ => return None,
+        })
+    }
+
+    /// Whether a document found to be in this encoding may declare `declared`.
+    pub(crate) fn accepts(self, declared: Encoding) -> bool {
+        match self {
+            Encoding::Utf16 | Encoding::Utf16Le | Encoding::Utf16Be => matches!(
+                declared,
+                Encoding::Utf16 | Encoding::Utf16Le | Encoding::Utf16Be
+            ),
+            // Whatever was declared has been decoded from.
+            Encoding::Latin1 | Encoding::Ascii => true,
+            Encoding::Utf8 => matches!(declared, Encoding::Utf8 | Encoding::Ascii),
+        }
+    }
+
+    /// Whether `c` can be written in this encoding.
+    fn can_encode(self, c: char) -> bool {
+        match self {
+            Encoding::Latin1 => c as u32 <= 0xff,
+            Encoding::Ascii => c.is_ascii(),
+            Encoding::Utf8 | Encoding::Utf16 | Encoding::Utf16Le | Encoding::Utf16Be => true,
+        }
+    }
+
+    /// Append `c` encoded in this encoding to `out`, returning whether it can be encoded.
+    fn encode_char(self, c: char, out: &mut Vec<u8>) -> bool {
+        if !self.can_encode(c) {
+            return false;
+        }
+        let mut buf = [0; 2];
+        match self {
+            Encoding::Utf8 => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            Encoding::Utf16 | Encoding::Utf16Be => {
+                for unit in c.encode_utf16(&mut buf) {
+                    out.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+            Encoding::Utf16Le => {
+                for unit in c.encode_utf16(&mut buf) {
+                    out.extend_from_slice(&unit.to_le_bytes());
+                }
+            }
+            Encoding::Latin1 | Encoding::Ascii => out.push(c as u8),
+        }
+        true
+    }
+}
+
+/// Where the character being written is in the document, which tells whether it can be
+/// replaced with a character reference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Context {
+    Text,
+    /// After `<`, with what follows it so far while the kind of markup isn't known
+    MarkupStart(String),
+    Tag {
+        quote: Option<char>,
+    },
+    Comment,
+    CData,
+    ProcessingInstruction,
+    Doctype {
+        in_subset: bool,
+        quote: Option<char>,
+    },
+}
+
+/// Writes the UTF-8 output of an `ItemWriter` in another encoding as it comes.
+///
+/// Characters which can't be encoded are written as character references in text and
+/// attribute values, and CDATA sections are split around them. Anywhere else, writing fails
+/// and the character is kept, to be reported as `Error::UnrepresentableChar`.
+pub(crate) struct EncodedWriter<W> {
+    writer: W,
+    encoding: Encoding,
+    context: Context,
+    /// The two characters written before the current one, to find the end of markup
+    last: [char; 2],
+    /// The start of a UTF-8 sequence which hasn't been written whole yet
+    pending: Vec<u8>,
+    /// The encoded output of the current write
+    out: Vec<u8>,
+    unrepresentable: Option<char>,
+}
+
+impl<W: Write> EncodedWriter<W> {
+    pub(crate) fn new(writer: W, encoding: Encoding) -> Self {
+        let mut out = Vec::new();
+        if encoding == Encoding::Utf16 {
+            out.extend_from_slice(UTF16BE_BOM);
+        }
+        EncodedWriter {
+            writer,
+            encoding,
+            context: Context::Text,
+            last: ['\0'; 2],
+            pending: Vec::new(),
+            out,
+            unrepresentable: None,
+        }
+    }
+
+    /// The character writing has failed on, if any.
+    pub(crate) fn unrepresentable(&self) -> Option<char> {
+        self.unrepresentable
+    }
+
+    fn encode_str(&mut self, s: &str) -> io::Result<()> {
+        for c in s.chars() {
+            self.encode(c)?;
+            self.last = [self.last[1], c];
+        }
+        Ok(())
+    }
+
+    fn encode(&mut self, c: char) -> io::Result<()> {
+        let escapable = match self.context {
+            Context::Text => {
+                if c == '<' {
+                    self.context = Context::MarkupStart(String::new());
+                }
+                c != '<'
+            }
+            Context::MarkupStart(ref mut seen) => {
+                seen.push(c);
+                self.context = match seen.as_str() {
+                    "?" => Context::ProcessingInstruction,
+                    "!--" => Context::Comment,
+                    "![CDATA[" => Context::CData,
+                    seen if "!--".starts_with(seen) || "![CDATA[".starts_with(seen) => {
+                        return self.encode_strict(c)
+                    }
+                    seen if seen.starts_with('!') => Context::Doctype {
+                        in_subset: false,
+                        quote: None,
+                    },
+                    _ => Context::Tag { quote: None },
+                };
+                false
+            }
+            Context::Tag { ref mut quote } => match (*quote, c) {
+                (Some(q), c) if q == c => {
+                    *quote = None;
+                    false
+                }
+                (Some(_), _) => true,
+                (None, '"' | '\'') => {
+                    *quote = Some(c);
+                    false
+                }
+                (None, '>') => {
+                    self.context = Context::Text;
+                    false
+                }
+                (None, _) => false,
+            },
+            Context::Comment => {
+                if c == '>' && self.last == ['-', '-'] {
+                    self.context = Context::Text;
+                }
+                false
+            }
+            Context::CData => {
+                if c == '>' && self.last == [']', ']'] {
+                    self.context = Context::Text;
+                } else if !self.encoding.can_encode(c) {
+                    // Character references aren't recognized in CDATA sections, so the section
+                    // is closed around it.
+                    self.out.extend(self.encode_ascii("]]>"));
+                    self.escape(c);
+                    self.out.extend(self.encode_ascii("<![CDATA["));
+                    return Ok(());
+                }
+                false
+            }
+            Context::ProcessingInstruction => {
+                if c == '>' && self.last[1] == '?' {
+                    self.context = Context::Text;
+                }
+                false
+            }
+            Context::Doctype {
+                ref mut in_subset,
+                ref mut quote,
+            } => {
+                match (*quote, c) {
+                    (Some(q), c) if q == c => *quote = None,
+                    (Some(_), _) => (),
+                    (None, '"' | '\'') => *quote = Some(c),
+                    (None, '[') => *in_subset = true,
+                    (None, ']') => *in_subset = false,
+                    (None, '>') if !*in_subset => self.context = Context::Text,
+                    (None, _) => (),
+                }
+                false
+            }
+        };
+        if escapable {
+            if !self.encoding.encode_char(c, &mut self.out) {
+                self.escape(c);
+            }
+            Ok(())
+        } else {
+            self.encode_strict(c)
+        }
+    }
+
+    /// Write `c`, failing if it can't be encoded.
+    fn encode_strict(&mut self, c: char) -> io::Result<()> {
+        if self.encoding.encode_char(c, &mut self.out) {
+            return Ok(());
+        }
+        self.unrepresentable = Some(c);
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "character can't be written in the declared encoding",
+        ))
+    }
+
+    /// Write `c` as a character reference.
+    fn escape(&mut self, c: char) {
+        let reference = format!("&#x{:X};", c as u32);
+        let encoded = self.encode_ascii(&reference);
+        self.out.extend(encoded);
+    }
+
+    fn encode_ascii(&self, s: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        for c in s.chars() {
+            self.encoding.encode_char(c, &mut out);
+        }
+        out
+    }
+}
+
+impl<W: Write> Write for EncodedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8");
+        let mut input = buf;
+        // Complete the character the previous write ended in the middle of.
+        while !self.pending.is_empty() && !input.is_empty() {
+            self.pending.push(input[0]);
+            input = &input[1..];
+            match str::from_utf8(&self.pending) {
+                Ok(s) => {
+                    let c = s.chars().next().unwrap_or_default();
+                    self.pending.clear();
+                    self.encode(c)?;
+                    self.last = [self.last[1], c];
+                }
+                Err(err) if err.error_len().is_none() => (),
+                Err(_) => return Err(invalid()),
+            }
+        }
+        let valid = match str::from_utf8(input) {
+            Ok(s) => s,
+            Err(err) if err.error_len().is_none() => {
+                self.pending.extend_from_slice(&input[err.valid_up_to()..]);
+                str::from_utf8(&input[..err.valid_up_to()]).map_err(|_| invalid())?
+            }
+            Err(_) => return Err(invalid()),
+        };
+        self.encode_str(valid)?;
+        self.writer.write_all(&self.out)?;
+        self.out.clear();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.out)?;
+        self.out.clear();
+        self.writer.flush()
+    }
+}
+
+/// Turns the bytes of a document into UTF-8, once their encoding has been found from its byte
+/// order mark or XML declaration.
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    /// The encoding being decoded, once known
+    encoding: Option<Encoding>,
+    /// Bytes which have been fed but not decoded yet
+    pending: Vec<u8>,
+    /// A high surrogate waiting for the low surrogate following it
+    high_surrogate: Option<u16>,
+    /// Longest XML declaration searched for its encoding
+    max_declaration_size: usize,
+    /// How far the end of the XML declaration has been searched for
+    scanned: usize,
+}
+
+impl Decoder {
+    /// Create a decoder giving up on XML declarations longer than `max_declaration_size`.
+    pub(crate) fn new(max_declaration_size: usize) -> Self {
+        Decoder {
+            encoding: None,
+            pending: Vec::new(),
+            high_surrogate: None,
+            max_declaration_size,
+            scanned: 0,
+        }
+    }
+
+    /// The encoding the document has been found to be in, if known yet.
+    pub(crate) fn encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
+    /// How many bytes haven't been decoded yet.
+    pub(crate) fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the bytes which haven't been decoded yet, a high surrogate waiting for the rest
+    /// of its pair included.
+    pub(crate) fn into_pending(self) -> Vec<u8> {
+        let high_surrogate = match (self.high_surrogate, self.encoding) {
+            (Some(unit), Some(Encoding::Utf16Le)) => unit.to_le_bytes(),
+            (Some(unit), _) => unit.to_be_bytes(),
+            (None, _) => return self.pending,
+        };
+        let mut pending = high_surrogate.to_vec();
+        pending.extend(self.pending);
+        pending
+    }
+
+    /// Add bytes to be decoded.
+    pub(crate) fn feed(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+    }
+
+    /// Decode as much as possible of the bytes fed so far, appending the result to `out`.
+    pub(crate) fn decode(&mut self, out: &mut Vec<u8>, eof: bool) -> Result<()> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => match self.detect(eof)? {
+                Some(encoding) => encoding,
+                None => return Ok(()),
+            },
+        };
+        let len = match encoding {
+            // Validating UTF-8 is left to the driver.
+            Encoding::Utf8 => {
+                out.extend_from_slice(&self.pending);
+                self.pending.len()
+            }
+            Encoding::Utf16 | Encoding::Utf16Be => self.decode_utf16(u16::from_be_bytes, out)?,
+            Encoding::Utf16Le => self.decode_utf16(u16::from_le_bytes, out)?,
+            Encoding::Latin1 | Encoding::Ascii => {
+                let mut buf = [0; 4];
+                for &b in &self.pending {
+                    if encoding == Encoding::Ascii && !b.is_ascii() {
+                        return Err(rxml::Error::InvalidChar(b.into()).into());
+                    }
+                    out.extend_from_slice(char::from(b).encode_utf8(&mut buf).as_bytes());
+                }
+                self.pending.len()
+            }
+        };
+        self.pending.drain(..len);
+        if eof && (!self.pending.is_empty() || self.high_surrogate.is_some()) {
+            return Err(XmlError::InvalidEof("in encoded character").into());
+        }
+        Ok(())
+    }
+
+    /// Decode the complete UTF-16 code units of `pending`, returning how many bytes have been
+    /// used.
+    fn decode_utf16(&mut self, from_bytes: fn([u8; 2]) -> u16, out: &mut Vec<u8>) -> Result<usize> {
+        let mut buf = [0; 4];
+        let units = self.pending.chunks_exact(2);
+        let len = self.pending.len() - units.remainder().len();
+        for unit in units {
+            let unit = from_bytes([unit[0], unit[1]]);
+            let c = match (self.high_surrogate.take(), unit) {
+                (None, 0xd800..=0xdbff) => {
+                    self.high_surrogate = Some(unit);
+                    continue;
+                }
+                (Some(high), 0xdc00..=0xdfff) => char::from_u32(
+                    0x10000 + ((u32::from(high) - 0xd800) << 10) + (u32::from(unit) - 0xdc00),
+                ),
+                (Some(high), _) => return Err(rxml::Error::InvalidChar(high.into()).into()),
+                (None, _) => char::from_u32(unit.into()),
+            };
+            let c = c.ok_or(rxml::Error::InvalidChar(unit.into()))?;
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+        Ok(len)
+    }
+
+    /// Find the encoding from the first bytes of the document, skipping its byte order mark.
+    fn detect(&mut self, eof: bool) -> Result<Option<Encoding>> {
+        let start = &self.pending[..];
+        if !eof && (start.len() < 4 || b"<?xml".starts_with(start)) {
+            return Ok(None);
+        }
+        let (encoding, bom) = if start.starts_with(UTF8_BOM) {
+            (Encoding::Utf8, UTF8_BOM.len())
+        } else if start.starts_with(UTF16BE_BOM) {
+            (Encoding::Utf16Be, UTF16BE_BOM.len())
+        } else if start.starts_with(UTF16LE_BOM) {
+            (Encoding::Utf16Le, UTF16LE_BOM.len())
+        } else if start.starts_with(b"\0<\0?") {
+            (Encoding::Utf16Be, 0)
+        } else if start.starts_with(b"<\0?\0") {
+            (Encoding::Utf16Le, 0)
+        } else if start.starts_with(b"<?xml") {
+            // Resume the search where the previous one stopped, the `?` of a `?>` split
+            // between two feeds included.
+            let from = self.scanned.saturating_sub(1);
+            let end = match start[from..].windows(2).position(|window| window == b"?>") {
+                Some(end) => from + end,
+                None if eof => return Ok(Some(self.detected(Encoding::Utf8, 0))),
+                None if start.len() > self.max_declaration_size => {
+                    return Err(Error::LimitExceeded(Limit::TokenSize))
+                }
+                None => {
+                    self.scanned = start.len();
+                    return Ok(None);
+                }
+            };
+            // Errors in the declaration are left for the driver to report.
+            let encoding = str::from_utf8(&start[5..end])
+                .ok()
+                .and_then(|content| parse_xml_declaration(content).ok())
+                .and_then(|declaration| declaration.encoding)
+                .and_then(|name| Encoding::from_name(&name))
+                .filter(|encoding| matches!(encoding, Encoding::Latin1 | Encoding::Ascii))
+                .unwrap_or(Encoding::Utf8);
+            (encoding, 0)
+        } else {
+            (Encoding::Utf8, 0)
+        };
+        Ok(Some(self.detected(encoding, bom)))
+    }
+
+    fn detected(&mut self, encoding: Encoding, bom: usize) -> Encoding {
+        self.pending.drain(..bom);
+        self.encoding = Some(encoding);
+        encoding
+    }
+}
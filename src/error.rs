@@ -11,6 +11,9 @@
 
 //! Provides an error type for this crate.
 
+use crate::position::Position;
+use crate::tree_builder::Limit;
+
 use std::convert::From;
 use std::error::Error as StdError;
 
@@ -32,6 +35,76 @@ pub enum Error {
 
     /// An error which is returned when a prefixed is defined twice
     DuplicatePrefix,
+
+    /// An error which is returned when a document goes over one of its `ParseLimits`.
+    LimitExceeded(Limit),
+
+    /// An error which is returned when a document is declared in an encoding which isn't
+    /// supported, when reading or writing it.
+    UnsupportedEncoding(String),
+
+    /// An error which is returned when a character can't be written in the encoding declared by
+    /// a document.
+    UnrepresentableChar(char),
+
+    /// An error which is returned when an element being written has a name which isn't a valid
+    /// XML name without a prefix.
+    InvalidElementName(String),
+
+    /// An error which is returned when an attribute being written has a local name which isn't a
+    /// valid XML name without a prefix.
+    InvalidAttributeName(String),
+
+    /// An error which is returned when an element being written declares a prefix which can't
+    /// be declared, like `xmlns`, or binds a prefix to a reserved namespace.
+    InvalidNamespaceDeclaration(Option<String>, String),
+
+    /// An error which is returned when items are written in an order which doesn't make a
+    /// well-formed document, like content after the end of the root element.
+    Encode(rxml::writer::EncodeError),
+
+    /// Any other error, found at the given position of the document being parsed. Parsing
+    /// functions return all of their errors this way when `ParseOptions::record_positions` is
+    /// set, and never otherwise.
+    Positioned(Position, Box<Error>),
+}
+
+impl Error {
+    /// Returns where this error has been found in the document being parsed, if known.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Error::Positioned(position, _) => Some(*position),
+            _ => None,
+        }
+    }
+
+    /// Returns this error without the position it has been found at.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, Error, ParseOptions};
+    ///
+    /// let xml = b"<a xmlns='ns1'>\n<b></c></a>";
+    /// let options = ParseOptions::new().record_positions(true);
+    /// let err = Element::from_reader_with_options(&xml[..], options).unwrap_err();
+    ///
+    /// assert_eq!(err.position().unwrap().line, 2);
+    /// assert!(matches!(err.without_position(), Error::XmlError(_)));
+    /// ```
+    pub fn without_position(&self) -> &Error {
+        match self {
+            Error::Positioned(_, err) => err,
+            err => err,
+        }
+    }
+
+    pub(crate) fn with_position(self, position: Position) -> Error {
+        match self {
+            Error::Positioned(..) => self,
+            err => Error::Positioned(position, Box::new(err)),
+        }
+    }
 }
 
 impl StdError for Error {
@@ -42,6 +115,14 @@ impl StdError for Error {
             Error::InvalidPrefix => None,
             Error::MissingNamespace => None,
             Error::DuplicatePrefix => None,
+            Error::LimitExceeded(_) => None,
+            Error::UnsupportedEncoding(_) => None,
+            Error::UnrepresentableChar(_) => None,
+            Error::InvalidElementName(_) => None,
+            Error::InvalidAttributeName(_) => None,
+            Error::InvalidNamespaceDeclaration(..) => None,
+            Error::Encode(e) => Some(e),
+            Error::Positioned(_, e) => Some(e),
         }
     }
 }
@@ -56,6 +137,29 @@ impl std::fmt::Display for Error {
             Error::InvalidPrefix => write!(fmt, "the prefix is invalid"),
             Error::MissingNamespace => write!(fmt, "the XML element is missing a namespace",),
             Error::DuplicatePrefix => write!(fmt, "the prefix is already defined"),
+            Error::LimitExceeded(limit) => write!(fmt, "the {} limit has been exceeded", limit),
+            Error::UnsupportedEncoding(name) => {
+                write!(fmt, "the {} encoding is not supported", name)
+            }
+            Error::UnrepresentableChar(c) => {
+                write!(fmt, "{:?} can't be written in the declared encoding", c)
+            }
+            Error::InvalidElementName(name) => {
+                write!(fmt, "{:?} is not a valid element name", name)
+            }
+            Error::InvalidAttributeName(name) => {
+                write!(fmt, "{:?} is not a valid attribute name", name)
+            }
+            Error::InvalidNamespaceDeclaration(Some(prefix), namespace) => write!(
+                fmt,
+                "the {} prefix can't be bound to {:?}",
+                prefix, namespace
+            ),
+            Error::InvalidNamespaceDeclaration(None, namespace) => {
+                write!(fmt, "the default namespace can't be {:?}", namespace)
+            }
+            Error::Encode(e) => write!(fmt, "encoding error: {}", e),
+            Error::Positioned(position, e) => write!(fmt, "{} at {}", e, position),
         }
     }
 }
@@ -66,6 +170,12 @@ impl From<rxml::Error> for Error {
     }
 }
 
+impl From<rxml::writer::EncodeError> for Error {
+    fn from(err: rxml::writer::EncodeError) -> Error {
+        Error::Encode(err)
+    }
+}
+
 impl From<rxml::error::XmlError> for Error {
     fn from(err: rxml::error::XmlError) -> Error {
         Error::XmlError(err.into())
@@ -0,0 +1,31 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Sharing of the names and namespaces found while building a tree.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// How many different strings are kept, so that a long stream of random names doesn't make the
+/// interner grow forever.
+const MAX_STRINGS: usize = 1024;
+
+/// Gives out the same `Arc<str>` for every occurrence of a string.
+#[derive(Debug, Default)]
+pub(crate) struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub(crate) fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(shared) = self.strings.get(s) {
+            return Arc::clone(shared);
+        }
+        let shared = Arc::<str>::from(s);
+        if self.strings.len() < MAX_STRINGS {
+            self.strings.insert(Arc::clone(&shared));
+        }
+        shared
+    }
+}
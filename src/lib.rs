@@ -75,19 +75,42 @@
 //! minidom = "*"
 //! ```
 
+mod attributes;
+pub mod canonical;
 pub mod convert;
+pub mod cursor;
+mod doctype;
+pub mod document;
+mod driver;
 pub mod element;
+pub mod element_ref;
+mod encoding;
 pub mod error;
+mod interner;
+mod namespace_tracker;
 mod namespaces;
 pub mod node;
+pub mod position;
 mod prefixes;
+pub mod push_parser;
+pub mod stanza_reader;
 pub mod tree_builder;
+pub mod writer;
 
 #[cfg(test)]
 mod tests;
 
+pub use canonical::Canonicalization;
 pub use convert::IntoAttributeValue;
+pub use cursor::{Cursor, CursorMut};
+pub use document::{Doctype, Document, XmlDeclaration};
 pub use element::{Children, ChildrenMut, Element, ElementBuilder};
+pub use element_ref::{ElementRef, NodeRef};
 pub use error::{Error, Result};
 pub use namespaces::NSChoice;
 pub use node::Node;
+pub use position::{Position, Span};
+pub use push_parser::PushParser;
+pub use stanza_reader::StanzaReader;
+pub use tree_builder::{Limit, ParseLimits, ParseOptions, WhitespaceMode};
+pub use writer::{StreamWriter, WriteOptions};
@@ -0,0 +1,262 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides `ScopedNamespaces`, which keeps track of the namespace declarations in scope while
+//! writing, so that prefixes can be reused.
+
+use rxml::parser::NamespaceName;
+use rxml::writer::{PrefixError, TrackNamespace, PREFIX_XML, PREFIX_XMLNS};
+use rxml::{NcName, NcNameStr, XMLNS_XML, XMLNS_XMLNS};
+
+use std::collections::HashSet;
+use std::mem;
+
+/// The declarations made on an element being written.
+#[derive(Debug)]
+struct Scope {
+    /// The default namespace, inherited from the parent if not declared
+    default: Option<NamespaceName>,
+    prefixes: Vec<(Option<NamespaceName>, NcName)>,
+}
+
+/// Where a prefix declaration is stored: the index of its scope, or `None` for the element being
+/// started, and its index among the declarations there.
+type Location = (Option<usize>, usize);
+
+/// A namespace tracker remembering the declarations of every open element, unlike rxml's
+/// `SimpleNamespaces` which forgets prefixes declared below the root.
+///
+/// Elements and attributes reuse the prefixes which are in scope for their namespace, and the
+/// prefix an element or attribute has been parsed with is preferred over other ones.
+#[derive(Debug, Default)]
+pub struct ScopedNamespaces {
+    /// Declarations of the open elements, innermost last
+    scopes: Vec<Scope>,
+    /// Default namespace declared on the element being started
+    next_default: Option<Option<NamespaceName>>,
+    /// Prefixes declared on the element being started
+    next_prefixes: Vec<(Option<NamespaceName>, NcName)>,
+    /// Prefix to use for the next element or attribute if it is bound to its namespace
+    preferred_prefix: Option<NcName>,
+    /// How many prefixes have been generated so far
+    auto_prefixes: usize,
+}
+
+impl ScopedNamespaces {
+    /// Create a tracker without any declaration.
+    pub fn new() -> ScopedNamespaces {
+        ScopedNamespaces::default()
+    }
+
+    /// Use `prefix` for the next element or attribute if it is in scope and bound to its
+    /// namespace.
+    pub(crate) fn prefer_prefix(&mut self, prefix: Option<NcName>) {
+        self.preferred_prefix = prefix;
+    }
+
+    /// Every prefix declaration in scope with where it is stored, innermost first.
+    fn locations(&self) -> impl Iterator<Item = (Location, &(Option<NamespaceName>, NcName))> {
+        let next = self
+            .next_prefixes
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, binding)| ((None, i), binding));
+        let scopes = self.scopes.iter().enumerate().rev().flat_map(|(s, scope)| {
+            scope
+                .prefixes
+                .iter()
+                .enumerate()
+                .rev()
+                .map(move |(i, binding)| ((Some(s), i), binding))
+        });
+        next.chain(scopes)
+    }
+
+    /// Every prefix declaration in scope, innermost first.
+    fn bindings(&self) -> impl Iterator<Item = &(Option<NamespaceName>, NcName)> {
+        self.locations().map(|(_, binding)| binding)
+    }
+
+    /// Returns the namespace `prefix` is bound to.
+    fn namespace_of(&self, prefix: &NcNameStr) -> Option<&Option<NamespaceName>> {
+        self.bindings()
+            .find(|(_, declared)| **declared == *prefix)
+            .map(|(namespace, _)| namespace)
+    }
+
+    /// Returns where the innermost prefix bound to `name` is declared, skipping the prefixes
+    /// shadowed by another declaration of the same prefix.
+    fn locate_prefix_of(&self, name: &Option<NamespaceName>) -> Option<Location> {
+        let mut shadowed = HashSet::new();
+        self.locations()
+            .find_map(|(location, (namespace, prefix))| {
+                (shadowed.insert(&**prefix) && namespace == name).then_some(location)
+            })
+    }
+
+    /// Returns where the preferred prefix is declared, if it is bound to `name`.
+    fn locate_preferred_prefix(&mut self, name: &Option<NamespaceName>) -> Option<Location> {
+        let prefix = self.preferred_prefix.take()?;
+        self.locations()
+            .find(|(_, (_, declared))| *declared == prefix)
+            .and_then(|(location, (namespace, _))| (namespace == name).then_some(location))
+    }
+
+    /// Returns the prefix declared at `location`.
+    fn prefix_at(&self, (scope, i): Location) -> &NcNameStr {
+        match scope {
+            Some(scope) => &self.scopes[scope].prefixes[i].1,
+            None => &self.next_prefixes[i].1,
+        }
+    }
+
+    /// Returns the innermost prefix bound to `name`, which isn't shadowed by another
+    /// declaration of the same prefix.
+    fn prefix_of(&self, name: &Option<NamespaceName>) -> Option<&NcNameStr> {
+        self.locate_prefix_of(name)
+            .map(|location| self.prefix_at(location))
+    }
+
+    fn current_default(&self) -> Option<&NamespaceName> {
+        match self.next_default {
+            Some(ref default) => default.as_ref(),
+            None => self.scopes.last().and_then(|scope| scope.default.as_ref()),
+        }
+    }
+
+    /// Declare a new prefix for `name` on the element being started.
+    fn declare_new_prefix(&mut self, name: Option<NamespaceName>) -> &NcNameStr {
+        let prefix = loop {
+            let prefix: NcName = match format!("tns{}", self.auto_prefixes).try_into() {
+                Ok(prefix) => prefix,
+                Err(_) => unreachable!("generated prefixes are valid names"),
+            };
+            self.auto_prefixes += 1;
+            if self.namespace_of(&prefix).is_none() {
+                break prefix;
+            }
+        };
+        self.next_prefixes.push((name, prefix));
+        &self.next_prefixes[self.next_prefixes.len() - 1].1
+    }
+}
+
+impl TrackNamespace for ScopedNamespaces {
+    /// Declare a prefix on the element being started, returning false without declaring it if
+    /// it has already been declared on it, or if it would rebind the `xml` or `xmlns` prefixes
+    /// or namespaces.
+    fn declare_fixed(&mut self, prefix: Option<&NcNameStr>, name: Option<NamespaceName>) -> bool {
+        let reserved = |namespace: &Option<NamespaceName>| matches!(namespace, Some(namespace) if **namespace == *XMLNS_XML || **namespace == *XMLNS_XMLNS);
+        match prefix {
+            Some(prefix) if prefix == PREFIX_XML || prefix == PREFIX_XMLNS => false,
+            _ if reserved(&name) => false,
+            Some(prefix) => {
+                if self
+                    .next_prefixes
+                    .iter()
+                    .any(|(_, declared)| **declared == *prefix)
+                {
+                    return false;
+                }
+                self.next_prefixes.push((name, prefix.to_ncname()));
+                true
+            }
+            None => {
+                if self.next_default.is_some() {
+                    return false;
+                }
+                self.next_default = Some(name);
+                true
+            }
+        }
+    }
+
+    fn declare_auto(&mut self, name: Option<NamespaceName>) -> (bool, Option<&NcNameStr>) {
+        match name {
+            Some(ref v) if **v == *XMLNS_XML => return (false, Some(PREFIX_XML)),
+            Some(ref v) if **v == *XMLNS_XMLNS => return (false, Some(PREFIX_XMLNS)),
+            _ => (),
+        }
+
+        if let Some(location) = self.locate_preferred_prefix(&name) {
+            return (false, Some(self.prefix_at(location)));
+        }
+        if self.current_default() == name.as_ref() {
+            return (false, None);
+        }
+        if let Some(location) = self.locate_prefix_of(&name) {
+            return (false, Some(self.prefix_at(location)));
+        }
+        if self.next_default.is_none() {
+            self.next_default = Some(name);
+            return (true, None);
+        }
+        (true, Some(self.declare_new_prefix(name)))
+    }
+
+    fn declare_with_auto_prefix(&mut self, name: Option<NamespaceName>) -> (bool, &NcNameStr) {
+        match name {
+            Some(ref v) if **v == *XMLNS_XML => return (false, PREFIX_XML),
+            Some(ref v) if **v == *XMLNS_XMLNS => return (false, PREFIX_XMLNS),
+            _ => (),
+        }
+
+        match self
+            .locate_preferred_prefix(&name)
+            .or_else(|| self.locate_prefix_of(&name))
+        {
+            Some(location) => (false, self.prefix_at(location)),
+            None => (true, self.declare_new_prefix(name)),
+        }
+    }
+
+    fn get_prefix_or_default(
+        &self,
+        name: Option<NamespaceName>,
+    ) -> Result<Option<&NcNameStr>, PrefixError> {
+        if self.current_default() == name.as_ref() {
+            return Ok(None);
+        }
+        self.get_prefix(name).map(Some)
+    }
+
+    fn get_prefix(&self, name: Option<NamespaceName>) -> Result<&NcNameStr, PrefixError> {
+        self.prefix_of(&name).ok_or(PrefixError::Undeclared)
+    }
+
+    fn push(&mut self) {
+        let default = match self.next_default.take() {
+            Some(default) => default,
+            None => self.scopes.last().and_then(|scope| scope.default.clone()),
+        };
+        self.scopes.push(Scope {
+            default,
+            prefixes: mem::take(&mut self.next_prefixes),
+        });
+        self.preferred_prefix = None;
+    }
+
+    fn pop(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn new_default_declaration(&self) -> Option<Option<&NamespaceName>> {
+        match self.next_default.as_ref().map(|default| default.as_ref()) {
+            // The root element is in no namespace unless told otherwise.
+            Some(None) if self.scopes.is_empty() => None,
+            other => other,
+        }
+    }
+
+    fn new_prefix_declarations<'x>(
+        &'x self,
+    ) -> Box<dyn Iterator<Item = (&Option<NamespaceName>, &NcNameStr)> + 'x> {
+        Box::new(
+            self.next_prefixes
+                .iter()
+                .map(|(namespace, prefix)| (namespace, &**prefix)),
+        )
+    }
+}
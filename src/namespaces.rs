@@ -29,7 +29,7 @@ impl<'a> From<&'a str> for NSChoice<'a> {
 impl<'a> NSChoice<'a> {
     pub(crate) fn compare(&self, ns: &str) -> bool {
         match (ns, &self) {
-            (_, NSChoice::None) => false,
+            (ns, NSChoice::None) => ns.is_empty(),
             (_, NSChoice::Any) => true,
             (ns, NSChoice::OneOf(wanted_ns)) => &ns == wanted_ns,
             (ns, NSChoice::AnyOf(wanted_nss)) => wanted_nss.iter().any(|w| &ns == w),
@@ -22,6 +22,19 @@ pub enum Node {
     Element(Element),
     /// A text node.
     Text(String),
+    /// A CDATA section, holding the text between `<![CDATA[` and `]]>`.
+    ///
+    /// The parser only produces these when asked to by `ParseOptions::preserve_cdata`.
+    CData(String),
+    /// A comment node, holding the text between `<!--` and `-->`.
+    Comment(String),
+    /// A processing instruction, `<?target data?>`.
+    ProcessingInstruction {
+        /// The application the instruction is directed at.
+        target: String,
+        /// The instruction itself, which may be empty.
+        data: String,
+    },
 }
 
 impl Node {
@@ -42,7 +55,10 @@ impl Node {
     pub fn as_element(&self) -> Option<&Element> {
         match *self {
             Node::Element(ref e) => Some(e),
-            Node::Text(_) => None,
+            Node::Text(_)
+            | Node::CData(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
         }
     }
 
@@ -63,7 +79,10 @@ impl Node {
     pub fn as_element_mut(&mut self) -> Option<&mut Element> {
         match *self {
             Node::Element(ref mut e) => Some(e),
-            Node::Text(_) => None,
+            Node::Text(_)
+            | Node::CData(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
         }
     }
 
@@ -84,7 +103,10 @@ impl Node {
     pub fn into_element(self) -> Option<Element> {
         match self {
             Node::Element(e) => Some(e),
-            Node::Text(_) => None,
+            Node::Text(_)
+            | Node::CData(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
         }
     }
 
@@ -104,8 +126,11 @@ impl Node {
     /// ```
     pub fn as_text(&self) -> Option<&str> {
         match *self {
-            Node::Element(_) => None,
             Node::Text(ref s) => Some(s),
+            Node::Element(_)
+            | Node::CData(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
         }
     }
 
@@ -131,8 +156,11 @@ impl Node {
     /// ```
     pub fn as_text_mut(&mut self) -> Option<&mut String> {
         match *self {
-            Node::Element(_) => None,
             Node::Text(ref mut s) => Some(s),
+            Node::Element(_)
+            | Node::CData(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
         }
     }
 
@@ -152,8 +180,134 @@ impl Node {
     /// ```
     pub fn into_text(self) -> Option<String> {
         match self {
-            Node::Element(_) => None,
             Node::Text(s) => Some(s),
+            Node::Element(_)
+            | Node::CData(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
+        }
+    }
+
+    /// Turns this into an `&str` if this is a CDATA section.
+    /// Else this returns `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Node;
+    ///
+    /// let txt = Node::Text("meow".to_owned());
+    /// let cdata = Node::CData("<meow>".to_owned());
+    ///
+    /// assert_eq!(txt.as_cdata(), None);
+    /// assert_eq!(cdata.as_cdata().unwrap(), "<meow>");
+    /// ```
+    pub fn as_cdata(&self) -> Option<&str> {
+        match *self {
+            Node::CData(ref s) => Some(s),
+            Node::Element(_)
+            | Node::Text(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
+        }
+    }
+
+    /// Turns this into an `&mut String` if this is a CDATA section.
+    /// Else this returns `None`.
+    pub fn as_cdata_mut(&mut self) -> Option<&mut String> {
+        match *self {
+            Node::CData(ref mut s) => Some(s),
+            Node::Element(_)
+            | Node::Text(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
+        }
+    }
+
+    /// Turns this into a `String`, consuming self, if this is a CDATA section.
+    /// Else this returns `None`.
+    pub fn into_cdata(self) -> Option<String> {
+        match self {
+            Node::CData(s) => Some(s),
+            Node::Element(_)
+            | Node::Text(_)
+            | Node::Comment(_)
+            | Node::ProcessingInstruction { .. } => None,
+        }
+    }
+
+    /// Turns this into an `&str` if this is a comment node.
+    /// Else this returns `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Node;
+    ///
+    /// let txt = Node::Text("meow".to_owned());
+    /// let comment = Node::Comment(" purr ".to_owned());
+    ///
+    /// assert_eq!(txt.as_comment(), None);
+    /// assert_eq!(comment.as_comment().unwrap(), " purr ");
+    /// ```
+    pub fn as_comment(&self) -> Option<&str> {
+        match *self {
+            Node::Comment(ref s) => Some(s),
+            Node::Element(_)
+            | Node::Text(_)
+            | Node::CData(_)
+            | Node::ProcessingInstruction { .. } => None,
+        }
+    }
+
+    /// Turns this into an `&mut String` if this is a comment node.
+    /// Else this returns `None`.
+    pub fn as_comment_mut(&mut self) -> Option<&mut String> {
+        match *self {
+            Node::Comment(ref mut s) => Some(s),
+            Node::Element(_)
+            | Node::Text(_)
+            | Node::CData(_)
+            | Node::ProcessingInstruction { .. } => None,
+        }
+    }
+
+    /// Turns this into a `String`, consuming self, if this is a comment node.
+    /// Else this returns `None`.
+    pub fn into_comment(self) -> Option<String> {
+        match self {
+            Node::Comment(s) => Some(s),
+            Node::Element(_)
+            | Node::Text(_)
+            | Node::CData(_)
+            | Node::ProcessingInstruction { .. } => None,
+        }
+    }
+
+    /// Turns this into a `(target, data)` pair if this is a processing instruction node.
+    /// Else this returns `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::Node;
+    ///
+    /// let txt = Node::Text("meow".to_owned());
+    /// let pi = Node::ProcessingInstruction {
+    ///     target: "cat".to_owned(),
+    ///     data: "purr".to_owned(),
+    /// };
+    ///
+    /// assert_eq!(txt.as_processing_instruction(), None);
+    /// assert_eq!(pi.as_processing_instruction(), Some(("cat", "purr")));
+    /// ```
+    pub fn as_processing_instruction(&self) -> Option<(&str, &str)> {
+        match *self {
+            Node::ProcessingInstruction {
+                ref target,
+                ref data,
+            } => Some((target, data)),
+            Node::Element(_) | Node::Text(_) | Node::CData(_) | Node::Comment(_) => None,
         }
     }
 
@@ -164,6 +318,12 @@ impl Node {
             Node::Text(ref s) => {
                 writer.write(Item::Text((&**s).try_into()?))?;
             }
+            Node::CData(ref s) => writer.write_cdata(s)?,
+            Node::Comment(ref s) => writer.write_comment(s)?,
+            Node::ProcessingInstruction {
+                ref target,
+                ref data,
+            } => writer.write_processing_instruction(target, data)?,
         }
 
         Ok(())
@@ -202,6 +362,18 @@ impl PartialEq for Node {
         match (self, other) {
             (Node::Element(elem1), Node::Element(elem2)) => elem1 == elem2,
             (Node::Text(text1), Node::Text(text2)) => text1 == text2,
+            (Node::CData(cdata1), Node::CData(cdata2)) => cdata1 == cdata2,
+            (Node::Comment(comment1), Node::Comment(comment2)) => comment1 == comment2,
+            (
+                Node::ProcessingInstruction {
+                    target: target1,
+                    data: data1,
+                },
+                Node::ProcessingInstruction {
+                    target: target2,
+                    data: data2,
+                },
+            ) => target1 == target2 && data1 == data2,
             _ => false,
         }
     }
@@ -0,0 +1,87 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides `Position` and `Span`, to locate what has been parsed in its source.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A location in a parsed document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    /// Number of bytes before this position
+    pub offset: usize,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Number of characters before this position on its line, plus one
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// The part of a parsed document between two positions, `end` being excluded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The position of the first byte
+    pub start: Position,
+    /// The position right after the last byte
+    pub end: Position,
+}
+
+/// Where the tags and attributes of an element have been found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ElementSpans {
+    pub(crate) start_tag: Span,
+    pub(crate) end_tag: Option<Span>,
+    /// By namespace and local name
+    pub(crate) attributes: BTreeMap<(Arc<str>, String), Span>,
+}
+
+/// Keeps track of the position at the end of the input seen so far.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Tracker {
+    position: Position,
+    /// Whether the last byte was a carriage return, which a line feed doesn't end another line
+    /// after
+    after_cr: bool,
+}
+
+impl Tracker {
+    pub(crate) fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Move past `bytes`.
+    pub(crate) fn advance(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            match b {
+                b'\n' if self.after_cr => (),
+                b'\r' | b'\n' => {
+                    self.position.line += 1;
+                    self.position.column = 1;
+                }
+                // Continuation bytes are part of the previous character.
+                b if b & 0xc0 == 0x80 => (),
+                _ => self.position.column += 1,
+            }
+            self.after_cr = b == b'\r';
+        }
+        self.position.offset += bytes.len();
+    }
+}
@@ -0,0 +1,168 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides `PushParser`, to parse a document from chunks of bytes as they are received.
+
+use crate::driver::Driver;
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::tree_builder::{Limit, ParseOptions, TreeBuilder};
+
+/// A parser fed with chunks of bytes, returning the children of the root element of the document
+/// as soon as they are complete.
+///
+/// Chunks may be cut anywhere, including in the middle of a tag or of a multi-byte character.
+/// Whatever could not be parsed yet is kept until the next chunk. As with `StanzaReader`,
+/// children are not kept in the root element once they have been returned, and parsing limits
+/// on the number of elements apply to each child separately.
+///
+/// More input is needed until `is_finished` returns true, which happens once the end tag of
+/// the root element has been parsed. Input fed after that is kept as it is, up to
+/// `ParseLimits::max_token_size` bytes, to be retrieved with `into_rest`.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::PushParser;
+///
+/// let mut parser = PushParser::new();
+///
+/// let children = parser.feed(b"<stream:stream xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'><mes").unwrap();
+/// assert!(children.is_empty());
+/// assert_eq!(parser.root().unwrap().name(), "stream");
+///
+/// let children = parser.feed(b"sage/><presence/><iq").unwrap();
+/// assert_eq!(children.len(), 2);
+/// assert_eq!(children[0].name(), "message");
+/// assert_eq!(children[1].name(), "presence");
+///
+/// let children = parser.feed(b"/></stream:stream>").unwrap();
+/// assert_eq!(children[0].name(), "iq");
+/// assert!(parser.is_finished());
+/// ```
+pub struct PushParser {
+    driver: Driver,
+    tree_builder: TreeBuilder,
+    /// The root element, once it has been closed
+    closed_root: Option<Element>,
+    /// An error to return on the next call, found after some children had been completed
+    error: Option<Error>,
+    /// How much input is kept once the root element has been closed
+    max_rest_size: usize,
+}
+
+impl Default for PushParser {
+    fn default() -> Self {
+        PushParser::new()
+    }
+}
+
+impl PushParser {
+    /// Create a parser waiting for the beginning of a document.
+    pub fn new() -> PushParser {
+        PushParser::with_options(ParseOptions::default())
+    }
+
+    /// Create a parser waiting for the beginning of a document, using the given `ParseOptions`.
+    pub fn with_options(options: ParseOptions) -> PushParser {
+        let tree_builder = TreeBuilder::new().with_options(options);
+        PushParser {
+            driver: Driver::new(tree_builder.options()),
+            max_rest_size: tree_builder.options().limits.max_token_size,
+            tree_builder,
+            closed_root: None,
+            error: None,
+        }
+    }
+
+    /// Parse the next chunk of the document, returning the children of the root element which
+    /// have been completed by it.
+    ///
+    /// If an error is found after some children have been completed, these children are
+    /// returned first, and the error on the next call. The parser shouldn't be fed any more
+    /// input after an error.
+    ///
+    /// Once the parser is finished, feeding it more than `ParseLimits::max_token_size` bytes
+    /// in total fails with `Error::LimitExceeded`, and the input is dropped.
+    pub fn feed(&mut self, data: &[u8]) -> Result<Vec<Element>> {
+        if self.closed_root.is_some() {
+            if self.driver.rest_len() + data.len() > self.max_rest_size {
+                return Err(Error::LimitExceeded(Limit::TokenSize));
+            }
+            self.driver.feed(data);
+            self.driver.decode()?;
+            return Ok(Vec::new());
+        }
+        self.driver.feed(data);
+        self.read_children()
+    }
+
+    /// Signal the end of the document, returning the children of the root element which have
+    /// been completed by what remained of the input.
+    ///
+    /// This fails if the root element hasn't been closed.
+    pub fn feed_eof(&mut self) -> Result<Vec<Element>> {
+        self.driver.feed_eof();
+        self.read_children()
+    }
+
+    /// Returns the root element, without any of its children, once its start tag has been parsed.
+    pub fn root(&self) -> Option<&Element> {
+        self.closed_root
+            .as_ref()
+            .or_else(|| self.tree_builder.root_element())
+    }
+
+    /// Whether the end tag of the root element has been parsed, after which no more input is
+    /// needed.
+    pub fn is_finished(&self) -> bool {
+        self.closed_root.is_some()
+    }
+
+    /// Returns the input following the end tag of the root element, once it has been parsed, or
+    /// what hasn't been parsed yet otherwise, in two parts as `Element::from_reader_with_rest`:
+    /// what has been decoded to UTF-8, and the bytes after it which couldn't be decoded yet.
+    pub fn into_rest(self) -> (Vec<u8>, Vec<u8>) {
+        self.driver.into_rest()
+    }
+
+    fn read_children(&mut self) -> Result<Vec<Element>> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        let mut children = Vec::new();
+        loop {
+            match self.read_child() {
+                Ok(Some(child)) => children.push(child),
+                Ok(None) => return Ok(children),
+                Err(err) => {
+                    let err = self.driver.locate(err);
+                    if children.is_empty() {
+                        return Err(err);
+                    }
+                    self.error = Some(err);
+                    return Ok(children);
+                }
+            }
+        }
+    }
+
+    /// Process the available events, returning a child of the root element if it has been
+    /// completed, or `None` if more input is required or the root element has been closed.
+    fn read_child(&mut self) -> Result<Option<Element>> {
+        while self.closed_root.is_none() {
+            match self.driver.read()? {
+                Some(event) => self.tree_builder.process_driver_event(event)?,
+                None if self.driver.is_eof() => return Err(Error::EndOfDocument),
+                None => return Ok(None),
+            }
+            if let Some(root) = self.tree_builder.root.take() {
+                self.closed_root = Some(root);
+            } else if let Some(child) = self.tree_builder.take_completed_child() {
+                return Ok(Some(child));
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,145 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides `StanzaReader`, to parse the children of a long-lived root element one at a time.
+
+use crate::driver::Driver;
+use crate::element::Element;
+use crate::error::{Error, Result};
+use crate::tree_builder::{ParseOptions, TreeBuilder};
+
+use std::io::BufRead;
+
+/// An iterator over the children of the root element of a document, such as the stanzas of an
+/// XMPP stream, yielding each of them as soon as its end tag has been read.
+///
+/// Children are not kept in the root element once they have been yielded, and neither are the
+/// text nodes between them, so the root element never has to be complete. Parsing limits on the
+/// number of elements apply to each child separately.
+///
+/// The iterator ends when the end tag of the root element is read, and yields an error if the
+/// input ends before that.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::StanzaReader;
+///
+/// let xml = b"<stream:stream xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams' to='example.com'>
+///   <message to='a@example.com'><body>Hi!</body></message>
+///   <presence/>
+/// </stream:stream>";
+/// let mut reader = StanzaReader::new(&xml[..]);
+///
+/// assert_eq!(reader.read_root().unwrap().attr("to"), Some("example.com"));
+///
+/// let message = reader.next().unwrap().unwrap();
+/// assert_eq!(message.name(), "message");
+/// assert_eq!(message.get_child("body", "jabber:client").unwrap().text(), "Hi!");
+///
+/// let presence = reader.next().unwrap().unwrap();
+/// assert_eq!(presence.name(), "presence");
+///
+/// assert!(reader.next().is_none());
+/// ```
+pub struct StanzaReader<R> {
+    reader: R,
+    driver: Driver,
+    tree_builder: TreeBuilder,
+    /// The root element, once it has been closed
+    closed_root: Option<Element>,
+    /// Whether the iterator has ended, either normally or on an error
+    done: bool,
+}
+
+impl<R: BufRead> StanzaReader<R> {
+    /// Create a reader over the document read from `reader`.
+    pub fn new(reader: R) -> StanzaReader<R> {
+        StanzaReader::with_options(reader, ParseOptions::default())
+    }
+
+    /// Create a reader over the document read from `reader`, using the given `ParseOptions`.
+    pub fn with_options(reader: R, options: ParseOptions) -> StanzaReader<R> {
+        let tree_builder = TreeBuilder::new().with_options(options);
+        StanzaReader {
+            reader,
+            driver: Driver::new(tree_builder.options()),
+            tree_builder,
+            closed_root: None,
+            done: false,
+        }
+    }
+
+    /// Returns the root element, without any of its children, once its start tag has been read.
+    pub fn root(&self) -> Option<&Element> {
+        self.closed_root
+            .as_ref()
+            .or_else(|| self.tree_builder.root_element())
+    }
+
+    /// Read up to the end of the start tag of the root element, and return the root element.
+    pub fn read_root(&mut self) -> Result<&Element> {
+        while self.root().is_none() {
+            if let Err(err) = self.step() {
+                self.done = true;
+                return Err(self.driver.locate(err));
+            }
+        }
+        Ok(self.root().unwrap())
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Process the next event, returning a child of the root element if it has been completed,
+    /// or `None` if the root element has been closed.
+    fn read_child(&mut self) -> Result<Option<Element>> {
+        loop {
+            if self.step()? {
+                return Ok(None);
+            }
+            if let Some(child) = self.tree_builder.take_completed_child() {
+                return Ok(Some(child));
+            }
+        }
+    }
+
+    /// Process the next event, or read more input, returning whether the root element has been
+    /// closed.
+    fn step(&mut self) -> Result<bool> {
+        match self.driver.read()? {
+            Some(event) => self.tree_builder.process_driver_event(event)?,
+            None if self.driver.is_eof() => return Err(Error::EndOfDocument),
+            None => self.driver.feed_from(&mut self.reader)?,
+        }
+        if let Some(root) = self.tree_builder.root.take() {
+            self.closed_root = Some(root);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+impl<R: BufRead> Iterator for StanzaReader<R> {
+    type Item = Result<Element>;
+
+    fn next(&mut self) -> Option<Result<Element>> {
+        if self.done {
+            return None;
+        }
+        match self.read_child() {
+            Ok(Some(child)) => Some(Ok(child)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(self.driver.locate(err)))
+            }
+        }
+    }
+}
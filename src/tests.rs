@@ -12,8 +12,15 @@
 
 use crate::element::Element;
 use crate::error::Error;
+use crate::{
+    Canonicalization, Doctype, Document, ElementRef, Limit, NSChoice, Node, NodeRef, ParseLimits,
+    ParseOptions, Position, PushParser, Span, StanzaReader, StreamWriter, WhitespaceMode,
+    WriteOptions, XmlDeclaration,
+};
 
-const TEST_STRING: &'static [u8] = br#"<root xmlns='root_ns' a="b" xml:lang="en">meow<child c="d"/><child xmlns='child_ns' d="e" xml:lang="fr"/>nya</root>"#;
+use std::ptr;
+
+const TEST_STRING: &[u8] = br#"<root xmlns='root_ns' a="b" xml:lang="en">meow<child c="d"/><child xmlns='child_ns' d="e" xml:lang="fr"/>nya</root>"#;
 
 fn build_test_tree() -> Element {
     let mut root = Element::builder("root", "root_ns")
@@ -346,6 +353,335 @@ fn two_elements_with_same_arguments_different_order_are_equal() {
     assert_ne!(elem1, elem2);
 }
 
+#[test]
+fn attribute_order_can_be_preserved() {
+    let xml = r#"<svg xmlns="svg"><rect y="2" x="1" xml:lang="en" width="3" height="4"/></svg>"#;
+    let options = ParseOptions::new().preserve_attribute_order(true);
+    let mut elem = Element::from_reader_with_options(xml.as_bytes(), options.clone()).unwrap();
+    let rect = elem.get_child_mut("rect", "svg").unwrap();
+    assert!(rect.preserves_attribute_order());
+    let names: Vec<_> = rect.attrs().map(|(name, _)| name).collect();
+    assert_eq!(names, ["y", "x", "lang", "width", "height"]);
+    rect.set_attr("x", "5");
+    rect.set_attr("fill", "red");
+    assert_eq!(rect.attr("xml:lang"), Some("en"));
+    for (name, value) in [("y", "2"), ("x", "5"), ("width", "3"), ("fill", "red")] {
+        assert_eq!(rect.attr(name), Some(value));
+    }
+    assert_eq!(rect.attr("z"), None);
+    assert_eq!(
+        String::from(&elem),
+        r#"<svg xmlns='svg'><rect y="2" x="5" xml:lang="en" width="3" height="4" fill="red"/></svg>"#
+    );
+    let rect = elem.get_child_mut("rect", "svg").unwrap();
+    rect.set_attr("x", None::<String>);
+    rect.set_attr("z", None::<String>);
+    assert_eq!(rect.attr("x"), None);
+    for (name, value) in [("y", "2"), ("width", "3"), ("height", "4"), ("fill", "red")] {
+        assert_eq!(rect.attr(name), Some(value));
+    }
+    assert_eq!(
+        String::from(&elem),
+        r#"<svg xmlns='svg'><rect y="2" xml:lang="en" width="3" height="4" fill="red"/></svg>"#
+    );
+
+    // The order doesn't matter for equality.
+    let sorted: Element = xml.parse().unwrap();
+    let rect = sorted.get_child("rect", "svg").unwrap();
+    assert!(!rect.preserves_attribute_order());
+    let names: Vec<_> = rect.attrs().map(|(name, _)| name).collect();
+    assert_eq!(names, ["height", "width", "x", "y", "lang"]);
+    let elem = Element::from_reader_with_options(xml.as_bytes(), options.clone()).unwrap();
+    assert_eq!(elem, sorted);
+
+    let elem_ref = ElementRef::parse_with_options(xml, options.clone()).unwrap();
+    let rect = elem_ref.get_child("rect", "svg").unwrap();
+    let names: Vec<_> = rect.attrs().map(|(name, _)| name).collect();
+    assert_eq!(names, ["y", "x", "lang", "width", "height"]);
+    for (name, value) in [("y", "2"), ("x", "1"), ("width", "3"), ("height", "4")] {
+        assert_eq!(rect.attr(name), Some(value));
+    }
+    assert_eq!(rect.attr("fill"), None);
+    assert_eq!(
+        String::from(&elem_ref.to_owned()),
+        String::from(&Element::from_reader_with_options(xml.as_bytes(), options).unwrap())
+    );
+
+    let mut built = Element::builder("rect", "svg")
+        .preserve_attribute_order(true)
+        .attr("y", "2")
+        .attr("x", "1")
+        .build();
+    assert_eq!(String::from(&built), r#"<rect xmlns='svg' y="2" x="1"/>"#);
+    built.set_preserve_attribute_order(false);
+    assert_eq!(String::from(&built), r#"<rect xmlns='svg' x="1" y="2"/>"#);
+    built.set_preserve_attribute_order(true);
+    built.set_attr("a", "0");
+    built.set_attr("x", "3");
+    assert_eq!((built.attr("a"), built.attr("x")), (Some("0"), Some("3")));
+    assert_eq!(
+        String::from(&built),
+        r#"<rect xmlns='svg' x="3" y="2" a="0"/>"#
+    );
+}
+
+fn pretty(elem: &Element, options: WriteOptions) -> String {
+    let mut out = Vec::new();
+    elem.write_to_with_options(&mut out, options).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn pretty_printing() {
+    let xml = "<root xmlns='ns1'>\n<a>\n    <b/>   <c/>\n</a><p>Some <em>mixed <i>text</i></em></p><e>  </e><!-- note --></root>";
+    let elem: Element = xml.parse().unwrap();
+    let expected = "<root xmlns='ns1'>\n  <a>\n    <b/>\n    <c/>\n  </a>\n  <p>Some <em>mixed <i>text</i></em></p>\n  <e>  </e>\n  <!-- note -->\n</root>";
+    let out = pretty(&elem, WriteOptions::new().indent("  "));
+    assert_eq!(out, expected);
+    // Indenting again doesn't add any more whitespace.
+    assert_eq!(
+        pretty(&out.parse().unwrap(), WriteOptions::new().indent("  ")),
+        expected
+    );
+    assert_eq!(pretty(&elem, WriteOptions::new()), String::from(&elem));
+
+    let xml = r#"<root xmlns='ns1'><pre xml:space="preserve"><a> <b/></a><c xml:space="default"><d/></c></pre></root>"#;
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(
+        pretty(&elem, WriteOptions::new().indent("  ")),
+        "<root xmlns='ns1'>\n  <pre xml:space=\"preserve\"><a> <b/></a><c xml:space=\"default\">\n      <d/>\n    </c></pre>\n</root>"
+    );
+
+    let elem: Element =
+        r#"<root xmlns='ns1' xmlns:x='ns2'><a one="1" three="3" x:two="2"/><b one="1"/></root>"#
+            .parse()
+            .unwrap();
+    assert_eq!(
+        pretty(&elem, WriteOptions::new().indent("\t").attributes_per_line(true)),
+        "<root\n\txmlns='ns1'\n\txmlns:x='ns2'>\n\t<a\n\t\tone=\"1\"\n\t\tthree=\"3\"\n\t\tx:two=\"2\"/>\n\t<b one=\"1\"/>\n</root>"
+    );
+    assert_eq!(
+        pretty(&elem, WriteOptions::new().indent("\t").line_width(32)),
+        "<root xmlns='ns1' xmlns:x='ns2'>\n\t<a\n\t\tone=\"1\"\n\t\tthree=\"3\"\n\t\tx:two=\"2\"/>\n\t<b one=\"1\"/>\n</root>"
+    );
+    // Attributes are only moved when pretty printing.
+    assert_eq!(
+        pretty(
+            &elem,
+            WriteOptions::new().line_width(10).attributes_per_line(true)
+        ),
+        String::from(&elem)
+    );
+}
+
+fn canonical(elem: &Element, method: Canonicalization) -> String {
+    let mut out = Vec::new();
+    elem.write_canonical_to(&mut out, method).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn canonical_forms() {
+    let xml = r#"<n0:local xmlns:n0="foo:bar" xmlns:n3="ftp://example.org"><n1:elem2 xmlns:n1="http://example.net" xml:lang="en"><n3:stuff/></n1:elem2></n0:local>"#;
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(
+        canonical(&elem, Canonicalization::Inclusive),
+        r#"<n0:local xmlns:n0="foo:bar" xmlns:n3="ftp://example.org"><n1:elem2 xmlns:n1="http://example.net" xml:lang="en"><n3:stuff></n3:stuff></n1:elem2></n0:local>"#
+    );
+    assert_eq!(
+        canonical(&elem, Canonicalization::Exclusive),
+        r#"<n0:local xmlns:n0="foo:bar"><n1:elem2 xmlns:n1="http://example.net" xml:lang="en"><n3:stuff xmlns:n3="ftp://example.org"></n3:stuff></n1:elem2></n0:local>"#
+    );
+
+    let xml = "<doc xmlns='ns1' xmlns:b='ns2' b:attr='sorted' attr2='all' attr='I&apos;m &quot;x&quot;&#9;&#10;&#13;&lt;&gt;'>\
+        <!-- comment -->text &gt; &amp; &#13;<![CDATA[<cdata>]]><?pi data?><e xmlns=''/><e></e></doc>";
+    let elem: Element = xml.parse().unwrap();
+    let start = r#"<doc xmlns="ns1" xmlns:b="ns2" attr="I'm &quot;x&quot;&#x9;&#xA;&#xD;&lt;>" attr2="all" b:attr="sorted">"#;
+    let end = "text &gt; &amp; &#xD;&lt;cdata&gt;<?pi data?><e xmlns=\"\"></e><e></e></doc>";
+    assert_eq!(
+        canonical(&elem, Canonicalization::Inclusive),
+        format!("{}{}", start, end)
+    );
+    assert_eq!(
+        canonical(&elem, Canonicalization::InclusiveWithComments),
+        format!("{}<!-- comment -->{}", start, end)
+    );
+    assert_eq!(
+        canonical(&elem, Canonicalization::ExclusiveWithComments),
+        format!("{}<!-- comment -->{}", start, end)
+    );
+
+    // Equal trees have the same canonical form however they are written.
+    let a: Element = r#"<x:a xmlns:x="ns1" xmlns:y="ns2" y:q='1' p="2"><x:b/></x:a>"#
+        .parse()
+        .unwrap();
+    let b: Element = "<x:a p='2' xmlns:y='ns2' y:q='1' xmlns:x='ns1'  ><x:b></x:b></x:a >"
+        .parse()
+        .unwrap();
+    for method in [Canonicalization::Inclusive, Canonicalization::Exclusive] {
+        assert_eq!(canonical(&a, method), canonical(&b, method));
+    }
+
+    // Built elements get prefixes for the namespaces of their attributes.
+    let elem = Element::builder("a", "ns1")
+        .attr_ns("href", "http://www.w3.org/1999/xlink", "#b")
+        .append(Element::builder("b", "ns1").append(Element::bare("c", "")))
+        .build();
+    assert_eq!(
+        canonical(&elem, Canonicalization::Exclusive),
+        r##"<a xmlns="ns1" xmlns:tns0="http://www.w3.org/1999/xlink" tns0:href="#b"><b><c xmlns=""></c></b></a>"##
+    );
+
+    // Attributes keep the prefix they have been parsed with, among the ones bound to their
+    // namespace.
+    let xml = "<a xmlns='ns1' xmlns:x='ns2' xmlns:y='ns2' y:h='1'><b x:k='2'/></a>";
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(
+        canonical(&elem, Canonicalization::Exclusive),
+        r#"<a xmlns="ns1" xmlns:y="ns2" y:h="1"><b xmlns:x="ns2" x:k="2"></b></a>"#
+    );
+    assert_eq!(
+        canonical(
+            &ElementRef::parse(xml).unwrap().to_owned(),
+            Canonicalization::Exclusive
+        ),
+        canonical(&elem, Canonicalization::Exclusive)
+    );
+    assert_eq!(
+        String::from(&elem),
+        xml.replace("'1'", "\"1\"").replace("'2'", "\"2\"")
+    );
+}
+
+#[test]
+fn stream_writer() {
+    let mut writer = StreamWriter::new(Vec::new());
+    let stream = Element::builder("stream", "http://etherx.jabber.org/streams")
+        .prefix(
+            Some(String::from("stream")),
+            "http://etherx.jabber.org/streams",
+        )
+        .unwrap()
+        .prefix(None, "jabber:client")
+        .unwrap()
+        .build();
+    writer.write_declaration().unwrap();
+    writer.open(&stream).unwrap();
+    assert_eq!(writer.depth(), 1);
+    let message = Element::builder("message", "jabber:client")
+        .attr("to", "juliet@example.com")
+        .append(Element::builder("body", "jabber:client").append("Hi"))
+        .build();
+    writer.write_element(&message).unwrap();
+    writer
+        .open(&Element::builder("features", "http://etherx.jabber.org/streams").build())
+        .unwrap();
+    writer.close().unwrap();
+    writer.write_node(&Node::Text(String::from(" "))).unwrap();
+    writer.write_element(&message).unwrap();
+    writer.flush().unwrap();
+    assert!(writer
+        .get_ref()
+        .ends_with(br#"<message to="juliet@example.com"><body>Hi</body></message>"#));
+    writer.close().unwrap();
+    assert_eq!(writer.depth(), 0);
+    let out = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(
+        out,
+        "<?xml version='1.0' encoding='utf-8'?>\n<stream:stream xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>\
+         <message to=\"juliet@example.com\"><body>Hi</body></message><stream:features></stream:features> \
+         <message to=\"juliet@example.com\"><body>Hi</body></message></stream:stream>"
+    );
+    let root: Element = out.parse().unwrap();
+    assert_eq!(root.children().count(), 3);
+
+    // Children are indented when pretty printing.
+    let mut writer = StreamWriter::with_options(Vec::new(), WriteOptions::new().indent("  "));
+    writer
+        .write_node(&Node::Comment(String::from(" svg ")))
+        .unwrap();
+    writer
+        .open(
+            &Element::builder("svg", "http://www.w3.org/2000/svg")
+                .append(Element::bare("defs", "http://www.w3.org/2000/svg"))
+                .build(),
+        )
+        .unwrap();
+    writer
+        .write_element(&Element::bare("rect", "http://www.w3.org/2000/svg"))
+        .unwrap();
+    writer.close().unwrap();
+    assert_eq!(
+        String::from_utf8(writer.into_inner()).unwrap(),
+        "<!-- svg --><svg xmlns='http://www.w3.org/2000/svg'>\n  <defs/>\n  <rect/>\n</svg>"
+    );
+
+    let mut writer = StreamWriter::new(Vec::new());
+    assert!(matches!(writer.close(), Err(Error::XmlError(_))));
+    writer.write_element(&Element::bare("a", "ns1")).unwrap();
+    assert!(writer.write_declaration().is_err());
+    assert!(writer.write_element(&Element::bare("a", "ns1")).is_err());
+    assert!(writer.open(&Element::bare("a", "ns1")).is_err());
+    assert!(writer
+        .write_node(&Node::Text(String::from("text")))
+        .is_err());
+}
+
+#[test]
+fn malformed_trees_fail_to_serialize() {
+    let write = |elem: &Element| elem.write_to(&mut Vec::new());
+
+    let elem = Element::bare("not a name", "ns1");
+    assert!(
+        matches!(write(&elem), Err(Error::InvalidElementName(ref name)) if name == "not a name")
+    );
+    assert!(matches!(
+        elem.to_xml_string(),
+        Err(Error::InvalidElementName(_))
+    ));
+    assert_eq!(String::from(&elem), "");
+    assert!(matches!(
+        elem.write_canonical_to(&mut Vec::new(), Canonicalization::Inclusive),
+        Err(Error::InvalidElementName(_))
+    ));
+
+    let mut elem = Element::bare("a", "ns1");
+    elem.set_attr_ns("x:y", "", "z");
+    assert!(matches!(write(&elem), Err(Error::InvalidAttributeName(ref name)) if name == "x:y"));
+    let elem = Element::builder("a", "ns1")
+        .append(Element::builder("b", "ns1").attr("1st", "z"))
+        .build();
+    assert!(matches!(write(&elem), Err(Error::InvalidAttributeName(_))));
+    assert!(matches!(
+        elem.write_canonical_to(&mut Vec::new(), Canonicalization::Exclusive),
+        Err(Error::InvalidAttributeName(_))
+    ));
+
+    for (prefix, namespace) in [
+        ("xmlns", "ns2"),
+        ("xml", "ns2"),
+        ("p", "http://www.w3.org/XML/1998/namespace"),
+        ("not a prefix", "ns2"),
+    ] {
+        let elem = Element::builder("a", "ns1")
+            .prefix(Some(String::from(prefix)), namespace)
+            .unwrap()
+            .build();
+        assert!(matches!(
+            write(&elem),
+            Err(Error::InvalidNamespaceDeclaration(Some(ref p), ref ns)) if p == prefix && ns == namespace
+        ));
+    }
+
+    let elem = Element::builder("a", "ns1").append("\u{1}").build();
+    assert!(matches!(write(&elem), Err(Error::XmlError(_))));
+    assert!(elem.write_to_decl(&mut Vec::new()).is_err());
+
+    let mut writer = StreamWriter::new(Vec::new());
+    writer.open(&Element::bare("a", "ns1")).unwrap();
+    assert!(writer.write_element(&Element::bare("b c", "ns1")).is_err());
+}
+
 #[test]
 fn namespace_attributes_works() {
     let root = Element::from_reader(TEST_STRING).unwrap();
@@ -359,6 +695,149 @@ fn namespace_attributes_works() {
     );
 }
 
+#[test]
+fn namespaced_attributes() {
+    let xlink = "http://www.w3.org/1999/xlink";
+    let elem1: Element =
+        "<svg xmlns='svg' xmlns:xlink='http://www.w3.org/1999/xlink'><use xlink:href='#a'/></svg>"
+            .parse()
+            .unwrap();
+    let elem2: Element =
+        "<svg xmlns='svg'><use xmlns:x='http://www.w3.org/1999/xlink' x:href='#a'/></svg>"
+            .parse()
+            .unwrap();
+    assert_eq!(elem1, elem2);
+    let use1 = elem1.get_child("use", "svg").unwrap();
+    let use2 = elem2.get_child("use", "svg").unwrap();
+    assert_eq!(use1.attr_ns("href", xlink), Some("#a"));
+    assert_eq!(use2.attr_ns("href", xlink), Some("#a"));
+    assert_eq!(use1.attr("href"), None);
+    assert_eq!(use1.attrs_ns().collect::<Vec<_>>(), [(xlink, "href", "#a")]);
+    assert!(use1.attr_span("href").is_none());
+
+    // The declaration isn't needed anymore once the element is moved elsewhere.
+    let mut moved = Element::bare("g", "svg");
+    moved.append_child(use2.clone());
+    let moved: Element = String::from(&moved).parse().unwrap();
+    assert_eq!(
+        moved
+            .get_child("use", "svg")
+            .unwrap()
+            .attr_ns("href", xlink),
+        Some("#a")
+    );
+
+    let built = Element::builder("use", "svg")
+        .attr("x", "1")
+        .attr_ns("x", "ns2", "2")
+        .attr("xml:lang", "en")
+        .build();
+    assert_eq!(built.attr("x"), Some("1"));
+    assert_eq!(built.attr_ns("x", "ns2"), Some("2"));
+    assert_eq!(
+        built.attr_ns("lang", "http://www.w3.org/XML/1998/namespace"),
+        Some("en")
+    );
+    let parsed: Element = String::from(&built).parse().unwrap();
+    assert_eq!(parsed, built);
+
+    // Prefixed names are resolved against the declarations of the element, or the prefixes
+    // its attributes have been parsed with.
+    assert_eq!(use1.attr("xlink:href"), Some("#a"));
+    assert_eq!(use2.attr("x:href"), Some("#a"));
+    assert_eq!(use2.attr("xlink:href"), None);
+    let parsed = ElementRef::parse(
+        "<svg xmlns='svg'><use xmlns:x='http://www.w3.org/1999/xlink' x:href='#a'/></svg>",
+    )
+    .unwrap();
+    let use3 = parsed.get_child("use", "svg").unwrap();
+    assert_eq!(use3.attr("x:href"), Some("#a"));
+    assert_eq!(use3.attr("xlink:href"), None);
+    let built = Element::builder("use", "svg")
+        .prefix(Some(String::from("xlink")), xlink)
+        .unwrap()
+        .attr("xlink:href", "#a")
+        .build();
+    assert_eq!(built.attr("xlink:href"), Some("#a"));
+    assert_eq!(built.attr_ns("href", xlink), Some("#a"));
+    let xml = built.to_xml_string().unwrap();
+    assert_eq!(
+        xml,
+        r##"<use xmlns='svg' xmlns:xlink='http://www.w3.org/1999/xlink' xlink:href="#a"/>"##
+    );
+    let parsed: Element = xml.parse().unwrap();
+    assert_eq!(parsed, built);
+    assert_eq!(parsed.attr("xlink:href"), Some("#a"));
+    let mut parsed = parsed;
+    parsed.set_attr("xlink:href", "#b");
+    assert_eq!(parsed.attr_ns("href", xlink), Some("#b"));
+
+    let err = "<a xmlns='ns1' x:b='c'/>".parse::<Element>().unwrap_err();
+    assert!(matches!(err, Error::MissingNamespace));
+    let err = ElementRef::parse("<a xmlns='ns1' x:b='c'/>").unwrap_err();
+    assert!(matches!(err, Error::MissingNamespace));
+
+    // Attributes with different prefixes may still have the same name.
+    let is_duplicate = |err| {
+        matches!(
+            err,
+            Error::XmlError(rxml::Error::Xml(rxml::error::XmlError::DuplicateAttribute))
+        )
+    };
+    for xml in [
+        "<a xmlns='ns1' xmlns:x='ns2' xmlns:y='ns2' x:b='c' y:b='d'/>",
+        "<a xmlns:x='m' xmlns:y='m' x:h='1' y:h='2'/>",
+    ] {
+        for options in [
+            ParseOptions::new().allow_missing_namespace(true),
+            ParseOptions::new()
+                .allow_missing_namespace(true)
+                .preserve_attribute_order(true),
+        ] {
+            let err = Element::from_reader_with_options(xml.as_bytes(), options.clone());
+            assert!(is_duplicate(err.unwrap_err()));
+            let err = ElementRef::parse_with_options(xml, options);
+            assert!(is_duplicate(err.unwrap_err()));
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "the prefix of attribute \"xlink:href\" isn't bound")]
+fn undeclared_attribute_prefix() {
+    Element::builder("use", "svg").attr("xlink:href", "#a");
+}
+
+#[test]
+fn prefixes_survive_round_trip() {
+    let xml = "<svg:svg xmlns:svg='http://www.w3.org/2000/svg' xmlns:xlink='http://www.w3.org/1999/xlink'><svg:g><svg:use xlink:href='#a'/></svg:g></svg:svg>";
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(String::from(&elem), xml.replace("'#a'", "\"#a\""));
+
+    // Prefixes declared below the root stay in scope for the descendants.
+    let xml = "<a xmlns='ns1'><x:b xmlns:x='ns2'><x:c x:d='e'/></x:b></a>";
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(elem.get_child("b", "ns2").unwrap().prefix(), Some("x"));
+    assert_eq!(String::from(&elem), xml.replace("'e'", "\"e\""));
+
+    // The original prefix is preferred to the default namespace, unless it has been rebound.
+    let xml = "<a xmlns='ns1' xmlns:x='ns1'><x:b/><c xmlns:x='ns2'><x:d xmlns='ns2'/></c></a>";
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(
+        String::from(&elem),
+        "<a xmlns='ns1' xmlns:x='ns1'><x:b/><c xmlns:x='ns2'><x:d xmlns='ns2'/></c></a>"
+    );
+    let mut moved = elem.get_child("b", "ns1").unwrap().clone();
+    moved.append_child(Element::bare("e", "ns1"));
+    assert_eq!(String::from(&moved), "<b xmlns='ns1'><e/></b>");
+
+    let elem = ElementRef::parse("<x:a xmlns:x='ns1'/>")
+        .unwrap()
+        .to_owned();
+    assert_eq!(elem.prefix(), Some("x"));
+    assert_eq!(Element::bare("a", "ns1").prefix(), None);
+}
+
 #[test]
 fn wrongly_closed_elements_error() {
     let elem1 = "<a xmlns='ns1'></b>".parse::<Element>();
@@ -420,12 +899,978 @@ fn namespace_inherited_prefixed2() {
 }
 
 #[test]
-fn fail_comments() {
-    let elem: Result<Element, Error> = "<foo xmlns='ns1'><!-- bar --></foo>".parse();
-    match elem {
-        Err(_) => (),
-        _ => panic!(),
+fn comments_round_trip() {
+    let xml = "<foo xmlns='ns1'><!-- bar -->baz<qux><!--\n  multi\n  line\n--></qux></foo>";
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(elem.comments().collect::<Vec<_>>(), [" bar "]);
+    assert_eq!(elem.text(), "baz");
+    assert_eq!(
+        elem.get_child("qux", "ns1").unwrap().nodes().next(),
+        Some(&Node::Comment("\n  multi\n  line\n".to_owned()))
+    );
+    assert_eq!(String::from(&elem), xml);
+}
+
+#[test]
+fn comments_outside_root_are_skipped() {
+    let elem: Element = "<!-- a --><foo xmlns='ns1'/>".parse().unwrap();
+    assert_eq!(elem, Element::bare("foo", "ns1"));
+}
+
+#[test]
+fn processing_instructions_round_trip() {
+    let xml = "<svg xmlns='svg'><?editor version=\"2\"?><g><?empty?></g></svg>";
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(
+        elem.nodes().next(),
+        Some(&Node::ProcessingInstruction {
+            target: "editor".to_owned(),
+            data: "version=\"2\"".to_owned(),
+        })
+    );
+    assert_eq!(
+        elem.get_child("g", "svg")
+            .unwrap()
+            .nodes()
+            .next()
+            .unwrap()
+            .as_processing_instruction(),
+        Some(("empty", ""))
+    );
+    assert_eq!(String::from(&elem), xml);
+}
+
+#[test]
+fn processing_instructions_around_root_are_accepted() {
+    let xml = "<?xml version='1.0'?>\n<?xml-stylesheet href='style.css'?>\n<svg xmlns='svg'/>\n";
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(elem, Element::bare("svg", "svg"));
+}
+
+#[test]
+fn document_round_trip() {
+    let xml = "<?xml version='1.0' encoding='UTF-8' standalone='no'?>\n<?xml-stylesheet href='style.css'?>\n<!-- prolog -->\n<svg xmlns='svg'><!-- inner --></svg>\n<!-- epilog -->\n";
+    let doc: Document = xml.parse().unwrap();
+    assert_eq!(
+        doc.declaration(),
+        Some(&XmlDeclaration {
+            version: String::from("1.0"),
+            encoding: Some(String::from("UTF-8")),
+            standalone: Some(false),
+        })
+    );
+    assert_eq!(doc.prolog().len(), 5);
+    assert_eq!(doc.prolog()[4], Node::Text(String::from("\n")));
+    assert_eq!(doc.root().nodes().count(), 1);
+    assert_eq!(doc.epilog().len(), 3);
+    assert_eq!(doc.epilog()[1].as_comment(), Some(" epilog "));
+
+    let mut out = Vec::new();
+    doc.write_to(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), xml);
+}
+
+#[test]
+fn document_without_declaration() {
+    let doc: Document = "<svg xmlns='svg'/>".parse().unwrap();
+    assert_eq!(doc.declaration(), None);
+    assert!(doc.prolog().is_empty() && doc.epilog().is_empty());
+    assert_eq!(doc, Document::new(Element::bare("svg", "svg")));
+
+    let mut doc = doc;
+    doc.set_declaration(Some(XmlDeclaration::default()));
+    let mut out = Vec::new();
+    doc.write_to(&mut out).unwrap();
+    assert_eq!(out, b"<?xml version='1.0'?><svg xmlns='svg'/>");
+}
+
+#[test]
+fn document_rejects_trailing_content() {
+    assert!("<a xmlns='ns1'/><b xmlns='ns1'/>"
+        .parse::<Document>()
+        .is_err());
+    assert!("<a xmlns='ns1'/>text".parse::<Document>().is_err());
+    assert!("<!-- only a comment -->".parse::<Document>().is_err());
+}
+
+const ILLUSTRATOR_SVG: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<!-- Generator: Adobe Illustrator -->
+<!DOCTYPE svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd" [
+	<!ENTITY ns_extend "http://ns.adobe.com/Extensibility/1.0/">
+	<!ENTITY ns_svg "http://www.w3.org/2000/svg">
+	<!ENTITY title "Logo &amp; &ns_extend;">
+	<!ENTITY % parameter "ignored">
+	<!ELEMENT svg ANY>
+	<!ATTLIST svg version CDATA "1.1">
+]>
+<svg version="1.1" xmlns:x="&ns_extend;" xmlns="&ns_svg;"><title>&title;</title><x:meta a='&apos;&ns_svg;"'/></svg>
+"#;
+
+#[test]
+fn doctype_is_rejected_by_default() {
+    assert!(ILLUSTRATOR_SVG.parse::<Element>().is_err());
+}
+
+#[test]
+fn doctype_entities_are_expanded() {
+    let options = ParseOptions::new().allow_doctype(true);
+    let elem = Element::from_reader_with_options(ILLUSTRATOR_SVG.as_bytes(), options).unwrap();
+    assert!(elem.is("svg", "http://www.w3.org/2000/svg"));
+    assert_eq!(
+        elem.get_child("title", "http://www.w3.org/2000/svg")
+            .unwrap()
+            .text(),
+        "Logo & http://ns.adobe.com/Extensibility/1.0/"
+    );
+    let meta = elem
+        .get_child("meta", "http://ns.adobe.com/Extensibility/1.0/")
+        .unwrap();
+    assert_eq!(meta.attr("a"), Some("'http://www.w3.org/2000/svg\""));
+
+    let options = ParseOptions::new().allow_doctype(true);
+    let elem_ref = ElementRef::parse_with_options(ILLUSTRATOR_SVG, options).unwrap();
+    assert_eq!(elem_ref.to_owned(), elem);
+}
+
+#[test]
+fn doctype_round_trip() {
+    let options = ParseOptions::new().allow_doctype(true);
+    let doc = Document::from_reader_with_options(ILLUSTRATOR_SVG.as_bytes(), options).unwrap();
+    let doctype = doc.doctype().unwrap();
+    assert_eq!(doctype.name, "svg");
+    assert_eq!(
+        doctype.public_id.as_deref(),
+        Some("-//W3C//DTD SVG 1.1//EN")
+    );
+    assert!(doctype
+        .internal_subset
+        .as_ref()
+        .unwrap()
+        .contains("<!ENTITY ns_svg"));
+
+    let mut out = Vec::new();
+    doc.write_to(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let root_start = ILLUSTRATOR_SVG.find("<svg").unwrap();
+    assert_eq!(
+        out[..root_start - 1],
+        format!(
+            "<?xml version='1.0' encoding='utf-8'?>{}",
+            &ILLUSTRATOR_SVG[ILLUSTRATOR_SVG.find('\n').unwrap()..root_start - 1]
+        )
+    );
+
+    let mut doc = Document::new(Element::bare("a", "ns1"));
+    doc.set_doctype(Some(Doctype {
+        name: String::from("a"),
+        public_id: None,
+        system_id: Some(String::from("a.dtd")),
+        internal_subset: None,
+    }));
+    let mut out = Vec::new();
+    doc.write_to(&mut out).unwrap();
+    assert_eq!(out, br#"<!DOCTYPE a SYSTEM "a.dtd"><a xmlns='ns1'/>"#);
+}
+
+#[test]
+fn doctype_entity_limits() {
+    let laughs = r#"<!DOCTYPE lolz [
+ <!ENTITY lol "lol">
+ <!ENTITY lol1 "&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;">
+ <!ENTITY lol2 "&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;&lol1;">
+ <!ENTITY lol3 "&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;&lol2;">
+ <!ENTITY lol4 "&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;&lol3;">
+ <!ENTITY lol5 "&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;&lol4;">
+ <!ENTITY lol6 "&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;&lol5;">
+ <!ENTITY empty "">
+ <!ENTITY empties "&empty;&empty;&empty;&empty;&empty;&empty;&empty;&empty;">
+ <!ENTITY empties2 "&empties;&empties;&empties;&empties;&empties;&empties;&empties;">
+ <!ENTITY empties3 "&empties2;&empties2;&empties2;&empties2;&empties2;&empties2;">
+ <!ENTITY empties4 "&empties3;&empties3;&empties3;&empties3;&empties3;&empties3;">
+ <!ENTITY empties5 "&empties4;&empties4;&empties4;&empties4;&empties4;&empties4;">
+]>
+"#;
+    let options = ParseOptions::new().allow_doctype(true);
+    let parse = |body: &str, options: &ParseOptions| {
+        let xml = format!("{}{}", laughs, body);
+        let elem = Element::from_reader_with_options(xml.as_bytes(), options.clone());
+        let elem_ref = ElementRef::parse_with_options(&xml, options.clone());
+        assert_eq!(
+            format!("{:?}", elem_ref.map(|elem| elem.to_owned())),
+            format!("{:?}", elem)
+        );
+        elem
+    };
+
+    assert_eq!(
+        parse("<lolz xmlns='ns1'>&lol2;</lolz>", &options)
+            .unwrap()
+            .text()
+            .len(),
+        300
+    );
+    for body in [
+        "<lolz xmlns='ns1'>&lol6;</lolz>",
+        "<lolz xmlns='ns1' a='&lol6;'/>",
+        "<lolz xmlns='ns1'>&empties5;</lolz>",
+    ] {
+        assert!(matches!(
+            parse(body, &options),
+            Err(Error::LimitExceeded(_))
+        ));
+    }
+    assert!(matches!(
+        parse(
+            "<lolz xmlns='ns1'>&lol2;</lolz>",
+            &options
+                .clone()
+                .limits(ParseLimits::new().max_entity_depth(2))
+        ),
+        Err(Error::LimitExceeded(_))
+    ));
+    assert!(matches!(
+        parse(
+            "<lolz xmlns='ns1'>&lol2;</lolz>",
+            &options
+                .clone()
+                .limits(ParseLimits::new().max_entity_expansion(100))
+        ),
+        Err(Error::LimitExceeded(_))
+    ));
+    assert!(matches!(
+        parse(
+            "<lolz xmlns='ns1'/>",
+            &options
+                .clone()
+                .limits(ParseLimits::new().max_entity_declarations(12))
+        ),
+        Err(Error::LimitExceeded(Limit::EntityDeclarations))
+    ));
+    assert!(matches!(
+        parse(
+            "<lolz xmlns='ns1'/>",
+            &options
+                .clone()
+                .limits(ParseLimits::new().max_doctype_size(100))
+        ),
+        Err(Error::LimitExceeded(Limit::DoctypeSize))
+    ));
+
+    // The DOCTYPE is rejected before its end has been read.
+    let mut parser = PushParser::with_options(
+        ParseOptions::new()
+            .allow_doctype(true)
+            .limits(ParseLimits::new().max_doctype_size(100)),
+    );
+    assert!(parser.feed(b"<!DOCTYPE a [<!ENTITY e '").is_ok());
+    assert!(parser.feed(&[b'x'; 100]).is_err());
+}
+
+#[test]
+fn doctype_invalid_entities() {
+    let options = ParseOptions::new().allow_doctype(true);
+    for xml in [
+        "<!DOCTYPE a [<!ENTITY a '&b;'><!ENTITY b '&a;'>]><a xmlns='ns1'>&a;</a>",
+        "<!DOCTYPE a [<!ENTITY a SYSTEM 'file:///etc/passwd'>]><a xmlns='ns1'>&a;</a>",
+        "<!DOCTYPE a [<!ENTITY a '<b/>'>]><a xmlns='ns1'>&a;</a>",
+        "<!DOCTYPE a [<!ENTITY % a '<!ENTITY b \"c\">'> %a;]><a xmlns='ns1'>&b;</a>",
+        "<!DOCTYPE a [<!ENTITY a 'b'>]><a xmlns='ns1'>&c;</a>",
+        "<!DOCTYPE a><!DOCTYPE a><a xmlns='ns1'/>",
+        "<!DOCTYPE a [<!ENTITY a 'b'>",
+    ] {
+        assert!(
+            Element::from_reader_with_options(xml.as_bytes(), options.clone()).is_err(),
+            "{}",
+            xml
+        );
+        assert!(
+            ElementRef::parse_with_options(xml, options.clone()).is_err(),
+            "{}",
+            xml
+        );
+    }
+    let xml = "<a xmlns='ns1'/><!DOCTYPE a>";
+    assert!(Document::from_reader_with_options(xml.as_bytes(), options.clone()).is_err());
+    assert!(ElementRef::parse_with_options(xml, options.clone()).is_err());
+
+    let xml = "<!DOCTYPE a [<!ENTITY a SYSTEM 'a.xml'>]><a xmlns='ns1'>b</a>";
+    let elem = Element::from_reader_with_options(xml.as_bytes(), options).unwrap();
+    assert_eq!(elem.text(), "b");
+}
+
+#[test]
+fn parse_limits() {
+    let parse = |xml: &str, limits: ParseLimits| {
+        Element::from_reader_with_options(xml.as_bytes(), ParseOptions::new().limits(limits))
+    };
+    let limit = |xml: &str, limits: ParseLimits| {
+        let by_ref =
+            ElementRef::parse_with_options(xml, ParseOptions::new().limits(limits.clone()));
+        let limit = match parse(xml, limits) {
+            Err(Error::LimitExceeded(limit)) => Some(limit),
+            Err(err) => panic!("Wrong error: {:?}", err),
+            Ok(_) => None,
+        };
+        match by_ref {
+            Err(Error::LimitExceeded(by_ref)) => assert_eq!(Some(by_ref), limit),
+            Err(err) => panic!("Wrong error: {:?}", err),
+            Ok(_) => assert_eq!(limit, None),
+        }
+        limit
+    };
+
+    let xml = "<a xmlns='ns1'><b><c/></b></a>";
+    assert_eq!(limit(xml, ParseLimits::new().max_depth(3)), None);
+    assert_eq!(
+        limit(xml, ParseLimits::new().max_depth(2)),
+        Some(Limit::Depth)
+    );
+    assert_eq!(limit(xml, ParseLimits::new().max_elements(3)), None);
+    assert_eq!(
+        limit(xml, ParseLimits::new().max_elements(2)),
+        Some(Limit::Elements)
+    );
+
+    let xml = "<a xmlns='ns1' xmlns:x='ns2' b='1' x:c='2'/>";
+    assert_eq!(limit(xml, ParseLimits::new().max_attributes(2)), None);
+    assert_eq!(
+        limit(xml, ParseLimits::new().max_attributes(1)),
+        Some(Limit::Attributes)
+    );
+    assert_eq!(
+        limit(xml, ParseLimits::new().max_namespace_declarations(2)),
+        None
+    );
+    assert_eq!(
+        limit(xml, ParseLimits::new().max_namespace_declarations(1)),
+        Some(Limit::NamespaceDeclarations)
+    );
+
+    let xml = "<a xmlns='ns1'>12345<b/>1234<!-- -->5</a>";
+    assert_eq!(limit(xml, ParseLimits::new().max_text_size(5)), None);
+    assert_eq!(
+        limit(xml, ParseLimits::new().max_text_size(4)),
+        Some(Limit::TextSize)
+    );
+
+    let xml = "<a xmlns='ns1'><!-- 1234567 --><?pi 123456789?><b c='1234567'/></a>";
+    assert_eq!(limit(xml, ParseLimits::new().max_token_size(16)), None);
+    for xml in [
+        "<a xmlns='ns1'><!-- 1234567 --></a>",
+        "<a xmlns='ns1'><?pi 123456789?></a>",
+        "<a xmlns='ns1'><b c='1234567'/></a>",
+    ] {
+        assert_eq!(
+            limit(xml, ParseLimits::new().max_token_size(15)),
+            Some(Limit::TokenSize)
+        );
+    }
+}
+
+#[test]
+fn unfinished_constructs_are_bounded() {
+    let limits = ParseLimits::new().max_text_size(1000).max_token_size(1000);
+    let chunk = [b'x'; 100];
+    for start in [
+        &b"<a xmlns='ns1' b='"[..],
+        b"<a xmlns='ns1'><!--",
+        b"<a xmlns='ns1'><?pi ",
+        b"<a xmlns='ns1'><![CDATA[",
+        b"<a xmlns='ns1'>&",
+    ] {
+        let mut parser = PushParser::with_options(ParseOptions::new().limits(limits.clone()));
+        parser.feed(start).unwrap();
+        let err = (0..20).find_map(|_| parser.feed(&chunk).err()).unwrap();
+        assert!(matches!(
+            err,
+            Error::LimitExceeded(Limit::TokenSize | Limit::TextSize)
+        ));
+    }
+
+    // Text is bounded as it comes, and isn't buffered until its end.
+    let mut parser = PushParser::with_options(ParseOptions::new().limits(limits));
+    parser.feed(b"<a xmlns='ns1'>").unwrap();
+    let err = (0..20).find_map(|_| parser.feed(&chunk).err()).unwrap();
+    assert!(matches!(err, Error::LimitExceeded(Limit::TextSize)));
+}
+
+#[test]
+fn source_positions() {
+    let xml = "<root xmlns='ns1'>\r\n\t<é a=\"1\"\n   b='2'>text</é><!-- \n --><c/>\n</root>";
+    let options = ParseOptions::new().record_positions(true);
+    let elem = Element::from_reader_with_options(xml.as_bytes(), options).unwrap();
+    assert_eq!(xml.parse::<Element>().unwrap().start_tag_span(), None);
+    let position = |offset, line, column| Position {
+        offset,
+        line,
+        column,
+    };
+    let span = |start, end| Span { start, end };
+
+    assert_eq!(
+        elem.start_tag_span(),
+        Some(span(position(0, 1, 1), position(18, 1, 19)))
+    );
+    assert_eq!(
+        elem.end_tag_span(),
+        Some(span(position(64, 5, 1), position(71, 5, 8)))
+    );
+    assert_eq!(elem.attr_span("xmlns"), None);
+
+    let child = elem.get_child("é", "ns1").unwrap();
+    assert_eq!(
+        child.start_tag_span(),
+        Some(span(position(21, 2, 2), position(40, 3, 10)))
+    );
+    assert_eq!(
+        child.attr_span("a"),
+        Some(span(position(25, 2, 5), position(30, 2, 10)))
+    );
+    assert_eq!(
+        child.attr_span("b"),
+        Some(span(position(34, 3, 4), position(39, 3, 9)))
+    );
+    assert_eq!(
+        child.end_tag_span(),
+        Some(span(position(44, 3, 14), position(49, 3, 18)))
+    );
+
+    let empty = elem.get_child("c", "ns1").unwrap();
+    assert_eq!(
+        empty.start_tag_span(),
+        Some(span(position(59, 4, 5), position(63, 4, 9)))
+    );
+    assert_eq!(empty.end_tag_span(), empty.start_tag_span());
+}
+
+#[test]
+fn errors_have_positions() {
+    let options = ParseOptions::new().record_positions(true);
+    let position = |xml: &str| {
+        let err = Element::from_reader_with_options(xml.as_bytes(), options.clone()).unwrap_err();
+        let position = err.position().unwrap();
+        (position.offset, position.line, position.column)
+    };
+    assert_eq!(position("<a xmlns='ns1'>\n<b></c></a>"), (19, 2, 4));
+    assert_eq!(
+        position("<a xmlns='ns1'>\n  <b/>\n  <p:c/></a>"),
+        (25, 3, 3)
+    );
+    assert_eq!(position("<a xmlns='ns1'>\n&foo;</a>"), (15, 1, 16));
+    assert_eq!(position("<a xmlns='ns1'>\n<!-- -- --></a>"), (16, 2, 1));
+    assert_eq!(position("<a xmlns='ns1'>\n"), (16, 2, 1));
+
+    let xml = b"<a xmlns='ns1'/>\n<b xmlns='ns1'/>";
+    let err = Document::from_reader_with_options(&xml[..], options).unwrap_err();
+    assert_eq!(err.position().unwrap().line, 2);
+    assert_eq!(
+        err.to_string().rsplit(" at ").next(),
+        Some("line 2, column 1")
+    );
+}
+
+#[test]
+fn stanza_reader_yields_children() {
+    let xml = "<?xml version='1.0'?><stream:stream xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams' id='abc'> <iq type='get'><ping xmlns='urn:xmpp:ping'/></iq>\n <!-- hi --> <message/></stream:stream>";
+    let reader = std::io::BufReader::with_capacity(3, xml.as_bytes());
+    let mut stanzas = StanzaReader::new(reader);
+    assert_eq!(stanzas.root(), None);
+
+    let root = stanzas.read_root().unwrap();
+    assert!(root.is("stream", "http://etherx.jabber.org/streams"));
+    assert_eq!(root.attr("id"), Some("abc"));
+
+    let iq = stanzas.next().unwrap().unwrap();
+    assert!(iq.is("iq", "jabber:client"));
+    assert!(iq.has_child("ping", "urn:xmpp:ping"));
+    assert_eq!(stanzas.root().unwrap().nodes().count(), 0);
+
+    let message = stanzas.next().unwrap().unwrap();
+    assert!(message.is("message", "jabber:client"));
+    assert!(stanzas.next().is_none());
+    assert!(stanzas.next().is_none());
+    assert_eq!(stanzas.root().unwrap().nodes().count(), 0);
+}
+
+#[test]
+fn stanza_reader_errors() {
+    let xml = b"<stream xmlns='ns1'><a/><b>";
+    let mut stanzas = StanzaReader::new(&xml[..]);
+    assert!(stanzas.next().unwrap().is_ok());
+    let err = stanzas.next().unwrap().unwrap_err();
+    assert!(matches!(
+        err,
+        Error::XmlError(rxml::Error::Xml(rxml::error::XmlError::InvalidEof(_)))
+    ));
+    assert!(stanzas.next().is_none());
+
+    let xml = b"<stream xmlns='ns1'><a/><b></c>";
+    let options = ParseOptions::new().record_positions(true);
+    let stanzas = StanzaReader::with_options(&xml[..], options);
+    let results: Vec<_> = stanzas.collect();
+    assert_eq!(results.len(), 2);
+    assert_eq!(
+        results[1].as_ref().unwrap_err().position().unwrap().offset,
+        27
+    );
+}
+
+#[test]
+fn stanza_reader_limits_apply_per_child() {
+    let xml = b"<stream xmlns='ns1'><a><b/></a><a><b/></a><a><b/><c/></a></stream>";
+    let options = ParseOptions::new().limits(ParseLimits::new().max_elements(3));
+    let mut stanzas = StanzaReader::with_options(&xml[..], options);
+    assert!(stanzas.next().unwrap().is_ok());
+    assert!(stanzas.next().unwrap().is_ok());
+    let err = stanzas.next().unwrap().unwrap_err();
+    assert!(matches!(err, Error::LimitExceeded(Limit::Elements)));
+}
+
+#[test]
+fn push_parser_handles_any_chunking() {
+    let xml = "<stream xmlns='ns1' xmlns:x='ns2'>\n  <a x:b='é'>text &amp; <![CDATA[more]]></a>\n  <!-- c --><x:c/><d><e/></d>\n</stream>";
+    for size in 1..xml.len() {
+        let mut parser = PushParser::new();
+        let mut children = Vec::new();
+        for chunk in xml.as_bytes().chunks(size) {
+            assert!(!parser.is_finished());
+            children.extend(parser.feed(chunk).unwrap());
+        }
+        assert!(parser.is_finished());
+        assert!(parser.feed_eof().unwrap().is_empty());
+
+        let names: Vec<_> = children.iter().map(|child| child.name()).collect();
+        assert_eq!(names, ["a", "c", "d"]);
+        assert_eq!(children[0].attr_ns("b", "ns2"), Some("é"));
+        assert_eq!(children[0].text(), "text & more");
+        assert!(children[1].is("c", "ns2"));
+        assert!(parser.root().unwrap().nodes().next().is_none());
+    }
+}
+
+#[test]
+fn push_parser_errors() {
+    let mut parser = PushParser::new();
+    assert!(parser.feed(b"<stream xmlns='ns1'><a/>").unwrap().len() == 1);
+    assert!(parser.feed(b"<b>").unwrap().is_empty());
+    let err = parser.feed_eof().unwrap_err();
+    assert!(matches!(
+        err,
+        Error::XmlError(rxml::Error::Xml(rxml::error::XmlError::InvalidEof(_)))
+    ));
+
+    // Children completed before an error are returned first.
+    let mut parser = PushParser::with_options(ParseOptions::new().record_positions(true));
+    let children = parser.feed(b"<stream xmlns='ns1'><a/><b/></c>").unwrap();
+    assert_eq!(children.len(), 2);
+    let err = parser.feed(b"").unwrap_err();
+    assert_eq!(err.position().unwrap().offset, 28);
+}
+
+#[test]
+fn whitespace_modes() {
+    let xml = "<a xmlns='ns1'>\n  <b> one <!-- c --> two </b>\n  <pre xml:space='preserve'>\n    <c> </c>\n    <d xml:space='default'> </d>\n  </pre>\n</a>";
+    let parse = |mode| {
+        let options = ParseOptions::new().whitespace(mode);
+        Element::from_reader_with_options(xml.as_bytes(), options).unwrap()
     };
+    let texts = |elem: &Element| -> Vec<String> { elem.texts().map(String::from).collect() };
+
+    let elem = parse(WhitespaceMode::Preserve);
+    assert_eq!(elem, xml.parse().unwrap());
+    assert_eq!(elem.texts().count(), 3);
+
+    let elem = parse(WhitespaceMode::DropWhitespaceOnly);
+    assert_eq!(elem.texts().count(), 0);
+    let b = elem.get_child("b", "ns1").unwrap();
+    assert_eq!(texts(b), [" one ", " two "]);
+    let pre = elem.get_child("pre", "ns1").unwrap();
+    assert_eq!(pre.texts().count(), 3);
+    assert_eq!(pre.get_child("c", "ns1").unwrap().text(), " ");
+    assert_eq!(pre.get_child("d", "ns1").unwrap().nodes().count(), 0);
+
+    let elem = parse(WhitespaceMode::Trim);
+    assert_eq!(elem.texts().count(), 0);
+    assert_eq!(texts(elem.get_child("b", "ns1").unwrap()), ["one", "two"]);
+    assert_eq!(elem.get_child("pre", "ns1").unwrap().texts().count(), 3);
+
+    for mode in [WhitespaceMode::DropWhitespaceOnly, WhitespaceMode::Trim] {
+        let mut normalized: Element = xml.parse().unwrap();
+        normalized.normalize_whitespace(mode);
+        assert_eq!(normalized, parse(mode));
+    }
+}
+
+#[test]
+fn whitespace_modes_with_stanza_reader() {
+    let xml = b"<stream xmlns='ns1' xml:space='preserve'>\n  <a> <b/> </a>\n  <c xml:space='default'> <b/> </c>\n</stream>";
+    let options = ParseOptions::new().whitespace(WhitespaceMode::DropWhitespaceOnly);
+    let mut stanzas = StanzaReader::with_options(&xml[..], options);
+    assert_eq!(stanzas.next().unwrap().unwrap().nodes().count(), 3);
+    assert_eq!(stanzas.next().unwrap().unwrap().nodes().count(), 1);
+}
+
+#[test]
+fn rest_after_root_is_returned() {
+    use std::io::{BufRead, BufReader, Read};
+
+    let data = b"<a xmlns='ns1'><b/></a><a xmlns='ns1'/>\n<c xmlns='ns1'>text</c>\x00\xff binary";
+    for capacity in 1..data.len() {
+        let mut reader = BufReader::with_capacity(capacity, &data[..]);
+        let mut rest = Vec::new();
+        let mut names = Vec::new();
+        loop {
+            let mut input = (&rest[..]).chain(&mut reader);
+            if input.fill_buf().unwrap().first() == Some(&0) {
+                let mut binary = Vec::new();
+                input.read_to_end(&mut binary).unwrap();
+                assert_eq!(binary, b"\x00\xff binary");
+                break;
+            }
+            let (elem, next, undecoded) =
+                Element::from_reader_with_rest(input, ParseOptions::new()).unwrap();
+            assert!(undecoded.is_empty());
+            names.push(elem.name().to_owned());
+            rest = next;
+        }
+        assert_eq!(names, ["a", "a", "c"]);
+    }
+
+    let mut parser = PushParser::new();
+    assert_eq!(parser.feed(b"<a xmlns='ns1'><b/></a><c").unwrap().len(), 1);
+    assert!(parser.is_finished());
+    parser.feed(b"/>").unwrap();
+    assert_eq!(parser.into_rest(), (b"<c/>".to_vec(), Vec::new()));
+
+    // Input fed once the root element is closed is kept up to the longest token.
+    let options = ParseOptions::new().limits(ParseLimits::new().max_token_size(16));
+    let mut parser = PushParser::with_options(options);
+    assert!(parser.feed(b"<a xmlns='ns1'/>0123").unwrap().is_empty());
+    assert!(parser.feed(b"456789abcdef").unwrap().is_empty());
+    assert!(matches!(
+        parser.feed(b"g"),
+        Err(Error::LimitExceeded(Limit::TokenSize))
+    ));
+    assert_eq!(parser.into_rest().0, b"0123456789abcdef");
+
+    // Decoded input is returned apart from the bytes which can't be decoded yet, such as the
+    // start of a surrogate pair.
+    let options = ParseOptions::new().detect_encoding(true);
+    let mut xml = b"\xff\xfe".to_vec();
+    xml.extend(utf16le("<a xmlns='ns1'/><b/>𝄞"));
+    let pair = xml.len() - 4;
+    for cut in 1..4 {
+        let input = &xml[..xml.len() - cut];
+        let (elem, rest, undecoded) =
+            Element::from_reader_with_rest(input, options.clone()).unwrap();
+        assert_eq!(elem.name(), "a");
+        assert_eq!(rest, b"<b/>");
+        assert_eq!(undecoded, input[pair..]);
+        let mut parser = PushParser::with_options(options.clone());
+        parser.feed(input).unwrap();
+        assert!(parser.is_finished());
+        assert_eq!(
+            parser.into_rest(),
+            (b"<b/>".to_vec(), input[pair..].to_vec())
+        );
+    }
+}
+
+#[test]
+fn element_ref_matches_element() {
+    let documents = [
+        std::str::from_utf8(TEST_STRING).unwrap(),
+        "<?xml version='1.0' encoding='utf-8'?>\n<!-- c --><a xmlns='ns1' xmlns:p='ns2'><p:b p:c='&lt;&#x20;\td'/>x &amp; <![CDATA[<y>]]>\r\nz<?pi data?><!--d--></a>\n",
+        "<a xmlns='ns1'><b xmlns=''><c xmlns='ns2'/></b>é</a>",
+        "<a xmlns='ns1'>\n  <b> text </b>\n  <c xml:space='preserve'> <d> </d> </c>\n</a>",
+        "<a xmlns='ns1'><!--\r\nb\r--><?c d\r\ne?></a>",
+    ];
+    let options = [
+        ParseOptions::new(),
+        ParseOptions::new().preserve_cdata(true),
+        ParseOptions::new().whitespace(WhitespaceMode::Trim),
+        ParseOptions::new().whitespace(WhitespaceMode::DropWhitespaceOnly),
+    ];
+    for xml in documents {
+        for options in &options {
+            let elem = Element::from_reader_with_options(xml.as_bytes(), options.clone()).unwrap();
+            let elem_ref = ElementRef::parse_with_options(xml, options.clone()).unwrap();
+            assert_eq!(elem_ref.to_owned(), elem);
+            assert_eq!(elem_ref.name(), elem.name());
+            assert_eq!(elem_ref.ns(), elem.ns());
+            assert!(elem_ref.attrs().eq(elem.attrs()));
+            assert_eq!(elem_ref.text(), elem.text());
+        }
+    }
+
+    let xml = "<a xmlns='ns1' b='plain' c='&amp;'>plain<d>a&amp;b</d></a>";
+    let elem = ElementRef::parse(xml).unwrap();
+    let is_borrowed = |s: &str| xml.as_bytes().as_ptr_range().contains(&s.as_ptr());
+    assert!(is_borrowed(elem.name()) && is_borrowed(elem.ns()));
+    assert!(is_borrowed(elem.attr("b").unwrap()));
+    assert!(!is_borrowed(elem.attr("c").unwrap()));
+    assert!(is_borrowed(elem.texts().next().unwrap()));
+    let d = elem.get_child("d", "ns1").unwrap();
+    assert_eq!(d.text(), "a&b");
+    assert!(is_borrowed(d.ns()));
+    assert_eq!(
+        elem.nodes().next(),
+        Some(&NodeRef::Text(std::borrow::Cow::Borrowed("plain")))
+    );
+}
+
+#[test]
+fn element_ref_errors() {
+    let invalid = [
+        "",
+        "<a/>",
+        "<a xmlns='ns1'>",
+        "<a xmlns='ns1'></b>",
+        "<a xmlns='ns1' b='<'/>",
+        "<a xmlns='ns1'>&unknown;</a>",
+        "<a xmlns='ns1'>]]></a>",
+        "<a xmlns='ns1'><!-- -- --></a>",
+        "<a xmlns='ns1'/><b xmlns='ns1'/>",
+        "<a xmlns='ns1'/>text",
+        "<!DOCTYPE a><a xmlns='ns1'/>",
+        "<a xmlns='ns1'><p:b/></a>",
+        "<a xmlns='ns1' xmlns:p=''/>",
+        "<a xmlns='ns1'>&#0;</a>",
+        "<a xmlns='ns1'b='c'/>",
+        " <?xml version='1.0'?><a xmlns='ns1'/>",
+    ];
+    for xml in invalid {
+        assert!(ElementRef::parse(xml).is_err(), "{:?} was accepted", xml);
+        assert!(xml.parse::<Document>().is_err(), "{:?} was accepted", xml);
+    }
+
+    let err = ElementRef::parse("<a xmlns='ns1' b='1' b='2'/>").unwrap_err();
+    assert!(matches!(
+        err,
+        Error::XmlError(rxml::Error::Xml(rxml::error::XmlError::DuplicateAttribute))
+    ));
+
+    let options = ParseOptions::new().record_positions(true);
+    let err =
+        ElementRef::parse_with_options("<a xmlns='ns1'>\n  <b></c></a>", options).unwrap_err();
+    assert_eq!(
+        err.position(),
+        Some(Position {
+            offset: 21,
+            line: 2,
+            column: 6
+        })
+    );
+
+    let options = ParseOptions::new().limits(ParseLimits::new().max_depth(1));
+    let err = ElementRef::parse_with_options("<a xmlns='ns1'><b/></a>", options).unwrap_err();
+    assert!(matches!(err, Error::LimitExceeded(Limit::Depth)));
+}
+
+fn utf16le(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+fn utf16be(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+}
+
+#[test]
+fn encoding_detection() {
+    let options = ParseOptions::new().detect_encoding(true);
+    let parse = |xml: &[u8]| Document::from_reader_with_options(xml, options.clone());
+    let expected = Element::builder("a", "ns1").append("é𝄞").build();
+
+    let mut xml = b"\xff\xfe".to_vec();
+    xml.extend(utf16le(
+        "<?xml version='1.0' encoding='UTF-16'?><a xmlns='ns1'>é𝄞</a>",
+    ));
+    assert_eq!(parse(&xml).unwrap().root(), &expected);
+    assert!(Document::from_reader(&xml[..]).is_err());
+
+    // Chunks may end in the middle of code units and surrogate pairs.
+    let xml = utf16be("<?xml version='1.0' encoding='UTF-16BE'?><s xmlns='ns1'><a>é𝄞</a></s>");
+    let mut parser = PushParser::with_options(options.clone());
+    let mut children = Vec::new();
+    for chunk in xml.chunks(1) {
+        children.extend(parser.feed(chunk).unwrap());
+    }
+    assert!(parser.feed_eof().unwrap().is_empty());
+    assert_eq!(children, [expected.clone()]);
+
+    let xml = b"\xef\xbb\xbf<a xmlns='ns1'>\xc3\xa9\xf0\x9d\x84\x9e</a>";
+    assert_eq!(parse(xml).unwrap().root(), &expected);
+
+    let xml = b"<?xml version='1.0' encoding='latin1'?><a xmlns='ns1' b='\xe9'/>";
+    assert_eq!(parse(xml).unwrap().root().attr("b"), Some("é"));
+
+    let xml = b"<?xml version='1.0' encoding='US-ASCII'?><a xmlns='ns1'>\xe9</a>";
+    assert!(parse(xml).is_err());
+
+    let xml = b"<?xml version='1.0' encoding='UTF-16'?><a xmlns='ns1'/>";
+    assert!(parse(xml).is_err());
+
+    let xml = b"<?xml version='1.0' encoding='Shift_JIS'?><a xmlns='ns1'/>";
+    match parse(xml) {
+        Err(Error::UnsupportedEncoding(name)) => assert_eq!(name, "Shift_JIS"),
+        err => panic!("No or wrong error: {:?}", err),
+    }
+
+    let mut xml = utf16le("<a xmlns='ns1'/>");
+    xml.push(b'<');
+    assert!(parse(&xml).is_err());
+
+    // The declaration is searched for its encoding as it comes, up to the longest token.
+    let xml = b"<?xml version='1.0' encoding='latin1'?><s xmlns='ns1'><a b='\xe9'/></s>";
+    let mut parser = PushParser::with_options(options.clone());
+    let mut children = Vec::new();
+    for chunk in xml.chunks(1) {
+        children.extend(parser.feed(chunk).unwrap());
+    }
+    assert_eq!(children[0].attr("b"), Some("é"));
+    let options = options.limits(ParseLimits::new().max_token_size(100));
+    let mut parser = PushParser::with_options(options);
+    assert!(parser.feed(b"<?xml version='1.0'").unwrap().is_empty());
+    let err = (0..10)
+        .map(|_| parser.feed(&[b' '; 20]))
+        .find_map(Result::err);
+    assert!(matches!(err, Some(Error::LimitExceeded(Limit::TokenSize))));
+}
+
+#[test]
+fn document_written_in_declared_encoding() {
+    let options = ParseOptions::new().detect_encoding(true);
+    let text = "<?xml version='1.0' encoding='UTF-16'?><a xmlns='ns1'>é𝄞</a>";
+    let mut xml = b"\xfe\xff".to_vec();
+    xml.extend(utf16be(text));
+    let doc = Document::from_reader_with_options(&xml[..], options.clone()).unwrap();
+    let mut out = Vec::new();
+    doc.write_to(&mut out).unwrap();
+    assert_eq!(out, xml);
+
+    let mut doc = doc;
+    for (encoding, xml) in [
+        ("UTF-16LE", utf16le(&text.replace("UTF-16", "UTF-16LE"))),
+        ("utf-8", text.replace("UTF-16", "utf-8").into_bytes()),
+    ] {
+        doc.set_declaration(Some(XmlDeclaration {
+            encoding: Some(String::from(encoding)),
+            ..XmlDeclaration::default()
+        }));
+        let mut out = Vec::new();
+        doc.write_to(&mut out).unwrap();
+        assert_eq!(out, xml);
+        let doc2 = Document::from_reader_with_options(&out[..], options.clone()).unwrap();
+        assert_eq!(doc2, doc);
+    }
+
+    // Characters the encoding lacks are written as references where possible.
+    let mut elem = Element::builder("a", "ns1")
+        .attr("b", "é𝄞\"")
+        .append("é𝄞<")
+        .append(Node::CData(String::from("é𝄞")))
+        .build();
+    elem.set_attr("c", "𝄞");
+    *doc.root_mut() = elem;
+    for (encoding, xml) in [
+        (
+            "ISO-8859-1",
+            &b"<?xml version='1.0' encoding='ISO-8859-1'?><a xmlns='ns1' b=\"\xe9&#x1D11E;&#34;\" c=\"&#x1D11E;\">\xe9&#x1D11E;&lt;<![CDATA[\xe9]]>&#x1D11E;<![CDATA[]]></a>"[..],
+        ),
+        (
+            "US-ASCII",
+            b"<?xml version='1.0' encoding='US-ASCII'?><a xmlns='ns1' b=\"&#xE9;&#x1D11E;&#34;\" c=\"&#x1D11E;\">&#xE9;&#x1D11E;&lt;<![CDATA[]]>&#xE9;<![CDATA[]]>&#x1D11E;<![CDATA[]]></a>",
+        ),
+    ] {
+        doc.set_declaration(Some(XmlDeclaration {
+            encoding: Some(String::from(encoding)),
+            ..XmlDeclaration::default()
+        }));
+        let mut out = Vec::new();
+        doc.write_to(&mut out).unwrap();
+        assert_eq!(out, xml);
+        let doc2 = Document::from_reader_with_options(&out[..], options.clone()).unwrap();
+        assert_eq!(doc2.root().text(), "é𝄞<é𝄞");
+    }
+
+    // Anywhere else, they can't be written.
+    doc.root_mut().append_node(Node::Comment(String::from("é")));
+    for (encoding, err) in [
+        ("US-ASCII", "UnrepresentableChar('é')"),
+        ("EBCDIC", "UnsupportedEncoding(\"EBCDIC\")"),
+    ] {
+        doc.set_declaration(Some(XmlDeclaration {
+            encoding: Some(String::from(encoding)),
+            ..XmlDeclaration::default()
+        }));
+        let result = doc.write_to(&mut Vec::new());
+        assert_eq!(format!("{:?}", result.unwrap_err()), err);
+    }
+    let mut elem = Element::bare("é", "ns1");
+    elem.set_attr("b", "c");
+    *doc.root_mut() = elem;
+    assert!(doc.write_to(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn invalid_documents_are_not_written() {
+    let mut doc = Document::new(Element::bare("a", "ns1"));
+    doc.epilog_mut().push(Node::Text(String::from("text")));
+    assert!(doc.write_to(&mut Vec::new()).is_err());
+
+    let mut doc = Document::new(Element::bare("a", "ns1"));
+    doc.set_declaration(Some(XmlDeclaration {
+        version: String::from("2.0"),
+        ..XmlDeclaration::default()
+    }));
+    assert!(doc.write_to(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn invalid_processing_instructions_are_not_written() {
+    for (target, data) in [
+        ("xml", ""),
+        ("XmL", ""),
+        ("a:b", ""),
+        ("", ""),
+        ("a", "b?>c"),
+        ("a", "\u{0}"),
+    ] {
+        let elem = Element::builder("foo", "ns1")
+            .append(Node::ProcessingInstruction {
+                target: target.to_owned(),
+                data: data.to_owned(),
+            })
+            .build();
+        assert!(elem.write_to(&mut Vec::new()).is_err());
+        for method in [Canonicalization::Inclusive, Canonicalization::Exclusive] {
+            assert!(elem.write_canonical_to(&mut Vec::new(), method).is_err());
+        }
+    }
+}
+
+#[test]
+fn cdata_is_split_on_terminator() {
+    let elem = Element::builder("foo", "ns1")
+        .append(Node::CData("a]]>b".to_owned()))
+        .build();
+    let xml = String::from(&elem);
+    assert_eq!(xml, "<foo xmlns='ns1'><![CDATA[a]]]]><![CDATA[>b]]></foo>");
+    let elem: Element = xml.parse().unwrap();
+    assert_eq!(elem.text(), "a]]>b");
+}
+
+#[test]
+fn invalid_comments_are_not_written() {
+    let elem = Element::builder("foo", "ns1")
+        .append(Node::Comment("a -- b".to_owned()))
+        .build();
+    assert!(elem.write_to(&mut Vec::new()).is_err());
+
+    let elem = Element::builder("foo", "ns1")
+        .append(Node::Comment("a-".to_owned()))
+        .build();
+    assert!(elem.write_to(&mut Vec::new()).is_err());
 }
 
 #[test]
@@ -464,3 +1909,271 @@ fn misserialisation() {
     let data = String::from(&elem);
     assert_eq!(xml, data);
 }
+
+#[test]
+fn missing_namespace_allowed() {
+    let options = ParseOptions::new().allow_missing_namespace(true);
+    let elem =
+        Element::from_reader_with_options(&b"<a><b xmlns='ns1'><c/></b></a>"[..], options).unwrap();
+    assert!(elem.is("a", NSChoice::None));
+    assert!(!elem.is("a", NSChoice::OneOf("ns1")));
+    let child = elem.get_child("b", "ns1").unwrap();
+    assert!(child.has_child("c", "ns1"));
+    assert_eq!(
+        String::from(&elem),
+        "<a><b xmlns='ns1'><c/></b></a>".to_owned()
+    );
+}
+
+#[test]
+fn missing_namespace_allowed_keeps_prefixes_strict() {
+    let options = ParseOptions::new().allow_missing_namespace(true);
+    match Element::from_reader_with_options(&b"<p1:a/>"[..], options) {
+        Err(Error::MissingNamespace) => (),
+        err => panic!("No or wrong error: {:?}", err),
+    }
+}
+
+#[test]
+fn no_namespace_child_undeclares_default() {
+    let elem = Element::builder("a", "ns1")
+        .append(Element::bare("b", ""))
+        .build();
+    let xml = String::from(&elem);
+    assert_eq!(xml, "<a xmlns='ns1'><b xmlns=''/></a>");
+
+    let options = ParseOptions::new().allow_missing_namespace(true);
+    let elem2 = Element::from_reader_with_options(xml.as_bytes(), options).unwrap();
+    assert_eq!(elem, elem2);
+    assert!(elem2.has_child("b", NSChoice::None));
+}
+
+#[test]
+fn shared_names_and_namespaces() {
+    let elem: Element = "<root xmlns='ns1'><a/><a/><b xmlns='ns2'><a xmlns='ns1'/></b></root>"
+        .parse()
+        .unwrap();
+    let children: Vec<_> = elem.children().collect();
+    assert!(ptr::eq(elem.ns(), children[0].ns()));
+    assert!(ptr::eq(children[0].name(), children[1].name()));
+    let nested = children[2].get_child("a", "ns1").unwrap();
+    assert!(ptr::eq(elem.ns(), nested.ns()));
+    assert!(ptr::eq(children[0].name(), nested.name()));
+    assert!(!ptr::eq(elem.ns(), children[2].ns()));
+
+    let xml = "<root xmlns='ns1'><a/><a/></root>";
+    let elem = ElementRef::parse(xml).unwrap().to_owned();
+    let children: Vec<_> = elem.children().collect();
+    assert!(ptr::eq(elem.ns(), children[1].ns()));
+    assert!(ptr::eq(children[0].name(), children[1].name()));
+}
+
+#[test]
+fn cursor() {
+    let root: Element = "<root xmlns='ns1'>a<x/>b<y><z/>c<z/></y><x><z/></x></root>"
+        .parse()
+        .unwrap();
+    let cursor = root.cursor();
+    assert!(cursor.parent().is_none());
+    assert!(cursor.next_sibling().is_none());
+    assert_eq!(cursor.index_in_parent(), None);
+    assert_eq!(cursor.path(), "/root");
+    assert!(cursor.indices().is_empty());
+
+    let paths: Vec<_> = cursor.descendants().map(|cursor| cursor.path()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/root/x[1]",
+            "/root/y[1]",
+            "/root/y[1]/z[1]",
+            "/root/y[1]/z[2]",
+            "/root/x[2]",
+            "/root/x[2]/z[1]",
+        ]
+    );
+
+    let y = cursor.children().nth(1).unwrap();
+    assert!(ptr::eq(y.element(), root.children().nth(1).unwrap()));
+    assert_eq!(y.index_in_parent(), Some(1));
+    assert_eq!(y.indices(), [3]);
+    assert_eq!(y.prev_sibling().unwrap().index_in_parent(), Some(0));
+    assert_eq!(y.next_sibling().unwrap().index_in_parent(), Some(2));
+    assert_eq!(y.descendants().count(), 2);
+
+    let z = y.first_child().unwrap().next_sibling().unwrap();
+    assert_eq!(z.indices(), [3, 2]);
+    assert!(z.next_sibling().is_none());
+    assert!(z.first_child().is_none());
+    assert_eq!(z.ancestors().count(), 2);
+    assert_eq!(
+        z.parent().unwrap().next_sibling().unwrap().path(),
+        "/root/x[2]"
+    );
+}
+
+#[test]
+fn cursor_mut() {
+    let mut root: Element = "<root xmlns='ns1'><a/><b><c/></b></root>".parse().unwrap();
+
+    let mut cursor = root.cursor_mut();
+    assert!(!cursor.move_to_parent());
+    assert!(cursor.insert_before(Element::bare("x", "ns1")).is_err());
+    assert!(cursor.remove().is_none());
+
+    assert!(cursor.move_to_first_child());
+    assert!(!cursor.move_to_prev_sibling());
+    cursor.insert_after("text").unwrap();
+    assert!(cursor.move_to_next_sibling());
+    assert_eq!(cursor.element().name(), "b");
+    assert_eq!(cursor.index_in_parent(), Some(1));
+    cursor.insert_before(Element::bare("new", "ns1")).unwrap();
+    assert_eq!(cursor.element().name(), "b");
+    assert_eq!(cursor.path(), "/root/b[1]");
+    assert_eq!(cursor.index_in_parent(), Some(2));
+
+    assert!(cursor.move_to_first_child());
+    cursor.element_mut().set_attr("d", "e");
+    assert!(cursor.move_to_parent());
+    let removed = cursor.remove().unwrap();
+    assert_eq!(removed.get_child("c", "ns1").unwrap().attr("d"), Some("e"));
+    assert_eq!(cursor.element().name(), "root");
+    assert_eq!(
+        String::from(&root),
+        "<root xmlns='ns1'><a/>text<new/></root>"
+    );
+
+    let indices = root
+        .cursor()
+        .descendants()
+        .find(|cursor| cursor.element().name() == "new")
+        .unwrap()
+        .indices();
+    let mut cursor = root.cursor_mut_at(&indices).unwrap();
+    cursor.element_mut().append_text_node("inside");
+    assert_eq!(root.get_child("new", "ns1").unwrap().text(), "inside");
+    assert!(root.cursor_mut_at(&[1]).is_none());
+    assert!(root.cursor_mut_at(&[5]).is_none());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_reader() {
+    use tokio::io::BufReader;
+
+    let xml = "<?xml version='1.0'?>\n<!-- prolog -->\n<root xmlns='ns1' a='b'><child>text &amp; more</child><![CDATA[raw]]></root>\n";
+    // A tiny buffer splits every construct across chunks.
+    let reader = BufReader::with_capacity(3, xml.as_bytes());
+    let elem = Element::from_async_reader(reader).await.unwrap();
+    assert_eq!(elem, Element::from_reader(xml.as_bytes()).unwrap());
+    assert_eq!(
+        elem.get_child("child", "ns1").unwrap().text(),
+        "text & more"
+    );
+
+    let reader = BufReader::with_capacity(3, xml.as_bytes());
+    let doc = Document::from_async_reader(reader).await.unwrap();
+    assert_eq!(doc, xml.parse().unwrap());
+
+    let reader = BufReader::with_capacity(3, &b"<root xmlns='ns1'><a></b></root>"[..]);
+    let options = ParseOptions::new().record_positions(true);
+    let err = Element::from_async_reader_with_options(reader, options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.position().unwrap().offset, 21);
+
+    let reader = BufReader::with_capacity(3, &b"<root xmlns='ns1'><a/>"[..]);
+    let err = Document::from_async_reader(reader).await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::XmlError(rxml::Error::Xml(rxml::error::XmlError::InvalidEof(_)))
+    ));
+
+    let options = ParseOptions::new().allow_missing_namespace(true);
+    let elem = Element::from_async_reader_with_options(&b"<svg/>"[..], options)
+        .await
+        .unwrap();
+    assert!(elem.is("svg", NSChoice::None));
+}
+
+/// An `AsyncWrite` taking at most three bytes at a time, and remembering the largest buffer it
+/// has been given.
+#[cfg(feature = "tokio")]
+#[derive(Default)]
+struct TrickleWriter(Vec<u8>, usize);
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for TrickleWriter {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        let len = buf.len().min(3);
+        self.0.extend_from_slice(&buf[..len]);
+        self.1 = self.1.max(buf.len());
+        std::task::Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_writer() {
+    let mut root = Element::from_reader(TEST_STRING).unwrap();
+    for i in 0..2000 {
+        root.append_child(
+            Element::builder("item", "root_ns")
+                .attr("n", i)
+                .append(Element::builder("deep", "other_ns").append("x & y"))
+                .build(),
+        );
+    }
+    let mut out = TrickleWriter::default();
+    root.write_to_async(&mut out).await.unwrap();
+    assert_eq!(out.0, String::from(&root).into_bytes());
+
+    let options = WriteOptions::new().indent(" ");
+    let mut writer = StreamWriter::with_options(Vec::new(), options.clone());
+    let mut out = TrickleWriter::default();
+    writer.open(&Element::bare("stream", "root_ns")).unwrap();
+    writer.write_element_async(&root, &mut out).await.unwrap();
+    writer.write_element_async(&root, &mut out).await.unwrap();
+    writer.close().unwrap();
+    writer.write_to_async(&mut out).await.unwrap();
+    assert!(writer.get_ref().is_empty());
+    let stream = Element::builder("stream", "root_ns")
+        .append(root.clone())
+        .append(root)
+        .build();
+    let mut expected = Vec::new();
+    stream
+        .write_to_with_options(&mut expected, options)
+        .unwrap();
+    assert_eq!(out.0, expected);
+
+    // Large text and CDATA sections are sent in pieces, keeping `]]>` in one of them.
+    let mut cdata = "é".repeat(5000);
+    cdata.insert_str(8190, "]]>");
+    let elem = Element::builder("a", "ns1")
+        .append(Node::CData(cdata))
+        .append("x".repeat(100_000))
+        .build();
+    let mut out = TrickleWriter::default();
+    elem.write_to_async(&mut out).await.unwrap();
+    assert_eq!(out.0, String::from(&elem).into_bytes());
+    assert!(out.1 < 20_000, "{} bytes were buffered", out.1);
+}
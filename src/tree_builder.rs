@@ -2,18 +2,390 @@
 
 //! SAX events to DOM tree conversion
 
+use crate::attributes::{Attribute, Attributes, XML_NS};
+use crate::driver::{Driver, Event};
+use crate::interner::Interner;
+use crate::position::{ElementSpans, Span};
 use crate::prefixes::{Prefix, Prefixes};
-use crate::{Element, Error};
+use crate::{Element, Error, Node};
+use rxml::error::XmlError;
 use rxml::RawEvent;
 use std::collections::BTreeMap;
+use std::fmt;
+
+/// A bound set by `ParseLimits`, reported by `Error::LimitExceeded`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// How deeply elements are nested
+    Depth,
+    /// How many attributes an element has
+    Attributes,
+    /// How long a single text node is
+    TextSize,
+    /// How long a single tag, comment, processing instruction or XML declaration is
+    TokenSize,
+    /// How many elements a document has
+    Elements,
+    /// How many namespaces an element declares
+    NamespaceDeclarations,
+    /// How deeply entity references are nested
+    EntityDepth,
+    /// How much replacement text entities expand to
+    EntityExpansion,
+    /// How long the DOCTYPE is
+    DoctypeSize,
+    /// How many entities the DOCTYPE declares
+    EntityDeclarations,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(match self {
+            Limit::Depth => "element depth",
+            Limit::Attributes => "attribute count",
+            Limit::TextSize => "text size",
+            Limit::TokenSize => "token size",
+            Limit::Elements => "element count",
+            Limit::NamespaceDeclarations => "namespace declaration count",
+            Limit::EntityDepth => "entity depth",
+            Limit::EntityExpansion => "entity expansion",
+            Limit::DoctypeSize => "DOCTYPE size",
+            Limit::EntityDeclarations => "entity declaration count",
+        })
+    }
+}
+
+/// Bounds on the size of a parsed document, to use when parsing untrusted input.
+///
+/// Nothing is bounded by default, except the size of markup, the DOCTYPE and the expansion of
+/// the entities it declares.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::{Element, Error, Limit, ParseLimits, ParseOptions};
+///
+/// let limits = ParseLimits::new().max_depth(2);
+/// let options = ParseOptions::new().limits(limits);
+///
+/// let xml = b"<a xmlns='ns1'><b><c/></b></a>";
+/// let err = Element::from_reader_with_options(&xml[..], options).unwrap_err();
+/// assert!(matches!(err, Error::LimitExceeded(Limit::Depth)));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseLimits {
+    pub(crate) max_depth: usize,
+    pub(crate) max_attributes: usize,
+    pub(crate) max_text_size: usize,
+    pub(crate) max_token_size: usize,
+    pub(crate) max_elements: usize,
+    pub(crate) max_namespace_declarations: usize,
+    pub(crate) max_entity_depth: usize,
+    pub(crate) max_entity_expansion: usize,
+    pub(crate) max_doctype_size: usize,
+    pub(crate) max_entity_declarations: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_depth: usize::MAX,
+            max_attributes: usize::MAX,
+            max_text_size: usize::MAX,
+            max_token_size: 64 * 1024,
+            max_elements: usize::MAX,
+            max_namespace_declarations: usize::MAX,
+            max_entity_depth: 8,
+            max_entity_expansion: 64 * 1024,
+            max_doctype_size: 64 * 1024,
+            max_entity_declarations: 1024,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// Create the default set of limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how deeply elements may be nested, the root element being at depth 1.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Set how many attributes an element may have, not counting namespace declarations.
+    pub fn max_attributes(mut self, count: usize) -> Self {
+        self.max_attributes = count;
+        self
+    }
+
+    /// Set how many bytes a single text node may hold.
+    pub fn max_text_size(mut self, bytes: usize) -> Self {
+        self.max_text_size = bytes;
+        self
+    }
+
+    /// Set how many bytes a single tag, comment, processing instruction or XML declaration may
+    /// take, delimiters and attributes included, 64 KiB by default. Unfinished character and
+    /// entity references in text count as well.
+    pub fn max_token_size(mut self, bytes: usize) -> Self {
+        self.max_token_size = bytes;
+        self
+    }
+
+    /// Set how many elements a document may have, the root element included.
+    pub fn max_elements(mut self, count: usize) -> Self {
+        self.max_elements = count;
+        self
+    }
+
+    /// Set how many namespaces a single element may declare.
+    pub fn max_namespace_declarations(mut self, count: usize) -> Self {
+        self.max_namespace_declarations = count;
+        self
+    }
+
+    /// Set how deeply entity references may be nested within the replacement text of other
+    /// entities, 8 by default.
+    pub fn max_entity_depth(mut self, depth: usize) -> Self {
+        self.max_entity_depth = depth;
+        self
+    }
+
+    /// Set how many bytes of replacement text entities may expand to over the whole document,
+    /// 64 KiB by default.
+    pub fn max_entity_expansion(mut self, bytes: usize) -> Self {
+        self.max_entity_expansion = bytes;
+        self
+    }
+
+    /// Set how many bytes the DOCTYPE may take, its internal subset included, 64 KiB by
+    /// default.
+    pub fn max_doctype_size(mut self, bytes: usize) -> Self {
+        self.max_doctype_size = bytes;
+        self
+    }
+
+    /// Set how many entities the internal subset of the DOCTYPE may declare, 1024 by default.
+    pub fn max_entity_declarations(mut self, count: usize) -> Self {
+        self.max_entity_declarations = count;
+        self
+    }
+}
+
+/// How whitespace in text nodes is handled, see `ParseOptions::whitespace`.
+///
+/// Whitespace is made of spaces, tabs, line feeds and carriage returns. Text nodes within an
+/// element with `xml:space="preserve"` are always kept as they are, up to a descendant with
+/// `xml:space="default"`. CDATA sections kept as `Node::CData` are never changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WhitespaceMode {
+    /// Keep text nodes as they are
+    #[default]
+    Preserve,
+    /// Drop text nodes made only of whitespace
+    DropWhitespaceOnly,
+    /// Remove the whitespace at both ends of text nodes, dropping the ones which end up empty
+    Trim,
+}
+
+/// Options changing how a document is turned into a tree.
+///
+/// The defaults accept exactly what the XMPP subset of XML allows.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::{Element, ParseOptions};
+///
+/// let options = ParseOptions::new().allow_missing_namespace(true);
+/// let elem = Element::from_reader_with_options(&b"<svg/>"[..], options).unwrap();
+///
+/// assert_eq!(elem.name(), "svg");
+/// assert_eq!(elem.ns(), "");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub(crate) allow_missing_namespace: bool,
+    pub(crate) preserve_cdata: bool,
+    pub(crate) allow_doctype: bool,
+    pub(crate) limits: ParseLimits,
+    pub(crate) whitespace: WhitespaceMode,
+    pub(crate) detect_encoding: bool,
+    pub(crate) preserve_attribute_order: bool,
+    pub(crate) record_positions: bool,
+}
+
+impl ParseOptions {
+    /// Create the default set of options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept unprefixed elements which are not in the scope of any default namespace
+    /// declaration, instead of failing with `Error::MissingNamespace`. Such elements get the
+    /// empty namespace, which `NSChoice::None` matches.
+    ///
+    /// Prefixed elements must still have their prefix declared.
+    pub fn allow_missing_namespace(mut self, allow: bool) -> Self {
+        self.allow_missing_namespace = allow;
+        self
+    }
+
+    /// Keep CDATA sections as `Node::CData` instead of merging them into the surrounding text, so
+    /// that they are written back as CDATA sections.
+    pub fn preserve_cdata(mut self, preserve: bool) -> Self {
+        self.preserve_cdata = preserve;
+        self
+    }
+
+    /// Keep the attributes of elements in the order they appear in the document, instead of
+    /// sorting them by namespace and name, so that they are written back in that order.
+    /// Attributes added later on are written after them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, ParseOptions};
+    ///
+    /// let xml = r#"<rect xmlns="svg" y="2" x="1" width="3"/>"#;
+    /// let options = ParseOptions::new().preserve_attribute_order(true);
+    /// let mut elem = Element::from_reader_with_options(xml.as_bytes(), options).unwrap();
+    /// elem.set_attr("height", "4");
+    ///
+    /// assert_eq!(String::from(&elem), r#"<rect xmlns='svg' y="2" x="1" width="3" height="4"/>"#);
+    /// ```
+    pub fn preserve_attribute_order(mut self, preserve: bool) -> Self {
+        self.preserve_attribute_order = preserve;
+        self
+    }
+
+    /// Accept a DOCTYPE before the root element, and expand the general entities declared in its
+    /// internal subset in text and attribute values.
+    ///
+    /// External entities are never loaded, and referencing them is an error, as is referencing
+    /// an entity whose replacement text contains markup.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, ParseOptions};
+    ///
+    /// let xml = br#"<!DOCTYPE svg [ <!ENTITY ns_svg "http://www.w3.org/2000/svg"> ]>
+    /// <svg xmlns="&ns_svg;">&ns_svg;</svg>"#;
+    /// let options = ParseOptions::new().allow_doctype(true);
+    /// let elem = Element::from_reader_with_options(&xml[..], options).unwrap();
+    ///
+    /// assert_eq!(elem.ns(), "http://www.w3.org/2000/svg");
+    /// assert_eq!(elem.text(), "http://www.w3.org/2000/svg");
+    /// ```
+    pub fn allow_doctype(mut self, allow: bool) -> Self {
+        self.allow_doctype = allow;
+        self
+    }
+
+    /// Set the limits the document must stay within, failing with `Error::LimitExceeded`
+    /// otherwise.
+    pub fn limits(mut self, limits: ParseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set how whitespace in text nodes is handled, `WhitespaceMode::Preserve` by default.
+    ///
+    /// Each element is normalized as with `Element::normalize_whitespace` once its end tag has
+    /// been parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, ParseOptions, WhitespaceMode};
+    ///
+    /// let xml = b"<a xmlns='ns1'>\n  <b> text </b>\n  <c xml:space='preserve'> </c>\n</a>";
+    /// let options = ParseOptions::new().whitespace(WhitespaceMode::Trim);
+    /// let elem = Element::from_reader_with_options(&xml[..], options).unwrap();
+    ///
+    /// assert_eq!(elem.nodes().count(), 2);
+    /// assert_eq!(elem.get_child("b", "ns1").unwrap().text(), "text");
+    /// assert_eq!(elem.get_child("c", "ns1").unwrap().text(), " ");
+    /// ```
+    pub fn whitespace(mut self, mode: WhitespaceMode) -> Self {
+        self.whitespace = mode;
+        self
+    }
+
+    /// Detect the encoding of the document from its byte order mark and XML declaration, and
+    /// decode it from UTF-16, ISO-8859-1 or US-ASCII instead of only accepting UTF-8.
+    ///
+    /// Offsets of positions are then counted in bytes of the document once decoded to UTF-8,
+    /// without its byte order mark.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, ParseOptions};
+    ///
+    /// let xml = b"<?xml version='1.0' encoding='ISO-8859-1'?><a xmlns='ns1'>caf\xe9</a>";
+    /// let options = ParseOptions::new().detect_encoding(true);
+    /// let elem = Element::from_reader_with_options(&xml[..], options).unwrap();
+    ///
+    /// assert_eq!(elem.text(), "café");
+    /// ```
+    pub fn detect_encoding(mut self, detect: bool) -> Self {
+        self.detect_encoding = detect;
+        self
+    }
+
+    /// Remember where the tags and attributes of elements are in the document, see
+    /// `Element::start_tag_span`, and report where parsing errors have been found by wrapping
+    /// them in `Error::Positioned`.
+    ///
+    /// This is off by default, errors included, so that they can be matched on directly and
+    /// parsed elements don't allocate their spans.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, Error, ParseOptions};
+    ///
+    /// let xml = b"<a xmlns='ns1'>\n<b></c></a>";
+    /// let options = ParseOptions::new().record_positions(true);
+    /// let err = Element::from_reader_with_options(&xml[..], options).unwrap_err();
+    ///
+    /// assert_eq!(err.position().unwrap().line, 2);
+    /// assert!(matches!(err.without_position(), Error::XmlError(_)));
+    /// ```
+    pub fn record_positions(mut self, record: bool) -> Self {
+        self.record_positions = record;
+        self
+    }
+}
+
+/// The prefix, local name, value and position of an attribute.
+type PendingAttribute = (Prefix, String, String, Option<Span>);
 
 /// Tree-building parser state
 pub struct TreeBuilder {
-    next_tag: Option<(Prefix, String, Prefixes, BTreeMap<String, String>)>,
+    /// The prefix, name, namespace declarations and attributes of the element being opened,
+    /// the prefixes of its attributes being resolved once all declarations have been seen
+    next_tag: Option<(Prefix, String, Prefixes, Vec<PendingAttribute>)>,
     /// Parsing stack
     stack: Vec<Element>,
     /// Namespace set stack by prefix
     prefixes_stack: Vec<Prefixes>,
+    /// Options this tree is built with
+    options: ParseOptions,
+    /// Number of elements opened so far
+    elements: usize,
+    /// Where the last tag has been found, and its attributes not processed yet
+    tag_span: Option<Span>,
+    attribute_spans: std::vec::IntoIter<Span>,
+    /// Where the element being opened has been found
+    next_spans: Option<ElementSpans>,
+    /// Names and namespaces shared between elements
+    interner: Interner,
     /// Document root element if finished
     pub root: Option<Element>,
 }
@@ -31,10 +403,28 @@ impl TreeBuilder {
             next_tag: None,
             stack: vec![],
             prefixes_stack: vec![],
+            options: ParseOptions::default(),
+            elements: 0,
+            tag_span: None,
+            attribute_spans: Vec::new().into_iter(),
+            next_spans: None,
+            interner: Interner::default(),
             root: None,
         }
     }
 
+    /// Set the options used to build the tree.
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Set the limits enforced while building the tree.
+    pub fn with_limits(mut self, limits: ParseLimits) -> Self {
+        self.options.limits = limits;
+        self
+    }
+
     /// Allow setting prefixes stack.
     ///
     /// Useful to provide knowledge of namespaces that would have been declared on parent elements
@@ -54,6 +444,23 @@ impl TreeBuilder {
         self.stack.last()
     }
 
+    /// Get the root element while it is being built
+    pub(crate) fn root_element(&self) -> Option<&Element> {
+        self.stack.first()
+    }
+
+    /// Take the first child out of the root element once it has been completed, for reading the
+    /// children of the root element one at a time. The text before it is dropped, and elements
+    /// are counted again from there for `ParseLimits::max_elements`.
+    pub(crate) fn take_completed_child(&mut self) -> Option<Element> {
+        if self.depth() != 1 {
+            return None;
+        }
+        let child = self.unshift_child()?;
+        self.elements = self.stack.len();
+        Some(child)
+    }
+
     /// Pop the top-most element from the stack
     fn pop(&mut self) -> Option<Element> {
         self.prefixes_stack.pop();
@@ -71,8 +478,11 @@ impl TreeBuilder {
     }
 
     /// Lookup XML namespace declaration for given prefix (or no prefix)
-    fn lookup_prefix(&self, prefix: &Option<String>) -> Option<&str> {
-        for nss in self.prefixes_stack.iter().rev() {
+    fn lookup_prefix<'a>(
+        prefixes_stack: &'a [Prefixes],
+        prefix: &Option<String>,
+    ) -> Option<&'a str> {
+        for nss in prefixes_stack.iter().rev() {
             if let Some(ns) = nss.get(prefix) {
                 return Some(ns);
             }
@@ -82,7 +492,13 @@ impl TreeBuilder {
     }
 
     fn process_end_tag(&mut self) -> Result<(), Error> {
-        if let Some(el) = self.pop() {
+        if let Some(mut el) = self.pop() {
+            if let Some(span) = self.tag_span.take() {
+                el.set_end_tag_span(span);
+            }
+            if self.options.whitespace != WhitespaceMode::Preserve && !self.preserves_space(&el) {
+                el.normalize_text_nodes(self.options.whitespace);
+            }
             if self.depth() > 0 {
                 let top = self.stack.len() - 1;
                 self.stack[top].append_child(el);
@@ -94,11 +510,91 @@ impl TreeBuilder {
         Ok(())
     }
 
-    fn process_text(&mut self, text: String) {
+    /// Whether `xml:space="preserve"` applies to the text of `el`, given its ancestors on the
+    /// stack.
+    fn preserves_space(&self, el: &Element) -> bool {
+        std::iter::once(el)
+            .chain(self.stack.iter().rev())
+            .find_map(|el| el.attr("xml:space"))
+            == Some("preserve")
+    }
+
+    fn process_text(&mut self, text: String) -> Result<(), Error> {
+        if text.len() > self.options.limits.max_text_size {
+            return Err(Error::LimitExceeded(Limit::TextSize));
+        }
         if self.depth() > 0 {
             let top = self.stack.len() - 1;
             self.stack[top].append_text_node(text);
         }
+        Ok(())
+    }
+
+    /// Process a comment. Comments outside of the root element are dropped.
+    pub fn process_comment(&mut self, comment: String) {
+        if let Some(top) = self.stack.last_mut() {
+            top.append_node(Node::Comment(comment));
+        }
+    }
+
+    /// Process a processing instruction. Processing instructions outside of the root element are
+    /// dropped.
+    pub fn process_processing_instruction(&mut self, target: String, data: String) {
+        if let Some(top) = self.stack.last_mut() {
+            top.append_node(Node::ProcessingInstruction { target, data });
+        }
+    }
+
+    /// Process the content of a CDATA section. CDATA sections are only valid inside of the root
+    /// element.
+    pub fn process_cdata(&mut self, cdata: String) -> Result<(), Error> {
+        if cdata.len() > self.options.limits.max_text_size {
+            return Err(Error::LimitExceeded(Limit::TextSize));
+        }
+        if let Some(top) = self.stack.last_mut() {
+            top.append_node(Node::CData(cdata));
+        }
+        Ok(())
+    }
+
+    /// Options this tree is built with
+    pub(crate) fn options(&self) -> &ParseOptions {
+        &self.options
+    }
+
+    /// Process an event produced by the `Driver`
+    pub(crate) fn process_driver_event(&mut self, event: Event) -> Result<(), Error> {
+        match event {
+            Event::Raw(event) => self.process_event(event)?,
+            Event::XmlDeclaration(_) | Event::Doctype(_) => {}
+            Event::TagSpan { span, attributes } => {
+                self.tag_span = Some(span);
+                self.attribute_spans = attributes.into_iter();
+            }
+            Event::Text(text) => self.process_text(text)?,
+            Event::CData(cdata) => self.process_cdata(cdata)?,
+            Event::Comment(comment) => self.process_comment(comment),
+            Event::ProcessingInstruction { target, data } => {
+                self.process_processing_instruction(target, data)
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Process the events available from `driver`, returning the root element once it has been
+    /// closed, or `None` if more input is required.
+    pub(crate) fn drive(&mut self, driver: &mut Driver) -> Result<Option<Element>, Error> {
+        while let Some(event) = driver.read()? {
+            self.process_driver_event(event)?;
+            if let Some(root) = self.root.take() {
+                return Ok(Some(root));
+            }
+        }
+        if driver.is_eof() {
+            return Err(Error::EndOfDocument);
+        }
+        Ok(None)
     }
 
     /// Process a Event that you got out of a RawParser
@@ -107,27 +603,53 @@ impl TreeBuilder {
             RawEvent::XmlDeclaration(_, _) => {}
 
             RawEvent::ElementHeadOpen(_, (prefix, name)) => {
+                let limits = &self.options.limits;
+                if self.stack.len() >= limits.max_depth {
+                    return Err(Error::LimitExceeded(Limit::Depth));
+                }
+                if self.elements >= limits.max_elements {
+                    return Err(Error::LimitExceeded(Limit::Elements));
+                }
+                self.elements += 1;
+                self.next_spans = self.tag_span.map(|start_tag| ElementSpans {
+                    start_tag,
+                    end_tag: None,
+                    attributes: BTreeMap::new(),
+                });
                 self.next_tag = Some((
                     prefix.map(|prefix| prefix.as_str().to_owned()),
                     name.as_str().to_owned(),
                     Prefixes::default(),
-                    BTreeMap::new(),
+                    Vec::new(),
                 ))
             }
 
             RawEvent::Attribute(_, (prefix, name), value) => {
                 if let Some((_, _, ref mut prefixes, ref mut attrs)) = self.next_tag.as_mut() {
+                    let limits = &self.options.limits;
+                    let is_declaration = match prefix {
+                        Some(ref prefix) => prefix.as_str() == "xmlns",
+                        None => name == "xmlns",
+                    };
+                    if is_declaration {
+                        if prefixes.declared_prefixes().len() >= limits.max_namespace_declarations {
+                            return Err(Error::LimitExceeded(Limit::NamespaceDeclarations));
+                        }
+                    } else if attrs.len() >= limits.max_attributes {
+                        return Err(Error::LimitExceeded(Limit::Attributes));
+                    }
+                    let span = self.attribute_spans.next();
                     match (prefix, name) {
                         (None, xmlns) if xmlns == "xmlns" => prefixes.insert(None, value),
                         (Some(xmlns), prefix) if xmlns.as_str() == "xmlns" => {
                             prefixes.insert(Some(prefix.as_str().to_owned()), value);
                         }
-                        (Some(prefix), name) => {
-                            attrs.insert(format!("{}:{}", prefix, name), value.as_str().to_owned());
-                        }
-                        (None, name) => {
-                            attrs.insert(name.as_str().to_owned(), value.as_str().to_owned());
-                        }
+                        (prefix, name) => attrs.push((
+                            prefix.map(|prefix| prefix.as_str().to_owned()),
+                            name.as_str().to_owned(),
+                            value.as_str().to_owned(),
+                            span,
+                        )),
                     }
                 }
             }
@@ -136,19 +658,54 @@ impl TreeBuilder {
                 if let Some((prefix, name, prefixes, attrs)) = self.next_tag.take() {
                     self.prefixes_stack.push(prefixes.clone());
 
-                    let namespace = self
-                        .lookup_prefix(&prefix.clone().map(|prefix| prefix.as_str().to_owned()))
-                        .ok_or(Error::MissingNamespace)?
-                        .to_owned();
-                    let el =
-                        Element::new(name.as_str().to_owned(), namespace, prefixes, attrs, vec![]);
+                    let namespace = match Self::lookup_prefix(
+                        &self.prefixes_stack,
+                        &prefix.clone().map(|prefix| prefix.as_str().to_owned()),
+                    ) {
+                        Some(namespace) => namespace,
+                        None if prefix.is_none() && self.options.allow_missing_namespace => "",
+                        None => return Err(Error::MissingNamespace),
+                    };
+                    let namespace = self.interner.intern(namespace);
+                    let name = self.interner.intern(&name);
+
+                    let mut spans = self.next_spans.take();
+                    let mut attributes = Vec::with_capacity(attrs.len());
+                    for (prefix, name, value, span) in attrs {
+                        let namespace = match prefix.as_deref() {
+                            None => "",
+                            Some("xml") => XML_NS,
+                            Some(_) => Self::lookup_prefix(&self.prefixes_stack, &prefix)
+                                .ok_or(Error::MissingNamespace)?,
+                        };
+                        let namespace = self.interner.intern(namespace);
+                        if let (Some(spans), Some(span)) = (spans.as_mut(), span) {
+                            spans
+                                .attributes
+                                .insert((namespace.clone(), name.clone()), span);
+                        }
+                        attributes.push(Attribute {
+                            namespace,
+                            name,
+                            value,
+                            prefix: prefix.map(|prefix| self.interner.intern(&prefix)),
+                        });
+                    }
+                    // Attributes may have the same name once their prefixes are resolved.
+                    let attributes =
+                        Attributes::from_list(attributes, self.options.preserve_attribute_order)
+                            .ok_or(rxml::Error::Xml(XmlError::DuplicateAttribute))?;
+
+                    let mut el = Element::new(name, namespace, prefixes, attributes, vec![]);
+                    el.prefix = prefix.map(|prefix| self.interner.intern(&prefix));
+                    el.spans = spans.map(Box::new);
                     self.stack.push(el);
                 }
             }
 
             RawEvent::ElementFoot(_) => self.process_end_tag()?,
 
-            RawEvent::Text(_, text) => self.process_text(text.as_str().to_owned()),
+            RawEvent::Text(_, text) => self.process_text(text.as_str().to_owned())?,
         }
 
         Ok(())
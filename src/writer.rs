@@ -0,0 +1,342 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provides `WriteOptions`, which changes how elements and documents are laid out when written,
+//! and `StreamWriter`, which writes a document one element at a time.
+
+use crate::document::write_misc;
+use crate::driver::syntax_error;
+use crate::element::{Element, ItemWriter};
+use crate::error::Result;
+use crate::node::Node;
+
+use rxml::writer::Item;
+use rxml::XmlVersion;
+
+use std::io::Write;
+
+#[cfg(feature = "tokio")]
+use crate::element::write_all_async;
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncWrite;
+
+/// Options changing how a tree is written out.
+///
+/// The defaults write everything on a single line, exactly as it is in the tree. Setting an
+/// indentation string turns on pretty printing: the children of elements are put on their own
+/// lines and indented, and start tags going over the line width get one attribute per line.
+///
+/// Pretty printing never changes text: elements with mixed content, that is with text which
+/// isn't only whitespace, are written as they are along with all of their descendants, and so
+/// are elements with `xml:space="preserve"`, up to a descendant with `xml:space="default"`.
+/// Whitespace-only text between the children which do get indented is replaced.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::{Element, WriteOptions};
+///
+/// let elem: Element = "<a xmlns='ns1'><b>text</b><c/></a>".parse().unwrap();
+///
+/// let mut out = Vec::new();
+/// elem.write_to_with_options(&mut out, WriteOptions::new().indent("  ")).unwrap();
+/// assert_eq!(out, b"<a xmlns='ns1'>\n  <b>text</b>\n  <c/>\n</a>");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteOptions {
+    pub(crate) indent: Option<String>,
+    pub(crate) line_width: usize,
+    pub(crate) attributes_per_line: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            indent: None,
+            line_width: usize::MAX,
+            attributes_per_line: false,
+        }
+    }
+}
+
+impl WriteOptions {
+    /// Create the default set of options, which don't add any whitespace.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pretty print, indenting each level of element-only content with `indent`.
+    pub fn indent<S: Into<String>>(mut self, indent: S) -> Self {
+        self.indent = Some(indent.into());
+        self
+    }
+
+    /// Put the attributes and namespace declarations of start tags on their own lines when the
+    /// tag would otherwise go past `width` characters, counting its indentation. Only applies
+    /// when pretty printing.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use minidom::{Element, WriteOptions};
+    ///
+    /// let elem: Element = r#"<a xmlns="ns1"><b first="1" second="2"/></a>"#.parse().unwrap();
+    ///
+    /// let mut out = Vec::new();
+    /// let options = WriteOptions::new().indent("  ").line_width(20);
+    /// elem.write_to_with_options(&mut out, options).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(out).unwrap(),
+    ///     "<a xmlns='ns1'>\n  <b\n    first=\"1\"\n    second=\"2\"/>\n</a>",
+    /// );
+    /// ```
+    pub fn line_width(mut self, width: usize) -> Self {
+        self.line_width = width;
+        self
+    }
+
+    /// Always put the attributes and namespace declarations of start tags having more than one
+    /// of them on their own lines. Only applies when pretty printing.
+    pub fn attributes_per_line(mut self, per_line: bool) -> Self {
+        self.attributes_per_line = per_line;
+        self
+    }
+}
+
+/// The layout state of an element opened by a `StreamWriter`, and the one of its parent.
+#[derive(Debug)]
+struct OpenElement {
+    /// Whether the parent may be indented
+    pretty: bool,
+    /// Whether the parent is within `xml:space="preserve"`
+    space_preserved: bool,
+    /// Whether a child of this element has been put on its own line
+    indented: bool,
+}
+
+/// Writes a document piece by piece, for documents which are never complete in memory, like
+/// XMPP streams or huge files.
+///
+/// Elements are opened with their start tag, then their children are written one at a time,
+/// and they are closed with a separate call. Everything written inside an open element shares its
+/// namespace declarations, so children don't declare them again.
+///
+/// When pretty printing, children are put on their own lines, until text is written into the
+/// element they are in.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::{Element, StreamWriter};
+///
+/// let mut writer = StreamWriter::new(Vec::new());
+/// let stream = Element::builder("stream", "http://etherx.jabber.org/streams")
+///     .prefix(Some(String::from("stream")), "http://etherx.jabber.org/streams")
+///     .unwrap()
+///     .prefix(None, "jabber:client")
+///     .unwrap()
+///     .attr("to", "example.org")
+///     .build();
+/// writer.write_declaration().unwrap();
+/// writer.open(&stream).unwrap();
+/// writer.write_element(&Element::builder("message", "jabber:client").build()).unwrap();
+/// writer.close().unwrap();
+///
+/// assert_eq!(
+///     String::from_utf8(writer.into_inner()).unwrap(),
+///     "<?xml version='1.0' encoding='utf-8'?>\n<stream:stream xmlns='jabber:client' \
+///      xmlns:stream='http://etherx.jabber.org/streams' to=\"example.org\"><message/></stream:stream>",
+/// );
+/// ```
+pub struct StreamWriter<W> {
+    writer: ItemWriter<W>,
+    open: Vec<OpenElement>,
+    /// Whether anything has been written yet
+    started: bool,
+    /// Whether the root element has been written or opened
+    has_root: bool,
+}
+
+impl<W: Write> StreamWriter<W> {
+    /// Create a writer writing everything as it is.
+    pub fn new(writer: W) -> StreamWriter<W> {
+        StreamWriter::with_options(writer, WriteOptions::default())
+    }
+
+    /// Create a writer laying everything out according to `options`.
+    pub fn with_options(writer: W, options: WriteOptions) -> StreamWriter<W> {
+        StreamWriter {
+            writer: ItemWriter::with_options(writer, options),
+            open: Vec::new(),
+            started: false,
+            has_root: false,
+        }
+    }
+
+    /// Write the `<?xml version='1.0' encoding='utf-8'?>` declaration, which has to come first.
+    pub fn write_declaration(&mut self) -> Result<()> {
+        if self.started {
+            return Err(syntax_error(
+                "the XML declaration must be at the start of the document",
+            ));
+        }
+        self.started = true;
+        self.writer.write(Item::XmlDeclaration(XmlVersion::V1_0))?;
+        Ok(())
+    }
+
+    /// Write the start tag of `head`, with its attributes and namespace declarations, followed
+    /// by the children it already has, and leave it open.
+    pub fn open(&mut self, head: &Element) -> Result<()> {
+        self.start_element()?;
+        head.write_head_inner(&mut self.writer)?;
+        self.writer.write(Item::ElementHeadEnd)?;
+        self.open.push(OpenElement {
+            pretty: self.writer.pretty,
+            space_preserved: self.writer.space_preserved,
+            indented: false,
+        });
+        match head.attr("xml:space") {
+            Some("preserve") => self.writer.space_preserved = true,
+            Some("default") => self.writer.space_preserved = false,
+            _ => (),
+        }
+        for child in head.nodes() {
+            self.write_node(child)?;
+        }
+        Ok(())
+    }
+
+    /// Write a complete element into the innermost open element, or as the root element.
+    pub fn write_element(&mut self, element: &Element) -> Result<()> {
+        self.start_element()?;
+        element.write_to_inner(&mut self.writer)
+    }
+
+    /// Write a node into the innermost open element. Outside of the root element, only
+    /// comments, processing instructions and whitespace text can be written, besides the root
+    /// element itself.
+    pub fn write_node(&mut self, node: &Node) -> Result<()> {
+        match *node {
+            Node::Element(ref element) => return self.write_element(element),
+            Node::Text(_) | Node::CData(_) => self.start_child(true)?,
+            Node::Comment(_) | Node::ProcessingInstruction { .. } => self.start_child(false)?,
+        }
+        if self.open.is_empty() {
+            write_misc(node, &mut self.writer)
+        } else {
+            node.write_to_inner(&mut self.writer)
+        }
+    }
+
+    /// Write the end tag of the innermost open element.
+    pub fn close(&mut self) -> Result<()> {
+        let open = self
+            .open
+            .pop()
+            .ok_or_else(|| syntax_error("there is no open element to close"))?;
+        if open.indented && self.writer.pretty {
+            self.writer.write_indent(self.writer.depth() - 1)?;
+        }
+        self.writer.write(Item::ElementFoot)?;
+        self.writer.pretty = open.pretty;
+        self.writer.space_preserved = open.space_preserved;
+        Ok(())
+    }
+
+    /// How many elements are open.
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    /// A reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// Returns the underlying writer, even if elements are still open.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    /// Get ready to write an element, which is the root element if none is open.
+    fn start_element(&mut self) -> Result<()> {
+        if self.open.is_empty() {
+            if self.has_root {
+                return Err(syntax_error("a document can only have one root element"));
+            }
+            self.has_root = true;
+        }
+        self.start_child(false)
+    }
+
+    /// Get ready to write a node, which is text if `is_text`, indenting it if needed.
+    fn start_child(&mut self, is_text: bool) -> Result<()> {
+        self.started = true;
+        let open = match self.open.last_mut() {
+            Some(open) => open,
+            None => return Ok(()),
+        };
+        if is_text {
+            // Like mixed content, the element isn't indented anymore.
+            self.writer.pretty = false;
+        } else if self.writer.pretty && !self.writer.space_preserved {
+            self.writer.write_indent(self.writer.depth())?;
+            open.indented = true;
+        }
+        Ok(())
+    }
+}
+
+/// A `StreamWriter` writing into a `Vec<u8>` can be used as a buffer in front of an
+/// `AsyncWrite`: what it writes is sent with `write_to_async`, and large elements are sent a
+/// chunk at a time with `write_element_async`.
+///
+/// This is tokio's `AsyncWrite`, with the `tokio` feature: there is no support for the one of
+/// `futures`, other than through the compatibility layer of `tokio-util`.
+///
+/// # Examples
+///
+/// ```rust
+/// use minidom::{Element, StreamWriter};
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let mut socket = Vec::new();
+/// let mut writer = StreamWriter::new(Vec::new());
+/// writer.open(&Element::bare("stream", "jabber:client")).unwrap();
+/// writer.write_to_async(&mut socket).await.unwrap();
+/// assert_eq!(socket, b"<stream xmlns='jabber:client'>");
+///
+/// let message = Element::builder("message", "jabber:client").append("Hi!").build();
+/// writer.write_element_async(&message, &mut socket).await.unwrap();
+/// writer.close().unwrap();
+/// writer.write_to_async(&mut socket).await.unwrap();
+/// assert_eq!(socket, b"<stream xmlns='jabber:client'><message>Hi!</message></stream>");
+/// # });
+/// ```
+#[cfg(feature = "tokio")]
+impl StreamWriter<Vec<u8>> {
+    /// Send everything written so far to `writer`, emptying the buffer.
+    pub async fn write_to_async<A: AsyncWrite + Unpin>(&mut self, writer: &mut A) -> Result<()> {
+        write_all_async(writer, self.writer.get_ref()).await?;
+        self.writer.get_mut().clear();
+        Ok(())
+    }
+
+    /// Write a complete element like `write_element`, sending it to `writer` along with
+    /// everything written before, a chunk at a time.
+    pub async fn write_element_async<A: AsyncWrite + Unpin>(
+        &mut self,
+        element: &Element,
+        writer: &mut A,
+    ) -> Result<()> {
+        self.start_element()?;
+        element.write_to_async_inner(&mut self.writer, writer).await
+    }
+}